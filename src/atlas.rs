@@ -0,0 +1,451 @@
+//! Texture atlases of images of any size
+//!
+//! Unlike the grids of [`native::pack`], images of an atlas can all have different
+//! sizes. They are placed with a skyline packer, tallest first, onto pages of an
+//! optional maximum size, starting a new page when an image fits on none of the
+//! pages so far. Like [`native`], this module has no `js_sys` types.
+use zune_core::colorspace::ColorSpace;
+use zune_core::options::DecoderOptions;
+use zune_jpeg::OutputLayout;
+
+use crate::errors::DecodeError;
+use crate::native::{self, DecodedImage, PendingImage};
+
+/// How images are placed into an atlas
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AtlasOptions {
+    /// Widest a page may be, without it pages are about as wide as they are tall
+    pub max_width: Option<u32>,
+    /// Tallest a page may be, without it all images go on a single page
+    pub max_height: Option<u32>,
+    /// Round the width and height of pages up to powers of two, for WebGL 1
+    /// textures that are mipmapped or repeat
+    pub power_of_two: bool,
+}
+
+/// Where an image is placed in an atlas
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AtlasRect {
+    /// Index of the page holding the image
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AtlasRect {
+    /// Texture coordinates `[u0, v0, u1, v1]` of the rectangle on a page of
+    /// `page_width`x`page_height`, from 0.0 to 1.0
+    pub fn uv(&self, page_width: u32, page_height: u32) -> [f32; 4] {
+        let (page_width, page_height) = (page_width as f32, page_height as f32);
+        [
+            self.x as f32 / page_width,
+            self.y as f32 / page_height,
+            (self.x + self.width) as f32 / page_width,
+            (self.y + self.height) as f32 / page_height,
+        ]
+    }
+}
+
+/// Size of the pages of an atlas and where each image goes on them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AtlasLayout {
+    /// Width and height of each page
+    pub pages: Vec<(u32, u32)>,
+    /// Where each image goes, in the order of the images
+    pub rects: Vec<AtlasRect>,
+}
+
+/// The pages of an atlas and where each image is on them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Atlas {
+    pub pages: Vec<DecodedImage>,
+    /// Where each image is, in the order of the images, counting rows as stored in
+    /// the data of the pages, so from the bottom of pages flipped vertically
+    pub rects: Vec<AtlasRect>,
+}
+
+/// Span of columns of the skyline of a page, whose lowest free row is `y`
+#[derive(Clone, Copy, Debug)]
+struct Segment {
+    x: u32,
+    y: u32,
+    width: u32,
+}
+
+/// Top edge of the images placed on a page so far, as segments from left to right
+/// covering the width of the page
+struct Skyline {
+    width: u32,
+    max_height: u32,
+    segments: Vec<Segment>,
+}
+
+impl Skyline {
+    fn new(width: u32, max_height: u32) -> Skyline {
+        Skyline {
+            width,
+            max_height,
+            segments: vec![Segment { x: 0, y: 0, width }],
+        }
+    }
+
+    /// Place a `width`x`height` image as low as it fits, leftmost among equally
+    /// low places, and return its top left corner
+    fn place(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        // index of the segment the image starts at and the row it goes on
+        let mut best: Option<(usize, u32)> = None;
+        for (index, segment) in self.segments.iter().enumerate() {
+            if u64::from(segment.x) + u64::from(width) > u64::from(self.width) {
+                break;
+            }
+            // the image rests on the highest segment under it
+            let mut y = 0;
+            let mut covered = 0;
+            for under in &self.segments[index..] {
+                if covered >= width {
+                    break;
+                }
+                y = y.max(under.y);
+                covered += under.width;
+            }
+            let fits = u64::from(y) + u64::from(height) <= u64::from(self.max_height);
+            if fits && best.is_none_or(|(_, best_y)| y < best_y) {
+                best = Some((index, y));
+            }
+        }
+        let (index, y) = best?;
+        let x = self.segments[index].x;
+        let end = x + width;
+
+        // drop the segments the image covers and cut the one it ends on
+        let covered = self.segments[index..]
+            .iter()
+            .take_while(|segment| segment.x + segment.width <= end)
+            .count();
+        self.segments.drain(index..index + covered);
+        if let Some(segment) = self.segments.get_mut(index) {
+            if segment.x < end {
+                segment.width -= end - segment.x;
+                segment.x = end;
+            }
+        }
+        self.segments.insert(
+            index,
+            Segment {
+                x,
+                y: y + height,
+                width,
+            },
+        );
+        self.segments.dedup_by(|next, segment| {
+            let merge = next.y == segment.y;
+            if merge {
+                segment.width += next.width;
+            }
+            merge
+        });
+        Some((x, y))
+    }
+}
+
+/// The largest power of two not above `size`
+const fn floor_power_of_two(size: u32) -> u32 {
+    if size == 0 {
+        0
+    } else {
+        1 << (u32::BITS - 1 - size.leading_zeros())
+    }
+}
+
+/// Place images of `sizes` into the pages of an atlas
+///
+/// # Errors
+/// `sizes` is empty, or an image is larger than `options` allow pages to be.
+pub fn atlas_layout(
+    sizes: &[(u32, u32)],
+    options: &AtlasOptions,
+) -> Result<AtlasLayout, DecodeError> {
+    if sizes.is_empty() {
+        return Err(DecodeError::invalid_argument(
+            "An atlas needs at least one image".to_string(),
+        ));
+    }
+    let mut max_width = options.max_width.unwrap_or_else(|| {
+        // about square, but at least as wide as the widest image
+        let area: u64 = sizes
+            .iter()
+            .map(|&(width, height)| u64::from(width) * u64::from(height))
+            .sum();
+        let widest = sizes.iter().map(|&(width, _)| width).max().unwrap_or(0);
+        widest.max((area as f64).sqrt().ceil() as u32)
+    });
+    let mut max_height = options.max_height.unwrap_or(u32::MAX);
+    if options.power_of_two {
+        if options.max_width.is_some() {
+            max_width = floor_power_of_two(max_width);
+        } else {
+            max_width = max_width.checked_next_power_of_two().unwrap_or(max_width);
+        }
+        max_height = floor_power_of_two(max_height);
+    }
+
+    // tallest images first, widest first among those of the same height
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&index| {
+        let (width, height) = sizes[index];
+        std::cmp::Reverse((height, width))
+    });
+    let mut skylines: Vec<Skyline> = Vec::new();
+    let mut rects = vec![
+        AtlasRect {
+            page: 0,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+        };
+        sizes.len()
+    ];
+    for index in order {
+        let (width, height) = sizes[index];
+        if width > max_width {
+            return Err(DecodeError::invalid_argument(format!(
+                "Image {index} is {width} pixels wide, atlas pages are at most {max_width}"
+            )));
+        }
+        if height > max_height {
+            return Err(DecodeError::invalid_argument(format!(
+                "Image {index} is {height} pixels tall, atlas pages are at most {max_height}"
+            )));
+        }
+        if width == 0 || height == 0 {
+            continue;
+        }
+        let placed = skylines
+            .iter_mut()
+            .enumerate()
+            .find_map(|(page, skyline)| Some((page, skyline.place(width, height)?)));
+        let (page, (x, y)) = match placed {
+            Some(placed) => placed,
+            None => {
+                let mut skyline = Skyline::new(max_width, max_height);
+                // an image no larger than the page fits on an empty page
+                let origin = skyline.place(width, height).unwrap();
+                skylines.push(skyline);
+                (skylines.len() - 1, origin)
+            }
+        };
+        rects[index] = AtlasRect {
+            page,
+            x,
+            y,
+            width,
+            height,
+        };
+    }
+
+    // pages shrink to the images on them
+    let mut pages = vec![(1, 1); skylines.len().max(1)];
+    for rect in rects.iter().filter(|rect| rect.width > 0) {
+        let page = &mut pages[rect.page];
+        page.0 = page.0.max(rect.x + rect.width);
+        page.1 = page.1.max(rect.y + rect.height);
+    }
+    if options.power_of_two {
+        for page in &mut pages {
+            page.0 = page.0.next_power_of_two();
+            page.1 = page.1.next_power_of_two();
+        }
+    }
+    Ok(AtlasLayout { pages, rects })
+}
+
+/// Blank pages of `layout` in `colorspace`
+fn blank_pages(layout: &AtlasLayout, colorspace: ColorSpace) -> Vec<DecodedImage> {
+    layout
+        .pages
+        .iter()
+        .map(|&(width, height)| DecodedImage {
+            data: vec![0; width as usize * height as usize * colorspace.num_components()],
+            width,
+            height,
+            colorspace,
+        })
+        .collect()
+}
+
+/// Byte layout of the rows of `rect` in the data of `page`
+fn rect_layout(rect: &AtlasRect, page: &DecodedImage) -> OutputLayout {
+    let channels = page.channels();
+    let stride = page.width as usize * channels;
+    OutputLayout {
+        offset: rect.y as usize * stride + rect.x as usize * channels,
+        stride,
+    }
+}
+
+/// Flip the pages vertically along with the rectangles on them
+fn flip_pages(atlas: &mut Atlas) {
+    for page in &mut atlas.pages {
+        native::flip_vertically(page);
+    }
+    for rect in &mut atlas.rects {
+        rect.y = atlas.pages[rect.page].height - rect.y - rect.height;
+    }
+}
+
+/// Place `images` into the pages of an atlas, see [`atlas_layout`]
+///
+/// All images must share the same colorspace and their pixels must fill their
+/// dimensions.
+pub fn pack_atlas(images: &[DecodedImage], options: &AtlasOptions) -> Result<Atlas, DecodeError> {
+    let sizes: Vec<_> = images.iter().map(|img| (img.width, img.height)).collect();
+    let layout = atlas_layout(&sizes, options)?;
+    native::check_same_colorspace(images.iter().map(|img| img.colorspace))?;
+    images.iter().try_for_each(DecodedImage::check_len)?;
+    let mut pages = blank_pages(&layout, images[0].colorspace);
+    for (image, rect) in images.iter().zip(&layout.rects) {
+        let page = &mut pages[rect.page];
+        let rows = rect_layout(rect, page);
+        native::copy_rows(image, &mut page.data[rows.offset..], rows.stride);
+    }
+    Ok(Atlas {
+        pages,
+        rects: layout.rects,
+    })
+}
+
+/// Decode images and place them into the pages of an atlas, see [`atlas_layout`]
+///
+/// Like [`native::decode_pack`], the headers of all images are read and the
+/// atlas laid out before anything is decoded, then each image is decoded
+/// straight into its place. Pages are flipped as a whole if `decode_options`
+/// flip them.
+pub fn decode_atlas<T: AsRef<[u8]>>(
+    encoded: &[T],
+    use_fast_jpeg: bool,
+    decode_options: &DecoderOptions,
+    options: &AtlasOptions,
+) -> Result<Atlas, DecodeError> {
+    // images are placed top to bottom, so pages are flipped as a whole
+    let image_options = decode_options.set_flip_vertically(false);
+    let images = encoded
+        .iter()
+        .map(|encoded| PendingImage::read(encoded.as_ref(), use_fast_jpeg, &image_options))
+        .collect::<Result<Vec<_>, _>>()?;
+    let sizes: Vec<_> = images
+        .iter()
+        .map(|image| {
+            let (width, height) = image.size();
+            (width as u32, height as u32)
+        })
+        .collect();
+    let layout = atlas_layout(&sizes, options)?;
+    native::check_same_colorspace(images.iter().map(PendingImage::colorspace))?;
+    let mut atlas = Atlas {
+        pages: blank_pages(&layout, images[0].colorspace()),
+        rects: layout.rects,
+    };
+    for (image, rect) in images.into_iter().zip(&atlas.rects) {
+        let page = &mut atlas.pages[rect.page];
+        let rows = rect_layout(rect, page);
+        image.decode_into(&mut page.data, rows)?;
+    }
+    if decode_options.get_flip_vertically() {
+        flip_pages(&mut atlas);
+    }
+    Ok(atlas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::{decode, default_options};
+
+    const TEST_JPEG: &[u8] = include_bytes!("../test_img.jpeg");
+    const TEST_PROGRESSIVE_JPEG: &[u8] = include_bytes!("../test_img_progressive.jpeg");
+
+    /// Whether any two non-empty rectangles on the same page overlap
+    fn overlap(rects: &[AtlasRect]) -> bool {
+        rects.iter().enumerate().any(|(i, a)| {
+            rects[i + 1..].iter().any(|b| {
+                a.page == b.page
+                    && a.x < b.x + b.width
+                    && b.x < a.x + a.width
+                    && a.y < b.y + b.height
+                    && b.y < a.y + a.height
+            })
+        })
+    }
+
+    #[test]
+    fn layout_mixed_sizes_onto_pages() {
+        let sizes = [
+            (30, 10),
+            (10, 30),
+            (20, 20),
+            (5, 5),
+            (30, 8),
+            (12, 17),
+            (0, 0),
+        ];
+        let layout = atlas_layout(&sizes, &AtlasOptions::default()).unwrap();
+        assert_eq!(layout.pages.len(), 1);
+        assert!(!overlap(&layout.rects));
+        let (width, height) = layout.pages[0];
+        for (rect, &(w, h)) in layout.rects.iter().zip(&sizes) {
+            assert_eq!((rect.width, rect.height), (w, h));
+            assert!(rect.x + w <= width && rect.y + h <= height);
+        }
+
+        let options = AtlasOptions {
+            max_width: Some(40),
+            max_height: Some(40),
+            power_of_two: true,
+        };
+        let layout = atlas_layout(&sizes, &options).unwrap();
+        assert!(layout.pages.len() > 1);
+        assert!(!overlap(&layout.rects));
+        for &(width, height) in &layout.pages {
+            assert!(width <= 32 && height <= 32);
+            assert!(width.is_power_of_two() && height.is_power_of_two());
+        }
+        assert!(atlas_layout(&[(33, 1)], &options).is_err());
+        assert!(atlas_layout(&[], &options).is_err());
+        assert_eq!(
+            layout.rects[3].uv(32, 32)[2] * 32.0,
+            (layout.rects[3].x + 5) as f32
+        );
+    }
+
+    #[test]
+    fn decode_atlas_matches_pack_atlas() {
+        let encoded = [TEST_JPEG, TEST_PROGRESSIVE_JPEG];
+        let options = AtlasOptions {
+            max_width: Some(512),
+            ..AtlasOptions::default()
+        };
+        let decode_options = default_options().jpeg_set_scale_denominator(4);
+        let decoded: Vec<_> = encoded
+            .iter()
+            .map(|encoded| decode(encoded, true, &decode_options).unwrap())
+            .collect();
+        let expected = pack_atlas(&decoded, &options).unwrap();
+        let atlas = decode_atlas(&encoded, true, &decode_options, &options).unwrap();
+        assert_eq!(atlas, expected);
+
+        let flipped = decode_options.set_flip_vertically(true);
+        let atlas = decode_atlas(&encoded, true, &flipped, &options).unwrap();
+        for (rect, image) in atlas.rects.iter().zip(&decoded) {
+            // the last row of the image is the first of its rect
+            let page = &atlas.pages[rect.page];
+            let channels = page.channels();
+            let start = (rect.y * page.width + rect.x) as usize * channels;
+            let row_len = image.width as usize * channels;
+            let last_row = &image.data[image.data.len() - row_len..];
+            assert_eq!(&page.data[start..start + row_len], last_row);
+        }
+    }
+}
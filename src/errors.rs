@@ -0,0 +1,164 @@
+//! Errors returned to JS
+//!
+//! Every failure is thrown as an `ImageDecodeError`, a subclass of the JS `Error`
+//! with three fields:
+//!
+//! - `code`: stable identifier of the failure. zune-jpeg errors use the name of the
+//!   `DecodeErrors` variant, e.g. `ExhaustedData`, `SofError`, `LargeDimensions`,
+//!   and unsupported jpegs are reported as `Unsupported.<scheme>`, e.g.
+//!   `Unsupported.ProgressiveDctArithmetic`. Errors from the `image` crate are
+//!   mapped onto the same codes where one fits. Images over the width, height or
+//!   pixel limits of the options are `LargeDimensions` whichever decoder rejects
+//!   them.
+//! - `format`: format of the image that failed to decode, e.g. `jpeg`, `png`, or
+//!   `unknown` if it was never detected
+//! - `message`: the message of the underlying decoder error
+use std::io::ErrorKind;
+
+use image::error::{LimitErrorKind, UnsupportedErrorKind};
+use image::{ImageError, ImageFormat};
+use wasm_bindgen::prelude::*;
+use zune_jpeg::errors::{DecodeErrors, UnsupportedSchemes};
+
+#[wasm_bindgen(inline_js = "
+export class ImageDecodeError extends Error {
+    constructor(message, code, format) {
+        super(message);
+        this.name = 'ImageDecodeError';
+        this.code = code;
+        this.format = format;
+    }
+}
+")]
+extern "C" {
+    type ImageDecodeError;
+
+    #[wasm_bindgen(constructor)]
+    fn new(message: &str, code: &str, format: &str) -> ImageDecodeError;
+}
+
+/// Format reported for errors that happen before the image format is known
+pub(crate) const UNKNOWN_FORMAT: &str = "unknown";
+
+/// A decode failure, converted to an `ImageDecodeError` when returned to JS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    pub code: String,
+    pub format: String,
+    pub message: String,
+}
+
+impl DecodeError {
+    pub(crate) fn new(code: &str, format: &str, message: String) -> DecodeError {
+        DecodeError {
+            code: code.to_string(),
+            format: format.to_string(),
+            message,
+        }
+    }
+
+    /// Error raised by the wrapper itself on invalid arguments
+    pub(crate) fn invalid_argument(message: String) -> DecodeError {
+        DecodeError::new("InvalidArgument", UNKNOWN_FORMAT, message)
+    }
+
+    pub(crate) fn from_jpeg(err: DecodeErrors) -> DecodeError {
+        let code = match &err {
+            DecodeErrors::Format(_) => "Format",
+            DecodeErrors::FormatStatic(_) => "FormatStatic",
+            DecodeErrors::IllegalMagicBytes(_) => "IllegalMagicBytes",
+            DecodeErrors::HuffmanDecode(_) => "HuffmanDecode",
+            DecodeErrors::ZeroError => "ZeroError",
+            DecodeErrors::DqtError(_) => "DqtError",
+            DecodeErrors::SosError(_) => "SosError",
+            DecodeErrors::SofError(_) => "SofError",
+            DecodeErrors::Unsupported(scheme) => match scheme {
+                UnsupportedSchemes::ExtendedSequentialHuffman => {
+                    "Unsupported.ExtendedSequentialHuffman"
+                }
+                UnsupportedSchemes::LosslessHuffman => "Unsupported.LosslessHuffman",
+                UnsupportedSchemes::ExtendedSequentialDctArithmetic => {
+                    "Unsupported.ExtendedSequentialDctArithmetic"
+                }
+                UnsupportedSchemes::ProgressiveDctArithmetic => {
+                    "Unsupported.ProgressiveDctArithmetic"
+                }
+                UnsupportedSchemes::LosslessArithmetic => "Unsupported.LosslessArithmetic",
+                UnsupportedSchemes::DefineNumberOfLines => "Unsupported.DefineNumberOfLines",
+            },
+            DecodeErrors::MCUError(_) => "MCUError",
+            DecodeErrors::ExhaustedData => "ExhaustedData",
+            DecodeErrors::LargeDimensions(_) => "LargeDimensions",
+            DecodeErrors::TooSmallOutput(_, _) => "TooSmallOutput",
+            DecodeErrors::MemoryLimit(_, _) => "MemoryLimit",
+            // same code as the `image` crate limits below
+            DecodeErrors::DimensionLimit(_, _, _) => "LargeDimensions",
+        };
+        DecodeError::new(code, "jpeg", err.to_string())
+    }
+
+    /// Map an `image` crate error onto the zune-jpeg codes, `format` is the
+    /// detected image format if there is one
+    pub(crate) fn from_image(err: ImageError, format: Option<ImageFormat>) -> DecodeError {
+        let code = match &err {
+            ImageError::Decoding(_) | ImageError::Encoding(_) => "Format",
+            ImageError::Parameter(_) => "InvalidArgument",
+            ImageError::Limits(limit) => match limit.kind() {
+                LimitErrorKind::DimensionError => "LargeDimensions",
+                LimitErrorKind::InsufficientMemory => "MemoryLimit",
+                _ => "Limits",
+            },
+            ImageError::Unsupported(unsupported) => match unsupported.kind() {
+                UnsupportedErrorKind::Format(_) => "Unsupported.Format",
+                UnsupportedErrorKind::Color(_)
+                | UnsupportedErrorKind::ColorLayout(_)
+                | UnsupportedErrorKind::ColorspaceCicp(_) => "Unsupported.Color",
+                _ => "Unsupported.Feature",
+            },
+            ImageError::IoError(io) if io.kind() == ErrorKind::UnexpectedEof => "ExhaustedData",
+            ImageError::IoError(_) => "Io",
+        };
+        let format = format
+            .map(|format| format!("{format:?}").to_lowercase())
+            .unwrap_or_else(|| UNKNOWN_FORMAT.to_string());
+        DecodeError::new(code, &format, err.to_string())
+    }
+
+    /// The caller-provided output buffer can't hold the decoded image
+    pub(crate) fn too_small_output(expected: usize, found: usize) -> DecodeError {
+        DecodeError::new(
+            "TooSmallOutput",
+            UNKNOWN_FORMAT,
+            format!("Too small output, expected buffer with at least {expected} bytes but got one with {found} bytes"),
+        )
+    }
+
+    /// Whether the decoder does not implement a feature the image uses, as
+    /// opposed to the image being corrupt
+    pub(crate) fn is_unsupported(&self) -> bool {
+        self.code.starts_with("Unsupported")
+    }
+
+    /// Error raised by the browser when building the output `ImageData`
+    pub(crate) fn from_js(err: JsValue) -> DecodeError {
+        DecodeError::new(
+            "ImageData",
+            UNKNOWN_FORMAT,
+            format!("Error creating JS imagedata: {err:?}"),
+        )
+    }
+}
+
+impl core::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} ({}): {}", self.code, self.format, self.message)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<DecodeError> for JsValue {
+    fn from(err: DecodeError) -> JsValue {
+        ImageDecodeError::new(&err.message, &err.code, &err.format).into()
+    }
+}
@@ -1,155 +1,882 @@
-use js_sys;
-use std::io::Cursor;
+use wasm_bindgen::prelude::*;
+use web_sys::ImageData;
+
 use zune_core::colorspace::ColorSpace;
 use zune_core::options::DecoderOptions;
-use zune_jpeg::JpegDecoder;
+use zune_jpeg::OutputLayout;
 
-use image::ImageReader;
+pub mod atlas;
+mod errors;
+pub mod native;
+mod options;
+pub mod resize;
+pub mod tensor;
 
-use wasm_bindgen::prelude::*;
-use web_sys::ImageData;
+use atlas::Atlas;
+pub use errors::DecodeError;
+use native::{
+    DecodedImage, ImageMetadata, ImageProbe, JpegFallback, Region, StreamStatus, YuvLayout,
+};
+use options::{
+    parse_atlas_options, parse_image_data_options, parse_options, parse_pack_options,
+    parse_resize_options, parse_tensor_options, JsAtlasOptions, JsDecodeOptions, JsPackOptions,
+    JsResizeOptions, JsTensorOptions,
+};
+use tensor::{TensorBatch, TensorShape};
 
-struct RsImg {
-    data: Vec<u8>,
+fn to_js_img(image: DecodedImage) -> Result<ImageData, DecodeError> {
+    slice_to_js_img(&image.data, image.width, image.height)
+}
+fn slice_to_js_img(data: &[u8], width: u32, height: u32) -> Result<ImageData, DecodeError> {
+    let js_arr: js_sys::Uint8ClampedArray =
+        js_sys::Uint8ClampedArray::new_with_length(data.len() as u32);
+    js_arr.copy_from(data);
+    let img_data = ImageData::new_with_js_u8_clamped_array_and_sh(&js_arr, width, height)
+        .map_err(DecodeError::from_js)?;
+    Ok(img_data)
+}
+/// Decoder that keeps its input and output bytes in wasm memory
+///
+/// JS writes encoded bytes straight into the view returned by
+/// [`input_buffer`](ZeroCopyDecoder::input_buffer), and the decoded pixels are
+/// returned as an `ImageData` viewing the output buffer, so no copies are made
+/// across the JS/wasm boundary.
+///
+/// Views into wasm memory are invalidated when the memory grows, so views returned
+/// by this struct must not be held across calls into the module.
+#[wasm_bindgen]
+pub struct ZeroCopyDecoder {
+    input: Vec<u8>,
+    output: Vec<u8>,
     width: u32,
     height: u32,
+    options: DecoderOptions,
 }
 
-fn decode_jpeg_rs(encoded: &Vec<u8>) -> Result<RsImg, String> {
-    let options: DecoderOptions =
-        DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGBA);
-    let mut decoder = JpegDecoder::new_with_options(encoded, options);
-    let bytes = decoder
-        .decode()
-        .map_err(|err| format!("JPEG Decode error: {err:?}"))?;
-    let dims: (usize, usize) = decoder
-        .dimensions()
-        .ok_or(format!("JPEG dimension Decode error"))?;
-    Ok(RsImg {
-        data: bytes,
-        width: dims.0 as u32,
-        height: dims.1 as u32,
-    })
+#[wasm_bindgen]
+impl ZeroCopyDecoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new(options: Option<JsDecodeOptions>) -> Result<ZeroCopyDecoder, DecodeError> {
+        Ok(ZeroCopyDecoder::with_options(parse_image_data_options(
+            options,
+        )?))
+    }
+    /// Size the input buffer to `len` bytes and return a view over it for JS to fill
+    pub fn input_buffer(&mut self, len: usize) -> js_sys::Uint8Array {
+        self.input.resize(len, 0);
+        // Safety: the view is only valid until wasm memory is next resized,
+        // which callers are told about in the struct docs.
+        unsafe { js_sys::Uint8Array::view_mut_raw(self.input.as_mut_ptr(), self.input.len()) }
+    }
+    /// Decode the jpeg in the input buffer with zune-jpeg
+    pub fn decode_jpeg(&mut self) -> Result<ImageData, DecodeError> {
+        let (width, height) =
+            native::decode_jpeg_into(&self.input, self.options, &mut self.output)?;
+        self.width = width;
+        self.height = height;
+        self.output_image()
+    }
+    /// Decode the image in the input buffer, see [`decode_img`]
+    pub fn decode_img(&mut self, use_fast_jpeg: bool) -> Result<ImageData, DecodeError> {
+        let mut fast_path_error = None;
+        if use_fast_jpeg && native::is_jpeg(&self.input) {
+            match native::decode_jpeg_into(&self.input, self.options, &mut self.output) {
+                Ok((width, height)) => {
+                    self.width = width;
+                    self.height = height;
+                    return self.output_image();
+                }
+                Err(err) if JpegFallback::default().retries(&err) => fast_path_error = Some(err),
+                Err(err) => return Err(err),
+            }
+        }
+        let image = native::decode_generic(&self.input, &self.options)
+            .map_err(|err| fast_path_error.unwrap_or(err))?;
+        // move the decoded pixels in instead of copying them
+        self.output = image.data;
+        self.width = image.width;
+        self.height = image.height;
+        self.output_image()
+    }
+    /// View over the pixels of the last decoded image
+    pub fn output_buffer(&self) -> js_sys::Uint8ClampedArray {
+        // Safety: see input_buffer
+        unsafe { js_sys::Uint8ClampedArray::view(&self.output) }
+    }
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    fn output_image(&self) -> Result<ImageData, DecodeError> {
+        ImageData::new_with_js_u8_clamped_array_and_sh(
+            &self.output_buffer(),
+            self.width,
+            self.height,
+        )
+        .map_err(DecodeError::from_js)
+    }
 }
 
-fn decode_img_generic_rs(encoded: &Vec<u8>) -> Result<RsImg, String> {
-    let decoded = ImageReader::new(Cursor::new(encoded))
-        .with_guessed_format()
-        .map_err(|err| format!("Error autodetecting image type: {err:?}"))?
-        .decode()
-        .map_err(|err| format!("Error decoding image: {err:?}"))?;
-
-    let img2: Vec<u8> = decoded.to_rgba8().into_raw();
-    Ok(RsImg {
-        data: img2,
-        width: decoded.width(),
-        height: decoded.height(),
-    })
+impl ZeroCopyDecoder {
+    fn with_options(options: DecoderOptions) -> ZeroCopyDecoder {
+        ZeroCopyDecoder {
+            input: Vec::new(),
+            output: Vec::new(),
+            width: 0,
+            height: 0,
+            options,
+        }
+    }
 }
-fn decode_img_rs(encoded: &Vec<u8>, use_fast_jpeg_path: bool) -> Result<RsImg, String> {
-    if use_fast_jpeg_path && encoded.len() > 2 && encoded[0] == 0xff && encoded[1] == 0xD8 {
-        decode_jpeg_rs(encoded)
-    } else {
-        decode_img_generic_rs(encoded)
+
+impl Default for ZeroCopyDecoder {
+    fn default() -> Self {
+        Self::with_options(native::default_options())
     }
 }
-fn pack_imgs_rs(decodedImgs: &[RsImg], num_imgs_col: usize) -> Result<RsImg, String> {
-    let num_imgs = decodedImgs.len();
-    if num_imgs == 0 {
-        return Err(format!("decode_packed_imgs_rs needs at least one image"));
+
+/// Stateful jpeg decoder for decoding a stream of frames
+///
+/// Keeps the zune-jpeg decoder, its per-component scratch buffers, the input
+/// bytes and the output pixels alive between calls, so decoding same-sized
+/// frames, e.g. from a camera feed, does not allocate after the first frame.
+#[wasm_bindgen]
+pub struct Decoder {
+    decoder: zune_jpeg::JpegDecoder<Vec<u8>>,
+    /// input buffer of the frame before the current one, reused for the next frame
+    spare_input: Vec<u8>,
+    output: Vec<u8>,
+    /// samples of the last tensor, reused for the next one
+    tensor: Vec<f32>,
+    width: u32,
+    height: u32,
+    /// colorspace frames are decoded to, zune-jpeg overrides the one of its
+    /// options with Luma for grayscale frames
+    colorspace: ColorSpace,
+}
+
+#[wasm_bindgen]
+impl Decoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new(options: Option<JsDecodeOptions>) -> Result<Decoder, DecodeError> {
+        Ok(Decoder::with_options(parse_image_data_options(options)?))
     }
-    if num_imgs_col <= 0 || num_imgs % num_imgs_col != 0 {
-        return Err(format!("Invalid num_imgs_col argument value: {num_imgs_col:?}, not divisible by num_imgs value {num_imgs:?}"));
+    /// Decode a jpeg frame
+    pub fn decode(&mut self, encoded_js: &js_sys::Uint8Array) -> Result<ImageData, DecodeError> {
+        self.decode_frame(encoded_js)?;
+        slice_to_js_img(&self.output, self.width, self.height)
     }
-    let num_rows = num_imgs / num_imgs_col;
-    let combinedImageSize: usize = decodedImgs
-        .iter()
-        .map(|img| (img.width * img.height * 4) as usize)
-        .sum();
-    let mut combinedImg: Vec<u8> = vec![0 as u8; combinedImageSize];
-    let firstImg = decodedImgs.first().unwrap();
-    let lastImg = decodedImgs.last().unwrap();
-    let mainWidth = firstImg.width as usize;
-    let mainHeight = firstImg.height as usize;
-    let lastWidth = lastImg.width as usize;
-    let lastHeight = lastImg.height as usize;
-    let xTotSize = mainWidth * (num_imgs_col - 1) + lastWidth;
-    let yTotSize = mainHeight * (num_rows - 1) + lastHeight;
-    for yidx in 0..num_rows {
-        for xidx in 0..num_imgs_col {
-            let img = &decodedImgs[yidx * num_imgs_col + xidx];
-            let img_width = img.width as usize;
-            let img_height = img.height as usize;
-            if ((xidx == num_imgs_col - 1 && img_width != lastWidth)
-                || (yidx == num_rows - 1 && img_height != lastHeight)
-                || (xidx != num_imgs_col - 1 && img_width != mainWidth)
-                || (yidx != num_rows - 1 && img_height != mainHeight))
-            {
-                return Err(format!("Invalid grid. Images of inconsistent size. num_rows:{num_rows:?}, num_imgs_col:{num_imgs_col:?} xidx: {xidx:?}, yidx: {yidx:?}, img_width: {img_width:?}, img_height: {img_height:?}, mainWidth: {mainWidth:?}, mainHeight: {mainHeight:?}, lastWidth: {lastWidth:?}, lastHeight: {lastHeight:?}"));
-            }
-            // number of channels
-            const nC: usize = 4;
-            let offset: usize = yidx * mainHeight * xTotSize * nC + xidx * mainWidth * nC;
-            for y in 0..img_height {
-                let y_src_offset = offset + y * xTotSize * nC;
-                let y_dest_offset = y * img_width * nC;
-                combinedImg[y_src_offset..y_src_offset + img_width * nC]
-                    .copy_from_slice(&img.data[y_dest_offset..y_dest_offset + img_width * nC]);
-            }
+    /// Decode a batch of jpeg frames into one tensor, see [`decode_tensor`]
+    ///
+    /// The tensor is written to a buffer kept alive between calls like the
+    /// frame buffers are, so same-sized batches don't allocate after the first.
+    #[wasm_bindgen(unchecked_return_type = "Tensor")]
+    pub fn decode_tensor(
+        &mut self,
+        encoded_js: &js_sys::Array,
+        tensor_options: Option<JsTensorOptions>,
+    ) -> Result<js_sys::Object, DecodeError> {
+        let tensor_options = parse_tensor_options(tensor_options)?;
+        let mut data = core::mem::take(&mut self.tensor);
+        let mut batch = TensorBatch::new(tensor_options, &mut data);
+        for encoded_js in uint8_arrays(encoded_js, "Decoder.decode_tensor")? {
+            self.decode_frame(&encoded_js)?;
+            // lend the frame buffer to the batch without copying it
+            let frame = DecodedImage {
+                data: core::mem::take(&mut self.output),
+                width: self.width,
+                height: self.height,
+                colorspace: self.colorspace,
+            };
+            let pushed = batch.push(&frame);
+            self.output = frame.data;
+            pushed?;
         }
+        let shape = batch.shape();
+        let tensor = tensor_js(&data, shape);
+        self.tensor = data;
+        Ok(tensor)
+    }
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.height
     }
-    Ok(RsImg {
-        data: combinedImg,
-        width: xTotSize as u32,
-        height: yTotSize as u32,
-    })
 }
 
-fn to_js_img(rs_img: RsImg) -> Result<ImageData, String> {
-    let js_arr: js_sys::Uint8ClampedArray =
-        js_sys::Uint8ClampedArray::new_with_length(rs_img.data.len() as u32);
-    js_arr.copy_from(&rs_img.data);
-    let img_data =
-        ImageData::new_with_js_u8_clamped_array_and_sh(&js_arr, rs_img.width, rs_img.height)
-            .map_err(|err: JsValue| format!("Error creating JS imagedata: {err:?}"))?;
-    Ok(img_data)
+impl Decoder {
+    fn with_options(options: DecoderOptions) -> Decoder {
+        Decoder {
+            decoder: zune_jpeg::JpegDecoder::new_with_options(Vec::new(), options),
+            spare_input: Vec::new(),
+            output: Vec::new(),
+            tensor: Vec::new(),
+            width: 0,
+            height: 0,
+            colorspace: options.jpeg_get_out_colorspace(),
+        }
+    }
+    /// Decode a jpeg frame into `output`
+    fn decode_frame(&mut self, encoded_js: &js_sys::Uint8Array) -> Result<(), DecodeError> {
+        let mut input = core::mem::take(&mut self.spare_input);
+        input.resize(encoded_js.length() as usize, 0);
+        encoded_js.copy_to(&mut input);
+        self.spare_input = self.decoder.reset(input);
+
+        let (width, height) = native::decode_jpeg_with(&mut self.decoder, &mut self.output)?;
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::with_options(native::default_options())
+    }
+}
+
+/// Jpeg decoder fed with chunks of the file as they arrive, e.g. from a `fetch` stream
+///
+/// Baseline jpegs are decoded row by row as chunks are pushed, so
+/// [`image`](StreamingDecoder::image) can show the top of the picture before the
+/// rest has been downloaded. Progressive jpegs are only decoded by
+/// [`finish`](StreamingDecoder::finish), their first scans aren't shown and
+/// [`image`](StreamingDecoder::image) stays blank until then.
+#[wasm_bindgen]
+pub struct StreamingDecoder {
+    stream: native::JpegStream,
+}
+
+#[wasm_bindgen]
+impl StreamingDecoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new(options: Option<JsDecodeOptions>) -> Result<StreamingDecoder, DecodeError> {
+        let options = parse_image_data_options(options)?;
+        Ok(StreamingDecoder {
+            stream: native::JpegStream::new(&options)?,
+        })
+    }
+    /// Append `chunk` to the input and decode as much of the image as it allows
+    pub fn push(&mut self, chunk: &js_sys::Uint8Array) -> Result<StreamStatus, DecodeError> {
+        self.stream.push(&chunk.to_vec())
+    }
+    /// Mark the input as finished and return the decoded image
+    pub fn finish(&mut self) -> Result<ImageData, DecodeError> {
+        to_js_img(self.stream.finish()?)
+    }
+    /// The image decoded so far, rows that haven't arrived yet are transparent black
+    ///
+    /// `undefined` until the headers have arrived.
+    pub fn image(&self) -> Result<Option<ImageData>, DecodeError> {
+        self.stream.image()?.map(to_js_img).transpose()
+    }
+    /// Number of rows of the stored image decoded so far
+    #[wasm_bindgen(getter)]
+    pub fn rows_decoded(&self) -> u32 {
+        self.stream.rows_decoded()
+    }
+}
+
+#[wasm_bindgen]
+pub fn decode_jpeg(
+    encoded_js: &js_sys::Uint8Array,
+    options: Option<JsDecodeOptions>,
+) -> Result<ImageData, DecodeError> {
+    let options = parse_image_data_options(options)?;
+    let encoded: Vec<u8> = encoded_js.to_vec();
+    to_js_img(native::decode_jpeg(&encoded, &options)?)
 }
+/// Decode the `width`x`height` rectangle at (`x`, `y`) of a jpeg with zune-jpeg
+///
+/// The rectangle is in pixels of the decoded image, i.e. after any `scaleDenominator`.
+/// Only the part of the jpeg covering it is transformed and color converted.
 #[wasm_bindgen]
-pub fn decode_jpeg(encoded_js: &js_sys::Uint8Array) -> Result<ImageData, String> {
+pub fn decode_jpeg_region(
+    encoded_js: &js_sys::Uint8Array,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    options: Option<JsDecodeOptions>,
+) -> Result<ImageData, DecodeError> {
+    let options = parse_image_data_options(options)?;
     let encoded: Vec<u8> = encoded_js.to_vec();
-    let rs_img = decode_jpeg_rs(&encoded)?;
-    let js_img = to_js_img(rs_img)?;
-    Ok(js_img)
+    let region = Region {
+        x,
+        y,
+        width,
+        height,
+    };
+    to_js_img(native::decode_jpeg_region(&encoded, region, &options)?)
 }
+/// Decode an image, resized as `resize` says if it is given
 #[wasm_bindgen]
 pub fn decode_img(
     encoded_js: &js_sys::Uint8Array,
     use_fast_jpeg: &js_sys::Boolean,
-) -> Result<ImageData, String> {
+    options: Option<JsDecodeOptions>,
+    resize: Option<JsResizeOptions>,
+) -> Result<ImageData, DecodeError> {
+    let options = parse_image_data_options(options)?;
+    let encoded: Vec<u8> = encoded_js.to_vec();
+    let use_fast_jpeg_rs = use_fast_jpeg.as_bool().unwrap_or(false);
+    to_js_img(decode_maybe_resized(
+        &encoded,
+        use_fast_jpeg_rs,
+        &options,
+        resize,
+    )?)
+}
+fn decode_maybe_resized(
+    encoded: &[u8],
+    use_fast_jpeg: bool,
+    options: &DecoderOptions,
+    resize: Option<JsResizeOptions>,
+) -> Result<DecodedImage, DecodeError> {
+    match resize {
+        Some(resize) => {
+            let resize = parse_resize_options(resize)?;
+            native::decode_resized(encoded, use_fast_jpeg, options, &resize)
+        }
+        None => native::decode(encoded, use_fast_jpeg, options),
+    }
+}
+/// Result of [`decode_img_with_report`]
+#[wasm_bindgen(getter_with_clone)]
+pub struct DecodeReport {
+    pub image: ImageData,
+    /// Decoder that produced `image`, `zune-jpeg` or `image`
+    pub decoder: String,
+    /// `ImageDecodeError` the zune-jpeg fast path failed with before falling back
+    /// to the `image` crate, `undefined` if there was no fallback
+    pub fast_path_error: JsValue,
+}
+/// Decode an image like [`decode_img`], retrying the zune-jpeg failures selected by
+/// `fallback` with the `image` crate, and report which decoder was used
+///
+/// If the fallback fails too, the zune-jpeg error is returned.
+#[wasm_bindgen]
+pub fn decode_img_with_report(
+    encoded_js: &js_sys::Uint8Array,
+    use_fast_jpeg: bool,
+    fallback: Option<JpegFallback>,
+    options: Option<JsDecodeOptions>,
+) -> Result<DecodeReport, DecodeError> {
+    let options = parse_image_data_options(options)?;
+    let encoded: Vec<u8> = encoded_js.to_vec();
+    let attempt = native::decode_with_fallback(
+        &encoded,
+        use_fast_jpeg,
+        fallback.unwrap_or_default(),
+        &options,
+    )?;
+    Ok(DecodeReport {
+        image: to_js_img(attempt.image)?,
+        decoder: attempt.decoder.to_string(),
+        fast_path_error: attempt
+            .fast_path_error
+            .map_or(JsValue::UNDEFINED, JsValue::from),
+    })
+}
+/// Read the dimensions and format of an image without decoding its pixels
+#[wasm_bindgen]
+pub fn probe_image(encoded_js: &js_sys::Uint8Array) -> Result<ImageProbe, DecodeError> {
+    let encoded: Vec<u8> = encoded_js.to_vec();
+    native::probe(&encoded)
+}
+/// Decode a jpeg, calling `on_frame(frame, scans)` with an `ImageData` of the scans
+/// decoded so far after every `scan_interval` (default 1) scans of a progressive
+/// jpeg, and return the finished image
+///
+/// This shows progressive jpegs refining the way browsers do while they load.
+/// Baseline jpegs are returned without calling `on_frame`. An exception thrown by
+/// `on_frame` stops decoding and is rethrown.
+#[wasm_bindgen]
+pub fn decode_jpeg_progressive(
+    encoded_js: &js_sys::Uint8Array,
+    on_frame: &js_sys::Function,
+    scan_interval: Option<usize>,
+    options: Option<JsDecodeOptions>,
+) -> Result<ImageData, JsValue> {
+    let options = parse_image_data_options(options)?;
     let encoded: Vec<u8> = encoded_js.to_vec();
-    let use_fast_jpeg_rs = use_fast_jpeg.as_bool().or(Some(false)).unwrap();
-    let rs_img = decode_img_rs(&encoded, use_fast_jpeg_rs)?;
-    let js_img = to_js_img(rs_img)?;
-    Ok(js_img)
+    let mut js_error = None;
+    let decoded = native::decode_jpeg_progressive(
+        &encoded,
+        scan_interval.unwrap_or(1),
+        &options,
+        |pixels, width, height, scans| {
+            let frame = slice_to_js_img(pixels, width, height)?;
+            let scans = JsValue::from(scans as u32);
+            on_frame
+                .call2(&JsValue::NULL, &frame, &scans)
+                .map_err(|err| {
+                    js_error = Some(err);
+                    DecodeError::invalid_argument("on_frame threw".to_string())
+                })?;
+            Ok(())
+        },
+    );
+    if let Some(err) = js_error {
+        return Err(err);
+    }
+    Ok(to_js_img(decoded?)?)
+}
+/// Read the ICC profile, EXIF data and other metadata of an image without
+/// decoding its pixels
+#[wasm_bindgen]
+pub fn read_metadata(encoded_js: &js_sys::Uint8Array) -> Result<ImageMetadata, DecodeError> {
+    let encoded: Vec<u8> = encoded_js.to_vec();
+    native::read_metadata(&encoded)
+}
+/// The `Uint8Array`s of `encoded_js`, the first argument of `function`
+fn uint8_arrays(
+    encoded_js: &js_sys::Array,
+    function: &str,
+) -> Result<Vec<js_sys::Uint8Array>, DecodeError> {
+    encoded_js
+        .iter()
+        .map(|js_obj| {
+            js_obj.dyn_into::<js_sys::Uint8Array>().map_err(|err| {
+                DecodeError::invalid_argument(format!(
+                    "{function}'s first argument expects an Array of Uint8Array: {err:?}"
+                ))
+            })
+        })
+        .collect()
+}
+/// Decode the Array of Uint8Array in `encoded_js` and pack them into a grid
+fn decode_pack_js_imgs(
+    encoded_js: &js_sys::Array,
+    num_imgs_col: usize,
+    use_fast_jpeg: bool,
+    options: &DecoderOptions,
+    pack_options: Option<JsPackOptions>,
+) -> Result<DecodedImage, DecodeError> {
+    let pack_options = parse_pack_options(pack_options)?;
+    let encoded: Vec<Vec<u8>> = uint8_arrays(encoded_js, "decode_pack_imgs")?
+        .iter()
+        .map(js_sys::Uint8Array::to_vec)
+        .collect();
+    native::decode_pack(
+        &encoded,
+        num_imgs_col,
+        use_fast_jpeg,
+        options,
+        &pack_options,
+    )
 }
 #[wasm_bindgen]
 pub fn decode_pack_imgs(
     encoded_js: &js_sys::Array,
     num_imgs_col: &js_sys::Number,
     use_fast_jpeg: &js_sys::Boolean,
-) -> Result<ImageData, String> {
-    let use_fast_jpeg_rs = use_fast_jpeg.as_bool().or(Some(false)).unwrap();
-    let num_imgs_col_rs = num_imgs_col
-        .as_f64()
-        .ok_or("Could not decode num_imgs_col into number")? as usize;
-    let mut decodedImgs: Vec<RsImg> = Vec::new();
-    for js_obj in encoded_js.iter() {
-        let js_arr = js_obj.dyn_into::<js_sys::Uint8Array>().map_err(|err| {
-            format!("decode_pack_imgs's first argument expects an Array of Uint8Array: {err:?}")
-        })?;
-        let rs_vec = js_arr.to_vec();
-        decodedImgs.push(decode_img_rs(&rs_vec, use_fast_jpeg_rs)?);
-    }
-    let combined_img = pack_imgs_rs(&decodedImgs, num_imgs_col_rs)?;
-    to_js_img(combined_img)
+    options: Option<JsDecodeOptions>,
+    pack_options: Option<JsPackOptions>,
+) -> Result<ImageData, DecodeError> {
+    let options = parse_image_data_options(options)?;
+    let use_fast_jpeg_rs = use_fast_jpeg.as_bool().unwrap_or(false);
+    let num_imgs_col_rs = num_imgs_col.as_f64().ok_or_else(|| {
+        DecodeError::invalid_argument("Could not decode num_imgs_col into number".to_string())
+    })? as usize;
+    to_js_img(decode_pack_js_imgs(
+        encoded_js,
+        num_imgs_col_rs,
+        use_fast_jpeg_rs,
+        &options,
+        pack_options,
+    )?)
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const RAW_IMAGE_TS: &str = r#"
+/** Decoded pixels as a plain object, for runtimes without `ImageData` such as Node and Deno
+ *
+ * `data` holds a byte per sample, float exports hold samples from 0.0 to 1.0 as
+ * a `Float32Array`, or a `Uint16Array` of half float bits. */
+export interface RawImage<Data = Uint8Array> {
+    width: number;
+    height: number;
+    /** Samples per pixel */
+    channels: number;
+    /** Layout of the pixels, see `DecodeOptions.outColorspace` */
+    colorspace: string;
+    data: Data;
+}
+
+/** Where `decode_img_into` wrote an image in the buffer it was given */
+export interface ImageLayout {
+    width: number;
+    height: number;
+    /** Samples per pixel */
+    channels: number;
+    /** Layout of the pixels, see `DecodeOptions.outColorspace` */
+    colorspace: string;
+    /** Position of the first byte of the first row */
+    offset: number;
+    /** Bytes from the start of a row to the start of the next */
+    stride: number;
+}
+"#;
+
+/// Build a `RawImage` plain object around `data`, the samples of a
+/// `width`x`height` image in `colorspace`
+fn raw_js_img(data: &JsValue, width: u32, height: u32, colorspace: ColorSpace) -> js_sys::Object {
+    js_object([
+        ("width", JsValue::from(width)),
+        ("height", JsValue::from(height)),
+        (
+            "channels",
+            JsValue::from(colorspace.num_components() as u32),
+        ),
+        ("colorspace", JsValue::from_str(&format!("{colorspace:?}"))),
+        ("data", data.clone()),
+    ])
+}
+/// Build a plain object with `fields`
+fn js_object<const N: usize>(fields: [(&str, JsValue); N]) -> js_sys::Object {
+    let object = js_sys::Object::new();
+    for (key, value) in fields {
+        // setting a property on a plain object can't fail
+        js_sys::Reflect::set(&object, &JsValue::from_str(key), &value).unwrap_throw();
+    }
+    object
+}
+fn to_raw_js_img(image: DecodedImage) -> js_sys::Object {
+    let data = js_sys::Uint8Array::new_with_length(image.data.len() as u32);
+    data.copy_from(&image.data);
+    raw_js_img(&data, image.width, image.height, image.colorspace)
+}
+/// [`decode_jpeg`] returning a `RawImage` instead of an `ImageData`
+#[wasm_bindgen(unchecked_return_type = "RawImage")]
+pub fn decode_jpeg_raw(
+    encoded_js: &js_sys::Uint8Array,
+    options: Option<JsDecodeOptions>,
+) -> Result<js_sys::Object, DecodeError> {
+    let options = parse_options(options)?;
+    let encoded: Vec<u8> = encoded_js.to_vec();
+    Ok(to_raw_js_img(native::decode_jpeg(&encoded, &options)?))
+}
+/// [`decode_jpeg_region`] returning a `RawImage` instead of an `ImageData`
+#[wasm_bindgen(unchecked_return_type = "RawImage")]
+pub fn decode_jpeg_region_raw(
+    encoded_js: &js_sys::Uint8Array,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    options: Option<JsDecodeOptions>,
+) -> Result<js_sys::Object, DecodeError> {
+    let options = parse_options(options)?;
+    let encoded: Vec<u8> = encoded_js.to_vec();
+    let region = Region {
+        x,
+        y,
+        width,
+        height,
+    };
+    Ok(to_raw_js_img(native::decode_jpeg_region(
+        &encoded, region, &options,
+    )?))
+}
+/// [`decode_img`] returning a `RawImage` instead of an `ImageData`
+#[wasm_bindgen(unchecked_return_type = "RawImage")]
+pub fn decode_img_raw(
+    encoded_js: &js_sys::Uint8Array,
+    use_fast_jpeg: bool,
+    options: Option<JsDecodeOptions>,
+    resize: Option<JsResizeOptions>,
+) -> Result<js_sys::Object, DecodeError> {
+    let options = parse_options(options)?;
+    let encoded: Vec<u8> = encoded_js.to_vec();
+    Ok(to_raw_js_img(decode_maybe_resized(
+        &encoded,
+        use_fast_jpeg,
+        &options,
+        resize,
+    )?))
+}
+/// Decode an image like [`decode_img_raw`] into `f32` samples from 0.0 to 1.0
+///
+/// zune-jpeg color converts the 8 bit output of its IDCT in floating point, so jpeg
+/// samples only skip the rounding after color conversion. 16 bit images of other
+/// formats keep their precision.
+#[wasm_bindgen(unchecked_return_type = "RawImage<Float32Array>")]
+pub fn decode_img_f32(
+    encoded_js: &js_sys::Uint8Array,
+    use_fast_jpeg: bool,
+    options: Option<JsDecodeOptions>,
+) -> Result<js_sys::Object, DecodeError> {
+    let options = parse_options(options)?;
+    let encoded: Vec<u8> = encoded_js.to_vec();
+    let image = native::decode_f32(&encoded, use_fast_jpeg, &options)?;
+    let data = js_sys::Float32Array::from(&image.data[..]);
+    Ok(raw_js_img(
+        &data,
+        image.width,
+        image.height,
+        image.colorspace,
+    ))
+}
+/// [`decode_img_f32`] returning the samples as half floats, for WebGL
+/// `HALF_FLOAT` textures
+///
+/// `data` is a `Uint16Array` holding the bits of each half float.
+#[wasm_bindgen(unchecked_return_type = "RawImage<Uint16Array>")]
+pub fn decode_img_f16(
+    encoded_js: &js_sys::Uint8Array,
+    use_fast_jpeg: bool,
+    options: Option<JsDecodeOptions>,
+) -> Result<js_sys::Object, DecodeError> {
+    let options = parse_options(options)?;
+    let encoded: Vec<u8> = encoded_js.to_vec();
+    let image = native::decode_f32(&encoded, use_fast_jpeg, &options)?;
+    let data = js_sys::Uint16Array::from(&image.to_f16_bits()[..]);
+    Ok(raw_js_img(
+        &data,
+        image.width,
+        image.height,
+        image.colorspace,
+    ))
+}
+/// Decode an image into the caller-provided `out`
+///
+/// Rows are written `offset` bytes into `out` and `stride` bytes apart, 0 and
+/// `width * channels` by default, so images can be decoded straight into atlas
+/// tiles, GPU staging buffers with aligned rows or padded framebuffers. `out` must
+/// hold every row up to the end of the last one, bytes around the rows are left
+/// untouched.
+///
+/// Jpegs are decoded straight into `out`. Other formats, and jpegs zune-jpeg can't
+/// decode, are decoded by the `image` crate into a buffer of their own first and
+/// copied into `out`, so they briefly take twice the memory.
+///
+/// Returns the `ImageLayout` the rows were written with.
+#[wasm_bindgen(unchecked_return_type = "ImageLayout")]
+pub fn decode_img_into(
+    encoded_js: &js_sys::Uint8Array,
+    out: &mut [u8],
+    use_fast_jpeg: bool,
+    options: Option<JsDecodeOptions>,
+    offset: Option<usize>,
+    stride: Option<usize>,
+) -> Result<js_sys::Object, DecodeError> {
+    let options = parse_options(options)?;
+    let encoded: Vec<u8> = encoded_js.to_vec();
+    let image = native::PendingImage::read(&encoded, use_fast_jpeg, &options)?;
+    let (width, height) = image.size();
+    let colorspace = image.colorspace();
+    let layout = OutputLayout {
+        offset: offset.unwrap_or(0),
+        stride: stride.unwrap_or(image.row_size()),
+    };
+    image.decode_into(out, layout)?;
+    Ok(js_object([
+        ("width", JsValue::from(width as u32)),
+        ("height", JsValue::from(height as u32)),
+        (
+            "channels",
+            JsValue::from(colorspace.num_components() as u32),
+        ),
+        ("colorspace", JsValue::from_str(&format!("{colorspace:?}"))),
+        ("offset", JsValue::from(layout.offset)),
+        ("stride", JsValue::from(layout.stride)),
+    ]))
+}
+/// [`decode_pack_imgs`] returning a `RawImage` instead of an `ImageData`
+#[wasm_bindgen(unchecked_return_type = "RawImage")]
+pub fn decode_pack_imgs_raw(
+    encoded_js: &js_sys::Array,
+    num_imgs_col: usize,
+    use_fast_jpeg: bool,
+    options: Option<JsDecodeOptions>,
+    pack_options: Option<JsPackOptions>,
+) -> Result<js_sys::Object, DecodeError> {
+    let options = parse_options(options)?;
+    Ok(to_raw_js_img(decode_pack_js_imgs(
+        encoded_js,
+        num_imgs_col,
+        use_fast_jpeg,
+        &options,
+        pack_options,
+    )?))
+}
+#[wasm_bindgen(typescript_custom_section)]
+const YUV_IMAGE_TS: &str = r#"
+/** A plane of samples in `YuvImage.data` */
+export interface YuvPlane {
+    /** Position of the first sample of the plane */
+    offset: number;
+    /** Width in samples, in Cb and Cr pairs for the chroma plane of `YuvLayout.SemiPlanar` */
+    width: number;
+    height: number;
+    /** Bytes from the start of a row to the start of the next */
+    stride: number;
+}
+/** Y, Cb and Cr planes of a jpeg, see `decode_jpeg_yuv` */
+export interface YuvImage {
+    /** Size of the image, and of the Y plane */
+    width: number;
+    height: number;
+    /** The Y, Cb and Cr planes, or the Y and the interleaved CbCr plane for `YuvLayout.SemiPlanar` */
+    planes: YuvPlane[];
+    data: Uint8Array;
+}
+"#;
+/// Decode the Y, Cb and Cr planes of a jpeg with zune-jpeg without converting
+/// them to RGB, e.g. to pass frames to a video encoder
+///
+/// Only `scaleDenominator` and the limits of `options` apply. Jpegs that aren't
+/// YCbCr, such as grayscale or CMYK ones, fail.
+#[wasm_bindgen(unchecked_return_type = "YuvImage")]
+pub fn decode_jpeg_yuv(
+    encoded_js: &js_sys::Uint8Array,
+    layout: YuvLayout,
+    options: Option<JsDecodeOptions>,
+) -> Result<js_sys::Object, DecodeError> {
+    let options = parse_options(options)?;
+    let encoded: Vec<u8> = encoded_js.to_vec();
+    let image = native::decode_jpeg_yuv(&encoded, layout, &options)?;
+    let planes: js_sys::Array = image
+        .planes
+        .iter()
+        .map(|plane| {
+            js_object([
+                ("offset", JsValue::from(plane.offset as u32)),
+                ("width", JsValue::from(plane.width as u32)),
+                ("height", JsValue::from(plane.height as u32)),
+                ("stride", JsValue::from(plane.stride as u32)),
+            ])
+        })
+        .collect();
+    let data = js_sys::Uint8Array::from(&image.data[..]);
+    Ok(js_object([
+        ("width", JsValue::from(image.width)),
+        ("height", JsValue::from(image.height)),
+        ("planes", planes.into()),
+        ("data", data.into()),
+    ]))
+}
+#[wasm_bindgen(typescript_custom_section)]
+const ATLAS_TS: &str = r#"
+/** Where an image is in an atlas, see `decode_atlas` */
+export interface AtlasRect {
+    /** Index of the page holding the image */
+    page: number;
+    /** Position and size in pixels, rows counted as stored in the page data */
+    x: number;
+    y: number;
+    width: number;
+    height: number;
+    /** Texture coordinates of the corners, from 0 to 1 */
+    u0: number;
+    v0: number;
+    u1: number;
+    v1: number;
+}
+/** Pages of an atlas and the rectangle of each image, in the order of the images */
+export interface Atlas {
+    pages: RawImage[];
+    rects: AtlasRect[];
+}
+"#;
+/// Build an `Atlas` plain object holding copies of the pages of `atlas`
+fn atlas_js(atlas: Atlas) -> js_sys::Object {
+    let rects: js_sys::Array = atlas
+        .rects
+        .iter()
+        .map(|rect| {
+            let page = &atlas.pages[rect.page];
+            let [u0, v0, u1, v1] = rect.uv(page.width, page.height);
+            JsValue::from(js_object([
+                ("page", JsValue::from(rect.page as u32)),
+                ("x", JsValue::from(rect.x)),
+                ("y", JsValue::from(rect.y)),
+                ("width", JsValue::from(rect.width)),
+                ("height", JsValue::from(rect.height)),
+                ("u0", JsValue::from(u0)),
+                ("v0", JsValue::from(v0)),
+                ("u1", JsValue::from(u1)),
+                ("v1", JsValue::from(v1)),
+            ]))
+        })
+        .collect();
+    let pages: js_sys::Array = atlas
+        .pages
+        .into_iter()
+        .map(|page| JsValue::from(to_raw_js_img(page)))
+        .collect();
+    js_object([("pages", pages.into()), ("rects", rects.into())])
+}
+/// Decode images of any size and place them into the pages of a texture atlas
+///
+/// Images are placed with a skyline packer within the page size `atlas_options`
+/// allow, starting a new page when one is full. All images must decode to the
+/// same colorspace.
+#[wasm_bindgen(unchecked_return_type = "Atlas")]
+pub fn decode_atlas(
+    encoded_js: &js_sys::Array,
+    use_fast_jpeg: bool,
+    atlas_options: Option<JsAtlasOptions>,
+    options: Option<JsDecodeOptions>,
+) -> Result<js_sys::Object, DecodeError> {
+    let atlas_options = parse_atlas_options(atlas_options)?;
+    let options = parse_options(options)?;
+    let encoded: Vec<Vec<u8>> = uint8_arrays(encoded_js, "decode_atlas")?
+        .iter()
+        .map(js_sys::Uint8Array::to_vec)
+        .collect();
+    let atlas = atlas::decode_atlas(&encoded, use_fast_jpeg, &options, &atlas_options)?;
+    Ok(atlas_js(atlas))
+}
+#[wasm_bindgen(typescript_custom_section)]
+const TENSOR_TS: &str = r#"
+/** A batch of images laid out for an ML model, see `decode_tensor` */
+export interface Tensor {
+    /** Size of each dimension, in the order of `TensorOptions.layout` */
+    shape: number[];
+    data: Float32Array;
+}
+"#;
+/// Build a `Tensor` plain object holding a copy of `data`
+fn tensor_js(data: &[f32], shape: TensorShape) -> js_sys::Object {
+    let dims: js_sys::Array = shape
+        .dims()
+        .iter()
+        .map(|&dim| JsValue::from(dim as u32))
+        .collect();
+    js_object([
+        ("shape", dims.into()),
+        ("data", js_sys::Float32Array::from(data).into()),
+    ])
+}
+/// Decode a batch of images into one `f32` tensor for an ML model
+///
+/// Images are laid out, normalised and resized as `tensor_options` say and written
+/// one after the other into a single `Float32Array`.
+#[wasm_bindgen(unchecked_return_type = "Tensor")]
+pub fn decode_tensor(
+    encoded_js: &js_sys::Array,
+    use_fast_jpeg: bool,
+    tensor_options: Option<JsTensorOptions>,
+    options: Option<JsDecodeOptions>,
+) -> Result<js_sys::Object, DecodeError> {
+    let tensor_options = parse_tensor_options(tensor_options)?;
+    let options = parse_options(options)?;
+    let encoded: Vec<Vec<u8>> = uint8_arrays(encoded_js, "decode_tensor")?
+        .iter()
+        .map(js_sys::Uint8Array::to_vec)
+        .collect();
+    let mut data = Vec::new();
+    let shape =
+        tensor::decode_tensor(&encoded, use_fast_jpeg, &options, tensor_options, &mut data)?;
+    Ok(tensor_js(&data, shape))
 }
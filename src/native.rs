@@ -0,0 +1,2887 @@
+//! Native Rust API
+//!
+//! The decode and pack logic behind the wasm exports, without any `js_sys` types,
+//! so it can be shared with native code and tested with `cargo test`. The wasm
+//! exports in the crate root are thin adapters over this module.
+use std::io::Cursor;
+
+use image::error::{LimitError, LimitErrorKind};
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{ColorType, DynamicImage, ImageBuffer, ImageDecoder, ImageError, ImageReader, Limits};
+use wasm_bindgen::prelude::*;
+use zune_core::bytestream::ZReaderTrait;
+use zune_core::colorspace::ColorSpace;
+use zune_core::options::DecoderOptions;
+use zune_jpeg::errors::DecodeErrors;
+use zune_jpeg::{DecodeStatus, IncrementalDecoder, JpegDecoder, OutputLayout, Plane, PlaneLayout};
+
+use crate::errors::{DecodeError, UNKNOWN_FORMAT};
+use crate::resize::{self, ResizeFilter, ResizeFit, ResizeOptions};
+
+/// Output colorspaces supported by both the zune-jpeg and the `image` crate paths
+pub const OUT_COLORSPACES: [ColorSpace; 5] = [
+    ColorSpace::RGBA,
+    ColorSpace::RGB,
+    ColorSpace::BGRA,
+    ColorSpace::BGR,
+    ColorSpace::Luma,
+];
+
+/// Supported denominators of the scale images are decoded at, see
+/// `DecoderOptions::jpeg_set_scale_denominator`
+pub const SCALE_DENOMINATORS: [usize; 4] = [1, 2, 4, 8];
+
+/// Name of the zune-jpeg decoder in [`DecodeAttempt::decoder`]
+pub const FAST_JPEG_DECODER: &str = "zune-jpeg";
+/// Name of the `image` crate decoder in [`DecodeAttempt::decoder`]
+pub const GENERIC_DECODER: &str = "image";
+
+/// A decoded image, rows stored top to bottom without padding
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodedImage {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    /// Layout of the pixels in `data`, one of [`OUT_COLORSPACES`]
+    pub colorspace: ColorSpace,
+}
+
+impl DecodedImage {
+    /// Number of bytes per pixel
+    pub const fn channels(&self) -> usize {
+        self.colorspace.num_components()
+    }
+
+    /// Check that `data` holds exactly `width`x`height` pixels of `colorspace`
+    ///
+    /// # Errors
+    /// The length of `data` is another one, e.g. for an image built by hand with the
+    /// wrong colorspace.
+    pub fn check_len(&self) -> Result<(), DecodeError> {
+        let expected = (self.width as u64) * (self.height as u64) * self.channels() as u64;
+        if self.data.len() as u64 != expected {
+            return Err(DecodeError::invalid_argument(format!(
+                "{}x{} {:?} image holds {} bytes instead of {expected}",
+                self.width,
+                self.height,
+                self.colorspace,
+                self.data.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A decoded image with `f32` samples from 0.0 to 1.0, see [`DecodedImage`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct FloatImage {
+    pub data: Vec<f32>,
+    pub width: u32,
+    pub height: u32,
+    /// Layout of the pixels in `data`, one of [`OUT_COLORSPACES`]
+    pub colorspace: ColorSpace,
+}
+
+impl FloatImage {
+    /// Number of samples per pixel
+    pub const fn channels(&self) -> usize {
+        self.colorspace.num_components()
+    }
+
+    /// Bits of the samples as IEEE 754 half floats, the layout of WebGL `HALF_FLOAT`
+    /// textures
+    pub fn to_f16_bits(&self) -> Vec<u16> {
+        self.data.iter().map(|&value| f16_bits(value)).collect()
+    }
+}
+
+/// Bits of the IEEE 754 half float nearest to `value`, ties to even
+fn f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // infinity stays infinity, NaN stays NaN
+        return sign | 0x7c00 | if mantissa == 0 { 0 } else { 0x200 };
+    }
+    // rebias from 127 to 15
+    let exponent = exponent - 112;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    // keep the 10 high bits of the mantissa, rounding on the ones shifted out
+    let round = |kept: u32, shift: u32, full: u32| {
+        let rest = full & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        kept + u32::from(rest > halfway || (rest == halfway && kept & 1 == 1))
+    };
+    if exponent <= 0 {
+        // too small for a normal half float, only the subnormals are left
+        if exponent < -10 {
+            return sign;
+        }
+        let full = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        return sign | round(full >> shift, shift, full) as u16;
+    }
+    let kept = ((exponent as u32) << 10) | (mantissa >> 13);
+    // a carry out of the mantissa correctly bumps the exponent
+    sign | round(kept, 13, mantissa) as u16
+}
+
+/// A rectangle of an image, in pixels of the decoded image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Which zune-jpeg failures are retried with the `image` crate decoder
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JpegFallback {
+    /// Return the zune-jpeg error
+    Never,
+    /// Retry jpegs using features zune-jpeg does not implement, e.g. arithmetic
+    /// coding, lossless frames or DNL markers
+    #[default]
+    Unsupported,
+    /// Retry on any zune-jpeg error, including corrupt and truncated files
+    Always,
+}
+
+impl JpegFallback {
+    pub(crate) fn retries(self, err: &DecodeError) -> bool {
+        match self {
+            JpegFallback::Never => false,
+            JpegFallback::Unsupported => err.is_unsupported(),
+            JpegFallback::Always => true,
+        }
+    }
+}
+
+/// Decoded image along with how it was decoded
+#[derive(Debug)]
+pub struct DecodeAttempt {
+    pub image: DecodedImage,
+    /// Decoder that produced `image`, [`FAST_JPEG_DECODER`] or [`GENERIC_DECODER`]
+    pub decoder: &'static str,
+    /// Error of the zune-jpeg fast path if `image` comes from the fallback decoder
+    pub fast_path_error: Option<DecodeError>,
+}
+
+/// Header information of an encoded image, read without decoding any pixels
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug)]
+pub struct ImageProbe {
+    /// Width as stored, before the EXIF orientation is applied
+    pub width: u32,
+    pub height: u32,
+    /// Detected container format, e.g. `jpeg`, `png`, `webp`
+    pub format: String,
+    /// Colorspace the pixels are stored in, e.g. `YCbCr`, `CMYK`, `RGBA`
+    pub colorspace: String,
+    /// JPEG start of frame type, `undefined` for other formats
+    pub sof: Option<String>,
+    pub components: u8,
+    /// Whether a jpeg is progressive, `undefined` for other formats as the `image`
+    /// crate doesn't tell whether they are interlaced
+    pub progressive: Option<bool>,
+}
+
+/// Metadata stored alongside the pixels of an encoded image
+///
+/// Fields a format can't hold, or the `image` crate doesn't expose for it, are
+/// `undefined`.
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImageMetadata {
+    /// Detected container format, e.g. `jpeg`, `png`, `webp`
+    pub format: String,
+    /// Raw ICC profile
+    pub icc_profile: Option<Vec<u8>>,
+    /// Raw EXIF data, starting at the TIFF header
+    pub exif: Option<Vec<u8>>,
+    /// Units of the JFIF densities, 0 if they only give the pixel aspect ratio,
+    /// 1 for dots per inch, 2 for dots per centimeter
+    pub density_units: Option<u8>,
+    /// Horizontal JFIF pixel density
+    pub x_density: Option<u16>,
+    /// Vertical JFIF pixel density
+    pub y_density: Option<u16>,
+    /// Whether the image is a motion jpeg frame, which leaves out its huffman tables
+    pub is_mjpeg: bool,
+    /// Color transform of the Adobe APP14 segment of a jpeg, 0 for RGB or CMYK,
+    /// 1 for YCbCr, 2 for YCCK
+    pub adobe_transform: Option<u8>,
+}
+
+/// Default options of the wrapper, zune defaults with RGBA output
+///
+/// Images are decoded as stored, EXIF orientation is only applied when
+/// [`DecoderOptions::set_apply_orientation`] asks for it so existing callers keep
+/// getting the same sizes and pixels.
+pub fn default_options() -> DecoderOptions {
+    DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGBA)
+}
+
+/// Check that the output colorspace of `options` is one of [`OUT_COLORSPACES`]
+/// and its scale denominator one of [`SCALE_DENOMINATORS`]
+pub fn check_options(options: &DecoderOptions) -> Result<(), DecodeError> {
+    let colorspace = options.jpeg_get_out_colorspace();
+    if !OUT_COLORSPACES.contains(&colorspace) {
+        return Err(DecodeError::invalid_argument(format!(
+            "Unsupported output colorspace {colorspace:?}, expected one of {OUT_COLORSPACES:?}"
+        )));
+    }
+    let denominator = options.jpeg_get_scale_denominator();
+    if !SCALE_DENOMINATORS.contains(&denominator) {
+        return Err(DecodeError::invalid_argument(format!(
+            "Unsupported scale denominator {denominator}, expected one of {SCALE_DENOMINATORS:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Decode a jpeg into `out`, reusing its capacity, and return the image dimensions
+pub(crate) fn decode_jpeg_into(
+    encoded: &[u8],
+    options: DecoderOptions,
+    out: &mut Vec<u8>,
+) -> Result<(u32, u32), DecodeError> {
+    let mut decoder = JpegDecoder::new_with_options(encoded, options);
+    decode_jpeg_with(&mut decoder, out)
+}
+
+/// Decode the image `decoder` was set up with into `out` and return its dimensions
+///
+/// Pixels are in the output colorspace the options of `decoder` ask for, see
+/// [`expand_luma`].
+pub(crate) fn decode_jpeg_with<T: ZReaderTrait>(
+    decoder: &mut JpegDecoder<T>,
+    out: &mut Vec<u8>,
+) -> Result<(u32, u32), DecodeError> {
+    // read before the headers, which override it for grayscale jpegs
+    let colorspace = decoder.get_options().jpeg_get_out_colorspace();
+    decoder.decode_headers().map_err(DecodeError::from_jpeg)?;
+    let size = decoder
+        .output_buffer_size()
+        .ok_or_else(missing_jpeg_dimensions)?;
+    out.resize(size, 0);
+    decoder.decode_into(out).map_err(DecodeError::from_jpeg)?;
+    let decoded = decoder
+        .get_output_colorspace()
+        .ok_or_else(missing_jpeg_dimensions)?;
+    expand_luma(out, decoded, colorspace, u8::MAX)?;
+    let dims: (usize, usize) = decoder
+        .output_dimensions()
+        .ok_or_else(missing_jpeg_dimensions)?;
+    Ok((dims.0 as u32, dims.1 as u32))
+}
+
+/// Convert `samples` zune-jpeg decoded to `decoded` to `colorspace` in place
+///
+/// zune-jpeg decodes grayscale jpegs to Luma whatever output colorspace its
+/// options ask for, so their samples are copied to every color channel, with
+/// `opaque` alpha.
+fn expand_luma<T: Copy>(
+    samples: &mut Vec<T>,
+    decoded: ColorSpace,
+    colorspace: ColorSpace,
+    opaque: T,
+) -> Result<(), DecodeError> {
+    if decoded == colorspace {
+        return Ok(());
+    }
+    if decoded != ColorSpace::Luma {
+        return Err(DecodeError::from_jpeg(DecodeErrors::Format(format!(
+            "Decoded to {decoded:?} instead of the requested {colorspace:?}"
+        ))));
+    }
+    let pixels = samples.len();
+    samples.resize(pixels * colorspace.num_components(), opaque);
+    spread_luma(samples, colorspace, opaque);
+    Ok(())
+}
+
+/// Spread the Luma samples at the start of `samples`, one per pixel, over the
+/// channels of the `colorspace` pixels `samples` holds
+fn spread_luma<T: Copy>(samples: &mut [T], colorspace: ColorSpace, opaque: T) {
+    let channels = colorspace.num_components();
+    let alpha = colorspace.alpha_position();
+    // back to front so no sample is overwritten before it is read
+    for i in (0..samples.len() / channels).rev() {
+        let luma = samples[i];
+        for (channel, sample) in samples[i * channels..(i + 1) * channels]
+            .iter_mut()
+            .enumerate()
+        {
+            *sample = if Some(channel) == alpha { opaque } else { luma };
+        }
+    }
+}
+
+fn missing_jpeg_dimensions() -> DecodeError {
+    DecodeError::from_jpeg(DecodeErrors::FormatStatic(
+        "Image dimensions not known after decoding headers",
+    ))
+}
+
+/// Decode a jpeg with zune-jpeg
+pub fn decode_jpeg(encoded: &[u8], options: &DecoderOptions) -> Result<DecodedImage, DecodeError> {
+    check_options(options)?;
+    let mut data = Vec::new();
+    let (width, height) = decode_jpeg_into(encoded, *options, &mut data)?;
+    Ok(DecodedImage {
+        data,
+        width,
+        height,
+        colorspace: options.jpeg_get_out_colorspace(),
+    })
+}
+
+/// Decode a jpeg with zune-jpeg into `f32` samples
+///
+/// The IDCT outputs 8 bit samples either way, they are only color converted in
+/// floating point, so color samples skip the rounding after conversion and are
+/// within 2/255 of those of [`decode_jpeg`], while grayscale samples are those of
+/// [`decode_jpeg`] divided by 255.
+pub fn decode_jpeg_f32(
+    encoded: &[u8],
+    options: &DecoderOptions,
+) -> Result<FloatImage, DecodeError> {
+    check_options(options)?;
+    let mut decoder = JpegDecoder::new_with_options(encoded, *options);
+    let mut data = decoder.decode_f32().map_err(DecodeError::from_jpeg)?;
+    let (width, height) = decoder
+        .output_dimensions()
+        .ok_or_else(missing_jpeg_dimensions)?;
+    let colorspace = options.jpeg_get_out_colorspace();
+    let decoded = decoder
+        .get_output_colorspace()
+        .ok_or_else(missing_jpeg_dimensions)?;
+    expand_luma(&mut data, decoded, colorspace, 1.0)?;
+    Ok(FloatImage {
+        data,
+        width: width as u32,
+        height: height as u32,
+        colorspace,
+    })
+}
+
+/// Decode only `region` of a jpeg with zune-jpeg
+///
+/// The region is in pixels of the decoded image, so it is scaled along with the
+/// image when decoding at a reduced scale. Only the MCUs covering the region are
+/// transformed and color converted, which is much faster than cropping a full
+/// decode of a large image.
+pub fn decode_jpeg_region(
+    encoded: &[u8],
+    region: Region,
+    options: &DecoderOptions,
+) -> Result<DecodedImage, DecodeError> {
+    check_options(options)?;
+    let mut decoder = JpegDecoder::new_with_options(encoded, *options);
+    decoder.decode_headers().map_err(DecodeError::from_jpeg)?;
+    let (width, height) = decoder
+        .output_dimensions()
+        .ok_or_else(missing_jpeg_dimensions)?;
+    let Region {
+        x,
+        y,
+        width: region_width,
+        height: region_height,
+    } = region;
+    if region_width == 0
+        || region_height == 0
+        || u64::from(x) + u64::from(region_width) > width as u64
+        || u64::from(y) + u64::from(region_height) > height as u64
+    {
+        return Err(DecodeError::invalid_argument(format!(
+            "Region {region_width}x{region_height} at ({x}, {y}) doesn't fit in the {width}x{height} image"
+        )));
+    }
+    let mut data = decoder
+        .decode_region(
+            x as usize,
+            y as usize,
+            region_width as usize,
+            region_height as usize,
+        )
+        .map_err(DecodeError::from_jpeg)?;
+    let colorspace = options.jpeg_get_out_colorspace();
+    let decoded = decoder
+        .get_output_colorspace()
+        .ok_or_else(missing_jpeg_dimensions)?;
+    expand_luma(&mut data, decoded, colorspace, u8::MAX)?;
+    Ok(DecodedImage {
+        data,
+        width: region_width,
+        height: region_height,
+        colorspace,
+    })
+}
+
+/// Decode a jpeg with zune-jpeg, calling `on_frame` with the image rendered from
+/// the scans decoded so far after every `scan_interval` scans of a progressive jpeg
+///
+/// `on_frame` receives the pixels, width and height of the frame and the number of
+/// scans decoded. Baseline jpegs have no intermediate frames. An error returned by
+/// `on_frame` stops decoding and is returned unchanged.
+pub fn decode_jpeg_progressive(
+    encoded: &[u8],
+    scan_interval: usize,
+    options: &DecoderOptions,
+    mut on_frame: impl FnMut(&[u8], u32, u32, usize) -> Result<(), DecodeError>,
+) -> Result<DecodedImage, DecodeError> {
+    check_options(options)?;
+    if scan_interval == 0 {
+        return Err(DecodeError::invalid_argument(
+            "Scan interval must be at least 1".to_string(),
+        ));
+    }
+    let mut decoder = JpegDecoder::new_with_options(encoded, *options);
+    decoder.decode_headers().map_err(DecodeError::from_jpeg)?;
+    let (width, height) = decoder
+        .output_dimensions()
+        .ok_or_else(missing_jpeg_dimensions)?;
+    let (width, height) = (width as u32, height as u32);
+    let size = decoder
+        .output_buffer_size()
+        .ok_or_else(missing_jpeg_dimensions)?;
+    let mut data = vec![0; size];
+    let colorspace = options.jpeg_get_out_colorspace();
+    let decoded = decoder
+        .get_output_colorspace()
+        .ok_or_else(missing_jpeg_dimensions)?;
+
+    // zune-jpeg only passes its own errors through, keep the one of `on_frame` aside
+    let mut frame_error = None;
+    let result = decoder.decode_into_with_scans(&mut data, scan_interval, |pixels, scans| {
+        let mut frame = pixels.to_vec();
+        expand_luma(&mut frame, decoded, colorspace, u8::MAX)
+            .and_then(|()| on_frame(&frame, width, height, scans))
+            .map_err(|err| {
+                frame_error = Some(err);
+                DecodeErrors::FormatStatic("Frame callback failed")
+            })
+    });
+    if let Some(err) = frame_error {
+        return Err(err);
+    }
+    result.map_err(DecodeError::from_jpeg)?;
+    expand_luma(&mut data, decoded, colorspace, u8::MAX)?;
+    Ok(DecodedImage {
+        data,
+        width,
+        height,
+        colorspace,
+    })
+}
+
+/// How [`decode_jpeg_yuv`] lays out the planes of a jpeg
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YuvLayout {
+    /// Y, Cb and Cr planes with chroma at the resolution the jpeg stores it, I420 for
+    /// 4:2:0 jpegs and I422 for 4:2:2 ones
+    Planar,
+    /// A Y plane and a plane of interleaved Cb and Cr samples at the resolution the
+    /// jpeg stores them, NV12 for 4:2:0 jpegs
+    SemiPlanar,
+    /// Y, Cb and Cr planes the size of the image, YUV444
+    FullResolution,
+}
+
+impl From<YuvLayout> for PlaneLayout {
+    fn from(layout: YuvLayout) -> PlaneLayout {
+        match layout {
+            YuvLayout::Planar => PlaneLayout::Planar,
+            YuvLayout::SemiPlanar => PlaneLayout::SemiPlanar,
+            YuvLayout::FullResolution => PlaneLayout::FullResolution,
+        }
+    }
+}
+
+/// Y, Cb and Cr planes of a jpeg decoded without color conversion
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct YuvImage {
+    pub data: Vec<u8>,
+    /// Size of the decoded image, which is the size of the Y plane
+    pub width: u32,
+    pub height: u32,
+    pub layout: YuvLayout,
+    /// The Y, Cb and Cr planes in `data`, or the Y and the interleaved CbCr plane
+    /// for [`YuvLayout::SemiPlanar`]
+    pub planes: Vec<Plane>,
+}
+
+/// Decode the Y, Cb and Cr planes of a jpeg with zune-jpeg, without converting
+/// them to RGB
+///
+/// Samples are written the way the jpeg stores them, so of `options` only the
+/// scale and the limits apply. At a reduced scale chroma may be decoded at the
+/// resolution of luma, check the size of the planes rather than assuming it from
+/// the layout. Jpegs that aren't YCbCr, such as grayscale or CMYK ones, fail.
+pub fn decode_jpeg_yuv(
+    encoded: &[u8],
+    layout: YuvLayout,
+    options: &DecoderOptions,
+) -> Result<YuvImage, DecodeError> {
+    check_options(options)?;
+    let mut decoder = JpegDecoder::new_with_options(encoded, *options);
+    let image = decoder
+        .decode_planar(layout.into())
+        .map_err(DecodeError::from_jpeg)?;
+    let luma = image.planes[0];
+    Ok(YuvImage {
+        data: image.data,
+        width: luma.width as u32,
+        height: luma.height as u32,
+        layout,
+        planes: image.planes,
+    })
+}
+
+/// Progress of a [`JpegStream`]
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamStatus {
+    /// Everything the bytes so far allow has been decoded, push more of them
+    NeedMoreData,
+    /// The whole image has been decoded
+    Complete,
+}
+
+impl From<DecodeStatus> for StreamStatus {
+    fn from(status: DecodeStatus) -> StreamStatus {
+        match status {
+            DecodeStatus::NeedMoreData => StreamStatus::NeedMoreData,
+            DecodeStatus::Complete => StreamStatus::Complete,
+        }
+    }
+}
+
+/// A jpeg decoded with zune-jpeg while its bytes arrive, e.g. from a network response
+///
+/// Baseline jpegs are decoded row by row as chunks are pushed, so the top of the
+/// image can be shown before the rest of it has arrived. Progressive jpegs are
+/// only decoded once the input is finished, nothing of them is shown before.
+pub struct JpegStream {
+    decoder: IncrementalDecoder,
+    options: DecoderOptions,
+}
+
+impl JpegStream {
+    pub fn new(options: &DecoderOptions) -> Result<JpegStream, DecodeError> {
+        check_options(options)?;
+        Ok(JpegStream {
+            decoder: IncrementalDecoder::new_with_options(*options),
+            options: *options,
+        })
+    }
+
+    /// Append `chunk` to the input and decode as much of the image as it allows
+    ///
+    /// After an error the stream can't be used anymore.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<StreamStatus, DecodeError> {
+        let status = self.decoder.push(chunk).map_err(DecodeError::from_jpeg)?;
+        Ok(status.into())
+    }
+
+    /// Mark the input as finished and return the decoded image
+    ///
+    /// A truncated jpeg is decoded the way [`decode_jpeg`] decodes it.
+    pub fn finish(&mut self) -> Result<DecodedImage, DecodeError> {
+        self.decoder.finish().map_err(DecodeError::from_jpeg)?;
+        self.image()?.ok_or_else(missing_jpeg_dimensions)
+    }
+
+    /// Number of rows of the stored image decoded so far, counted from its top
+    pub fn rows_decoded(&self) -> u32 {
+        self.decoder.rows_decoded() as u32
+    }
+
+    /// The image decoded so far, rows that haven't been decoded yet are zero
+    ///
+    /// Returns `None` until the headers have arrived. The EXIF orientation is
+    /// applied when the options ask for it like [`decode_jpeg`] does, so for rotated images the decoded rows
+    /// of the stored image show up as columns.
+    pub fn image(&self) -> Result<Option<DecodedImage>, DecodeError> {
+        let decoder = self.decoder.decoder();
+        let (Some((width, height)), Some(decoded)) =
+            (decoder.output_dimensions(), decoder.get_output_colorspace())
+        else {
+            return Ok(None);
+        };
+        let colorspace = self.options.jpeg_get_out_colorspace();
+        let mut data = self.decoder.pixels().to_vec();
+        expand_luma(&mut data, decoded, colorspace, u8::MAX)?;
+        let image = DecodedImage {
+            data,
+            width: width as u32,
+            height: height as u32,
+            colorspace,
+        };
+        // zune-jpeg leaves both to the end when decoding incrementally
+        let orientation = decoder
+            .orientation()
+            .filter(|_| self.options.get_apply_orientation());
+        orient(image, orientation, self.options.get_flip_vertically()).map(Some)
+    }
+}
+
+/// Turn `image`, stored with the EXIF `orientation`, upright and flip it
+/// upside down if `flip` is set
+///
+/// # Errors
+/// The pixels of `image` don't fill its dimensions.
+fn orient(
+    image: DecodedImage,
+    orientation: Option<u16>,
+    flip: bool,
+) -> Result<DecodedImage, DecodeError> {
+    let orientation = orientation.and_then(|orientation| Orientation::from_exif(orientation as u8));
+    if orientation.is_none() && !flip {
+        return Ok(image);
+    }
+    image.check_len()?;
+    let DecodedImage {
+        data,
+        width,
+        height,
+        colorspace,
+    } = image;
+    // only the number of channels matters for moving pixels around
+    let mut pixels = match colorspace.num_components() {
+        1 => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8),
+        3 => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8),
+        _ => ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8),
+    }
+    .ok_or_else(|| DecodeError::invalid_argument("Pixels don't fill the image".to_string()))?;
+    if let Some(orientation) = orientation {
+        pixels.apply_orientation(orientation);
+    }
+    if flip {
+        pixels.apply_orientation(Orientation::FlipVertical);
+    }
+    Ok(DecodedImage {
+        width: pixels.width(),
+        height: pixels.height(),
+        data: pixels.into_bytes(),
+        colorspace,
+    })
+}
+
+/// `image` crate limits equivalent to the zune limits in `options`
+fn image_limits(options: &DecoderOptions) -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(options.get_max_width().try_into().unwrap_or(u32::MAX));
+    limits.max_image_height = Some(options.get_max_height().try_into().unwrap_or(u32::MAX));
+    limits.max_alloc = Some(options.get_max_memory() as u64);
+    limits
+}
+
+/// Convert pixels decoded by the `image` crate to `colorspace`
+fn convert_generic(decoded: DynamicImage, colorspace: ColorSpace) -> Vec<u8> {
+    let swap_red_blue = |mut data: Vec<u8>, channels: usize| {
+        data.chunks_exact_mut(channels)
+            .for_each(|pixel| pixel.swap(0, 2));
+        data
+    };
+    match colorspace {
+        ColorSpace::RGB => decoded.into_rgb8().into_raw(),
+        ColorSpace::BGRA => swap_red_blue(decoded.into_rgba8().into_raw(), 4),
+        ColorSpace::BGR => swap_red_blue(decoded.into_rgb8().into_raw(), 3),
+        ColorSpace::Luma => decoded.into_luma8().into_raw(),
+        _ => decoded.into_rgba8().into_raw(),
+    }
+}
+
+/// Decode an image of any format supported by the `image` crate, turned upright and
+/// scaled as `options` say
+fn decode_generic_dynamic(
+    encoded: &[u8],
+    options: &DecoderOptions,
+) -> Result<DynamicImage, DecodeError> {
+    check_options(options)?;
+    let mut reader = guess_format(encoded)?;
+    reader.limits(image_limits(options));
+    let format = reader.format();
+    let to_decode_error = |err| DecodeError::from_image(err, format);
+    let mut decoder = reader.into_decoder().map_err(to_decode_error)?;
+    // the `image` crate has no pixel limit and not all of its decoders check the
+    // allocation limit, check both before allocating the image
+    let (width, height) = decoder.dimensions();
+    if u64::from(width) * u64::from(height) > options.get_max_pixels() as u64 {
+        return Err(to_decode_error(ImageError::Limits(LimitError::from_kind(
+            LimitErrorKind::DimensionError,
+        ))));
+    }
+    // the decoded image, and its copy converted to the output colorspace
+    let output_size = u64::from(width)
+        * u64::from(height)
+        * options.jpeg_get_out_colorspace().num_components() as u64;
+    image_limits(options)
+        .reserve(decoder.total_bytes().saturating_add(output_size))
+        .map_err(to_decode_error)?;
+    let orientation = decoder.orientation().map_err(to_decode_error)?;
+    let mut decoded = DynamicImage::from_decoder(decoder).map_err(to_decode_error)?;
+    if options.get_apply_orientation() {
+        decoded.apply_orientation(orientation);
+    }
+
+    // other formats have no cheap way to decode at a lower scale, so resize to
+    // the size zune-jpeg would decode at
+    let denominator = options.jpeg_get_scale_denominator() as u32;
+    if denominator > 1 {
+        decoded = decoded.resize_exact(
+            decoded.width().div_ceil(denominator),
+            decoded.height().div_ceil(denominator),
+            FilterType::Triangle,
+        );
+    }
+    Ok(decoded)
+}
+
+/// Write `decoded` in the output colorspace of `options` into the rows of `out`,
+/// which start `stride` bytes apart, flipped and premultiplied as `options` say
+///
+/// 8 bit images are converted row by row as they are written, only other ones are
+/// converted to a copy first, like color images written as Luma, which the `image`
+/// crate weighs to gray.
+fn write_generic_rows(
+    decoded: DynamicImage,
+    options: &DecoderOptions,
+    out: &mut [u8],
+    stride: usize,
+) {
+    let colorspace = options.jpeg_get_out_colorspace();
+    let channels = colorspace.num_components();
+    let (width, height) = (decoded.width() as usize, decoded.height() as usize);
+    let source_channels = match decoded {
+        DynamicImage::ImageLuma8(_) => Some(1),
+        DynamicImage::ImageLumaA8(_) => Some(2),
+        DynamicImage::ImageRgb8(_) if colorspace != ColorSpace::Luma => Some(3),
+        DynamicImage::ImageRgba8(_) if colorspace != ColorSpace::Luma => Some(4),
+        _ => None,
+    };
+    // positions in an RGBA pixel of the samples of an output pixel
+    let order: &[usize] = match colorspace {
+        ColorSpace::RGB => &[0, 1, 2],
+        ColorSpace::BGRA => &[2, 1, 0, 3],
+        ColorSpace::BGR => &[2, 1, 0],
+        ColorSpace::Luma => &[0],
+        _ => &[0, 1, 2, 3],
+    };
+    let converted;
+    let samples = match source_channels {
+        Some(_) => decoded.as_bytes(),
+        None => {
+            converted = convert_generic(decoded, colorspace);
+            &converted[..]
+        }
+    };
+    let source_row_len = width * source_channels.unwrap_or(channels);
+    for (y, row) in samples.chunks_exact(source_row_len).enumerate() {
+        let y = if options.get_flip_vertically() {
+            height - 1 - y
+        } else {
+            y
+        };
+        let out_row = &mut out[y * stride..][..width * channels];
+        match source_channels {
+            Some(source_channels) => {
+                for (pixel, out_pixel) in row
+                    .chunks_exact(source_channels)
+                    .zip(out_row.chunks_exact_mut(channels))
+                {
+                    let rgba = match *pixel {
+                        [luma] => [luma, luma, luma, u8::MAX],
+                        [luma, alpha] => [luma, luma, luma, alpha],
+                        [red, green, blue] => [red, green, blue, u8::MAX],
+                        [red, green, blue, alpha] => [red, green, blue, alpha],
+                        _ => unreachable!("pixels have 1 to 4 samples"),
+                    };
+                    for (sample, &position) in out_pixel.iter_mut().zip(order) {
+                        *sample = rgba[position];
+                    }
+                }
+            }
+            None => out_row.copy_from_slice(row),
+        }
+        if options.get_premultiply_alpha() {
+            premultiply_pixels(out_row, colorspace);
+        }
+    }
+}
+
+/// Decode an image of any format supported by the `image` crate
+pub(crate) fn decode_generic(
+    encoded: &[u8],
+    options: &DecoderOptions,
+) -> Result<DecodedImage, DecodeError> {
+    let decoded = decode_generic_dynamic(encoded, options)?;
+    let colorspace = options.jpeg_get_out_colorspace();
+    let mut image = DecodedImage {
+        width: decoded.width(),
+        height: decoded.height(),
+        data: convert_generic(decoded, colorspace),
+        colorspace,
+    };
+    if options.get_premultiply_alpha() {
+        premultiply_alpha(&mut image);
+    }
+    if options.get_flip_vertically() {
+        flip_vertically(&mut image);
+    }
+    Ok(image)
+}
+
+/// Decode an image of any format supported by the `image` crate into `f32` samples
+///
+/// 16 bit and float images keep their precision.
+fn decode_generic_f32(encoded: &[u8], options: &DecoderOptions) -> Result<FloatImage, DecodeError> {
+    let decoded = decode_generic_dynamic(encoded, options)?;
+    let swap_red_blue = |mut data: Vec<f32>, channels: usize| {
+        data.chunks_exact_mut(channels)
+            .for_each(|pixel| pixel.swap(0, 2));
+        data
+    };
+    let colorspace = options.jpeg_get_out_colorspace();
+    let (width, height) = (decoded.width(), decoded.height());
+    let mut data = match colorspace {
+        ColorSpace::RGB => decoded.into_rgb32f().into_raw(),
+        ColorSpace::BGRA => swap_red_blue(decoded.into_rgba32f().into_raw(), 4),
+        ColorSpace::BGR => swap_red_blue(decoded.into_rgb32f().into_raw(), 3),
+        // the image crate has no float grayscale, 16 bits keep the precision
+        ColorSpace::Luma => decoded
+            .into_luma16()
+            .into_raw()
+            .into_iter()
+            .map(|value| f32::from(value) / f32::from(u16::MAX))
+            .collect(),
+        _ => decoded.into_rgba32f().into_raw(),
+    };
+    let channels = colorspace.num_components();
+    if let Some(alpha_position) = colorspace
+        .alpha_position()
+        .filter(|_| options.get_premultiply_alpha())
+    {
+        for pixel in data.chunks_exact_mut(channels) {
+            let alpha = pixel[alpha_position];
+            for (position, value) in pixel.iter_mut().enumerate() {
+                if position != alpha_position {
+                    *value *= alpha;
+                }
+            }
+        }
+    }
+    if options.get_flip_vertically() {
+        flip_rows(&mut data, width as usize * channels);
+    }
+    Ok(FloatImage {
+        data,
+        width,
+        height,
+        colorspace,
+    })
+}
+
+/// Multiply the color channels of every pixel of `image` by its alpha, like
+/// zune-jpeg does with `DecoderOptions::set_premultiply_alpha`
+fn premultiply_alpha(image: &mut DecodedImage) {
+    premultiply_pixels(&mut image.data, image.colorspace);
+}
+
+/// [`premultiply_alpha`] for the pixels in `data`, laid out as `colorspace` says
+fn premultiply_pixels(data: &mut [u8], colorspace: ColorSpace) {
+    let Some(alpha_position) = colorspace.alpha_position() else {
+        return;
+    };
+    for pixel in data.chunks_exact_mut(colorspace.num_components()) {
+        let alpha = u16::from(pixel[alpha_position]);
+        for (position, value) in pixel.iter_mut().enumerate() {
+            if position != alpha_position {
+                *value = ((u16::from(*value) * alpha + 127) / 255) as u8;
+            }
+        }
+    }
+}
+
+/// Reverse the order of the rows of `image`, like zune-jpeg does with
+/// `DecoderOptions::set_flip_vertically`
+pub(crate) fn flip_vertically(image: &mut DecodedImage) {
+    let row_len = image.width as usize * image.channels();
+    flip_rows(&mut image.data, row_len);
+}
+
+/// Reverse the order of the `row_len` long rows of `data`
+fn flip_rows<T>(data: &mut [T], row_len: usize) {
+    let rows = data.len() / row_len;
+    for top in 0..rows / 2 {
+        let (upper, lower) = data.split_at_mut((rows - 1 - top) * row_len);
+        upper[top * row_len..(top + 1) * row_len].swap_with_slice(&mut lower[..row_len]);
+    }
+}
+fn guess_format(encoded: &[u8]) -> Result<ImageReader<Cursor<&[u8]>>, DecodeError> {
+    ImageReader::new(Cursor::new(encoded))
+        .with_guessed_format()
+        .map_err(|err| DecodeError::from_image(ImageError::IoError(err), None))
+}
+pub(crate) fn is_jpeg(encoded: &[u8]) -> bool {
+    encoded.len() > 2 && encoded[0] == 0xff && encoded[1] == 0xD8
+}
+
+/// Decode an image, retrying the zune-jpeg failures selected by `fallback` with
+/// the `image` crate
+///
+/// If the fallback fails too, the zune-jpeg error is returned.
+pub fn decode_with_fallback(
+    encoded: &[u8],
+    use_fast_jpeg: bool,
+    fallback: JpegFallback,
+    options: &DecoderOptions,
+) -> Result<DecodeAttempt, DecodeError> {
+    let mut fast_path_error = None;
+    if use_fast_jpeg && is_jpeg(encoded) {
+        match decode_jpeg(encoded, options) {
+            Ok(image) => {
+                return Ok(DecodeAttempt {
+                    image,
+                    decoder: FAST_JPEG_DECODER,
+                    fast_path_error: None,
+                })
+            }
+            Err(err) if fallback.retries(&err) => fast_path_error = Some(err),
+            Err(err) => return Err(err),
+        }
+    }
+    match decode_generic(encoded, options) {
+        Ok(image) => Ok(DecodeAttempt {
+            image,
+            decoder: GENERIC_DECODER,
+            fast_path_error,
+        }),
+        // the fallback was only a second attempt, report why the fast path failed
+        Err(err) => Err(fast_path_error.unwrap_or(err)),
+    }
+}
+
+/// Decode an image, using zune-jpeg for jpegs if `use_fast_jpeg` is set and the
+/// `image` crate for everything else
+pub fn decode(
+    encoded: &[u8],
+    use_fast_jpeg: bool,
+    options: &DecoderOptions,
+) -> Result<DecodedImage, DecodeError> {
+    decode_with_fallback(encoded, use_fast_jpeg, JpegFallback::default(), options)
+        .map(|attempt| attempt.image)
+}
+
+/// Largest scale denominator a jpeg can be decoded at by zune-jpeg while still
+/// being at least as large as `resize` needs, the one of `options` otherwise
+///
+/// Decoding at a reduced scale skips most of the work of decoding large images
+/// into small thumbnails, and leaves the resize less to shrink.
+fn prescale_denominator(
+    encoded: &[u8],
+    use_fast_jpeg: bool,
+    options: &DecoderOptions,
+    resize: &ResizeOptions,
+) -> usize {
+    let denominator = options.jpeg_get_scale_denominator();
+    if !use_fast_jpeg || !is_jpeg(encoded) {
+        return denominator;
+    }
+    let mut decoder = JpegDecoder::new_with_options(encoded, *options);
+    if decoder.decode_headers().is_err() {
+        return denominator;
+    }
+    let Some((width, height)) = decoder.output_dimensions() else {
+        return denominator;
+    };
+    let (out_width, out_height) = resize.output_size(width as u32, height as u32);
+    // sizes round up at every scale, so scaling further by `extra` rounds the same
+    SCALE_DENOMINATORS
+        .into_iter()
+        .filter(|&scale| scale % denominator == 0)
+        .filter(|&scale| {
+            let extra = scale / denominator;
+            width.div_ceil(extra) >= out_width as usize
+                && height.div_ceil(extra) >= out_height as usize
+        })
+        .max()
+        .unwrap_or(denominator)
+}
+
+/// Decode an image like [`decode`] does and resize it, see [`resize`]
+///
+/// Jpegs decoded with zune-jpeg are decoded at the smallest scale that is still
+/// at least the size they are resized to. Alpha is premultiplied and rows are
+/// flipped once the image is resized, as the resize filters expect straight alpha.
+pub fn decode_resized(
+    encoded: &[u8],
+    use_fast_jpeg: bool,
+    options: &DecoderOptions,
+    resize: &ResizeOptions,
+) -> Result<DecodedImage, DecodeError> {
+    let decode_options = options
+        .set_premultiply_alpha(false)
+        .set_flip_vertically(false)
+        .jpeg_set_scale_denominator(prescale_denominator(
+            encoded,
+            use_fast_jpeg,
+            options,
+            resize,
+        ));
+    let mut image = resize::resize(&decode(encoded, use_fast_jpeg, &decode_options)?, resize)?;
+    if options.get_premultiply_alpha() {
+        premultiply_alpha(&mut image);
+    }
+    if options.get_flip_vertically() {
+        flip_vertically(&mut image);
+    }
+    Ok(image)
+}
+
+/// Decode an image into `f32` samples like [`decode`] does, see [`decode_jpeg_f32`]
+pub fn decode_f32(
+    encoded: &[u8],
+    use_fast_jpeg: bool,
+    options: &DecoderOptions,
+) -> Result<FloatImage, DecodeError> {
+    let mut fast_path_error = None;
+    if use_fast_jpeg && is_jpeg(encoded) {
+        match decode_jpeg_f32(encoded, options) {
+            Ok(image) => return Ok(image),
+            Err(err) if JpegFallback::default().retries(&err) => fast_path_error = Some(err),
+            Err(err) => return Err(err),
+        }
+    }
+    decode_generic_f32(encoded, options).map_err(|err| fast_path_error.unwrap_or(err))
+}
+
+/// Decode several images, see [`decode`]
+pub fn decode_many<T: AsRef<[u8]>>(
+    encoded: &[T],
+    use_fast_jpeg: bool,
+    options: &DecoderOptions,
+) -> Result<Vec<DecodedImage>, DecodeError> {
+    encoded
+        .iter()
+        .map(|encoded| decode(encoded.as_ref(), use_fast_jpeg, options))
+        .collect()
+}
+
+fn probe_jpeg(encoded: &[u8]) -> Result<ImageProbe, DecodeError> {
+    let mut decoder = JpegDecoder::new(encoded);
+    decoder.decode_headers().map_err(DecodeError::from_jpeg)?;
+    let info = decoder.info().ok_or_else(missing_jpeg_dimensions)?;
+    let colorspace = decoder
+        .get_input_colorspace()
+        .ok_or_else(missing_jpeg_dimensions)?;
+    Ok(ImageProbe {
+        width: info.width as u32,
+        height: info.height as u32,
+        format: "jpeg".to_string(),
+        colorspace: format!("{colorspace:?}"),
+        sof: Some(format!("{:?}", info.sof)),
+        components: info.components,
+        progressive: Some(info.sof.is_progressive()),
+    })
+}
+
+/// Map an `image` crate color type onto the equivalent zune colorspace
+fn color_type_to_colorspace(color_type: ColorType) -> ColorSpace {
+    match color_type {
+        ColorType::L8 | ColorType::L16 => ColorSpace::Luma,
+        ColorType::La8 | ColorType::La16 => ColorSpace::LumaA,
+        ColorType::Rgb8 | ColorType::Rgb16 | ColorType::Rgb32F => ColorSpace::RGB,
+        ColorType::Rgba8 | ColorType::Rgba16 | ColorType::Rgba32F => ColorSpace::RGBA,
+        _ => ColorSpace::Unknown,
+    }
+}
+
+fn probe_generic(encoded: &[u8]) -> Result<ImageProbe, DecodeError> {
+    let reader = guess_format(encoded)?;
+    let format = reader.format().ok_or_else(|| {
+        DecodeError::new(
+            "Unsupported.Format",
+            UNKNOWN_FORMAT,
+            "Could not detect the image format".to_string(),
+        )
+    })?;
+    let decoder = reader
+        .into_decoder()
+        .map_err(|err| DecodeError::from_image(err, Some(format)))?;
+    let (width, height) = decoder.dimensions();
+    let color_type = decoder.color_type();
+    Ok(ImageProbe {
+        width,
+        height,
+        format: format!("{format:?}").to_lowercase(),
+        colorspace: format!("{:?}", color_type_to_colorspace(color_type)),
+        sof: None,
+        components: color_type.channel_count(),
+        progressive: None,
+    })
+}
+
+/// Read the dimensions and format of an image without decoding its pixels
+pub fn probe(encoded: &[u8]) -> Result<ImageProbe, DecodeError> {
+    if is_jpeg(encoded) {
+        probe_jpeg(encoded)
+    } else {
+        probe_generic(encoded)
+    }
+}
+
+fn read_jpeg_metadata(encoded: &[u8]) -> Result<ImageMetadata, DecodeError> {
+    let mut decoder = JpegDecoder::new(encoded);
+    decoder.decode_headers().map_err(DecodeError::from_jpeg)?;
+    let info = decoder.info().ok_or_else(missing_jpeg_dimensions)?;
+    // JFIF requires non zero densities, so zero means there was no JFIF segment
+    let has_jfif = info.x_density != 0 && info.y_density != 0;
+    Ok(ImageMetadata {
+        format: "jpeg".to_string(),
+        icc_profile: decoder.icc_profile(),
+        exif: decoder.exif().cloned(),
+        density_units: has_jfif.then_some(info.density_units),
+        x_density: has_jfif.then_some(info.x_density),
+        y_density: has_jfif.then_some(info.y_density),
+        is_mjpeg: decoder.is_mjpeg(),
+        adobe_transform: decoder.adobe_transform(),
+    })
+}
+
+fn read_generic_metadata(encoded: &[u8]) -> Result<ImageMetadata, DecodeError> {
+    let reader = guess_format(encoded)?;
+    let format = reader.format();
+    let to_decode_error = |err| DecodeError::from_image(err, format);
+    let mut decoder = reader.into_decoder().map_err(to_decode_error)?;
+    Ok(ImageMetadata {
+        format: format.map_or(UNKNOWN_FORMAT.to_string(), |format| {
+            format!("{format:?}").to_lowercase()
+        }),
+        icc_profile: decoder.icc_profile().map_err(to_decode_error)?,
+        exif: decoder.exif_metadata().map_err(to_decode_error)?,
+        ..ImageMetadata::default()
+    })
+}
+
+/// Read the ICC profile, EXIF data and other metadata of an image without
+/// decoding its pixels
+pub fn read_metadata(encoded: &[u8]) -> Result<ImageMetadata, DecodeError> {
+    if is_jpeg(encoded) {
+        read_jpeg_metadata(encoded)
+    } else {
+        read_generic_metadata(encoded)
+    }
+}
+
+/// How [`pack`] and [`decode_pack`] lay out the cells of a grid
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PackOptions {
+    /// Pixels left around each image, so filtering at the edge of an image doesn't
+    /// sample its neighbours
+    pub gutter: u32,
+    /// Fill gutters with the edge pixels of their image instead of `background`
+    pub extrude: bool,
+    /// RGBA color of the pixels no image covers, converted to the colorspace of the
+    /// grid and written as given, so premultiplied for premultiplied output
+    pub background: [u8; 4],
+    /// Multiple of pixels the left and top edges of images are placed on, e.g. 4
+    /// for block compressed textures
+    pub align: u32,
+    /// Width and height of the cells of the grid, images of any size are resized
+    /// into them as `tile_fit` says. Without it images are placed as they are
+    pub tile_size: Option<(u32, u32)>,
+    /// How images are resized into `tile_size`, letterboxed and centered for
+    /// [`ResizeFit::Fit`]
+    pub tile_fit: ResizeFit,
+}
+
+impl Default for PackOptions {
+    /// Images edge to edge on transparent black
+    fn default() -> Self {
+        PackOptions {
+            gutter: 0,
+            extrude: false,
+            background: [0; 4],
+            align: 1,
+            tile_size: None,
+            tile_fit: ResizeFit::default(),
+        }
+    }
+}
+
+impl PackOptions {
+    /// How images are resized into tiles, if the grid has a tile size
+    fn tile_resize(&self) -> Result<Option<ResizeOptions>, DecodeError> {
+        let Some((width, height)) = self.tile_size else {
+            return Ok(None);
+        };
+        if width == 0 || height == 0 {
+            return Err(DecodeError::invalid_argument(format!(
+                "Pack option `tile_size` must not be empty, got {width}x{height}"
+            )));
+        }
+        Ok(Some(ResizeOptions {
+            width,
+            height,
+            fit: self.tile_fit,
+            filter: ResizeFilter::default(),
+            linear_light: false,
+        }))
+    }
+}
+
+/// Where the images of a grid go in the packed image
+struct GridLayout {
+    width: usize,
+    height: usize,
+    /// Top left pixel of each image
+    origins: Vec<(usize, usize)>,
+}
+
+/// Lay out images of `sizes` row by row into a grid with `num_imgs_col` columns
+///
+/// All images must have the size of the first one, except for the last column,
+/// which may be narrower, and the last row, which may be shorter. Cells hold an
+/// image and its gutters, and are widened so images start on multiples of the
+/// alignment.
+fn grid_layout(
+    sizes: &[(usize, usize)],
+    num_imgs_col: usize,
+    options: &PackOptions,
+) -> Result<GridLayout, DecodeError> {
+    let num_imgs = sizes.len();
+    let (Some(&(main_width, main_height)), Some(&(last_width, last_height))) =
+        (sizes.first(), sizes.last())
+    else {
+        return Err(DecodeError::invalid_argument(
+            "pack needs at least one image".to_string(),
+        ));
+    };
+    if num_imgs_col == 0 || !num_imgs.is_multiple_of(num_imgs_col) {
+        return Err(DecodeError::invalid_argument(format!("Invalid num_imgs_col argument value: {num_imgs_col:?}, not divisible by num_imgs value {num_imgs:?}")));
+    }
+    if options.align == 0 {
+        return Err(DecodeError::invalid_argument(
+            "Pack option `align` must be at least 1".to_string(),
+        ));
+    }
+    let num_rows = num_imgs / num_imgs_col;
+    let (gutter, align) = (options.gutter as usize, options.align as usize);
+    let first = gutter.next_multiple_of(align);
+    let (pitch_x, pitch_y) = (
+        (main_width + 2 * gutter).next_multiple_of(align),
+        (main_height + 2 * gutter).next_multiple_of(align),
+    );
+    let mut origins = Vec::with_capacity(num_imgs);
+    for (index, &(img_width, img_height)) in sizes.iter().enumerate() {
+        let (xidx, yidx) = (index % num_imgs_col, index / num_imgs_col);
+        if (xidx == num_imgs_col - 1 && img_width != last_width)
+            || (yidx == num_rows - 1 && img_height != last_height)
+            || (xidx != num_imgs_col - 1 && img_width != main_width)
+            || (yidx != num_rows - 1 && img_height != main_height)
+        {
+            return Err(DecodeError::new("InvalidGrid", UNKNOWN_FORMAT, format!("Invalid grid. Images of inconsistent size. num_rows:{num_rows:?}, num_imgs_col:{num_imgs_col:?} xidx: {xidx:?}, yidx: {yidx:?}, img_width: {img_width:?}, img_height: {img_height:?}, main_width: {main_width:?}, main_height: {main_height:?}, last_width: {last_width:?}, last_height: {last_height:?}")));
+        }
+        origins.push((first + xidx * pitch_x, first + yidx * pitch_y));
+    }
+    Ok(GridLayout {
+        width: first + pitch_x * (num_imgs_col - 1) + last_width + gutter,
+        height: first + pitch_y * (num_rows - 1) + last_height + gutter,
+        origins,
+    })
+}
+
+/// `background` as a pixel of `colorspace`
+fn background_pixel(background: [u8; 4], colorspace: ColorSpace) -> Vec<u8> {
+    let [r, g, b, a] = background;
+    match colorspace {
+        ColorSpace::RGB => vec![r, g, b],
+        ColorSpace::BGR => vec![b, g, r],
+        ColorSpace::BGRA => vec![b, g, r, a],
+        ColorSpace::Luma => {
+            // BT.601 luma, as zune-jpeg converts to grayscale
+            let luma = (299 * u32::from(r) + 587 * u32::from(g) + 114 * u32::from(b) + 500) / 1000;
+            vec![luma as u8]
+        }
+        _ => background.to_vec(),
+    }
+}
+
+/// An empty grid of `grid`, filled with the background of `options`
+fn blank_grid(grid: &GridLayout, colorspace: ColorSpace, options: &PackOptions) -> DecodedImage {
+    let pixel = background_pixel(options.background, colorspace);
+    let mut data = vec![0; grid.width * grid.height * pixel.len()];
+    if pixel.iter().any(|&sample| sample != 0) {
+        for out in data.chunks_exact_mut(pixel.len()) {
+            out.copy_from_slice(&pixel);
+        }
+    }
+    DecodedImage {
+        data,
+        width: grid.width as u32,
+        height: grid.height as u32,
+        colorspace,
+    }
+}
+
+/// Fill the `gutter` pixels around the `size` image at `origin` in `grid` with
+/// copies of its edge pixels
+fn extrude_edges(
+    grid: &mut DecodedImage,
+    origin: (usize, usize),
+    size: (usize, usize),
+    gutter: usize,
+) {
+    let ((x, y), (width, height)) = (origin, size);
+    if gutter == 0 || width == 0 || height == 0 {
+        return;
+    }
+    let channels = grid.channels();
+    let stride = grid.width as usize * channels;
+    for row in grid.data.chunks_exact_mut(stride).skip(y).take(height) {
+        let (left, right) = (x * channels, (x + width - 1) * channels);
+        for column in 1..=gutter {
+            row.copy_within(left..left + channels, left - column * channels);
+            row.copy_within(right..right + channels, right + column * channels);
+        }
+    }
+    // rows of the image with their side gutters
+    let span = (x - gutter) * channels..(x + width + gutter) * channels;
+    for row in 1..=gutter {
+        let top = y * stride;
+        grid.data.copy_within(
+            top + span.start..top + span.end,
+            top - row * stride + span.start,
+        );
+        let bottom = (y + height - 1) * stride;
+        grid.data.copy_within(
+            bottom + span.start..bottom + span.end,
+            bottom + row * stride + span.start,
+        );
+    }
+}
+
+/// Error out if the images to pack don't all have the colorspace of the first one
+pub(crate) fn check_same_colorspace(
+    colorspaces: impl IntoIterator<Item = ColorSpace>,
+) -> Result<(), DecodeError> {
+    let mut colorspaces = colorspaces.into_iter();
+    let Some(first) = colorspaces.next() else {
+        return Ok(());
+    };
+    match colorspaces.find(|colorspace| *colorspace != first) {
+        Some(other) => Err(DecodeError::invalid_argument(format!(
+            "Images of different colorspaces can't be packed together, {first:?} and {other:?}"
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Copy the rows of `image` into `out`, whose rows start `stride` bytes apart
+pub(crate) fn copy_rows(image: &DecodedImage, out: &mut [u8], stride: usize) {
+    let row_len = image.width as usize * image.channels();
+    for (row, out_row) in image.data.chunks_exact(row_len).zip(out.chunks_mut(stride)) {
+        out_row[..row_len].copy_from_slice(row);
+    }
+}
+
+/// Copy `image` centered into the `cell` sized cell at `origin` of `grid`,
+/// extruding its edges into the gutter if `options` say so
+fn place_in_cell(
+    grid: &mut DecodedImage,
+    image: &DecodedImage,
+    origin: (usize, usize),
+    cell: (usize, usize),
+    options: &PackOptions,
+) {
+    let size = (image.width as usize, image.height as usize);
+    let x = origin.0 + (cell.0 - size.0) / 2;
+    let y = origin.1 + (cell.1 - size.1) / 2;
+    let channels = grid.channels();
+    let stride = grid.width as usize * channels;
+    copy_rows(image, &mut grid.data[y * stride + x * channels..], stride);
+    if options.extrude {
+        extrude_edges(grid, (x, y), size, options.gutter as usize);
+    }
+}
+
+/// [`pack`] for images already resized into the tiles of `options`, if it has any
+fn pack_cells(
+    images: &[DecodedImage],
+    num_imgs_col: usize,
+    options: &PackOptions,
+) -> Result<DecodedImage, DecodeError> {
+    let sizes: Vec<_> = match options.tile_size {
+        Some((width, height)) => vec![(width as usize, height as usize); images.len()],
+        None => images
+            .iter()
+            .map(|img| (img.width as usize, img.height as usize))
+            .collect(),
+    };
+    let grid = grid_layout(&sizes, num_imgs_col, options)?;
+    check_same_colorspace(images.iter().map(|img| img.colorspace))?;
+    images.iter().try_for_each(DecodedImage::check_len)?;
+    let mut combined_img = blank_grid(&grid, images[0].colorspace, options);
+    for ((img, origin), &cell) in images.iter().zip(grid.origins).zip(&sizes) {
+        place_in_cell(&mut combined_img, img, origin, cell, options);
+    }
+    Ok(combined_img)
+}
+
+/// Pack images row by row into a grid with `num_imgs_col` columns
+///
+/// All images must have the size of the first one, except for the last column,
+/// which may be narrower, and the last row, which may be shorter, so that tiles
+/// cut from the edges of a larger image can be put back together. With a tile
+/// size, images of any size are resized into their tiles instead, which expects
+/// straight alpha. All images must share the same colorspace, which is the one of
+/// the grid, and their pixels must fill their dimensions.
+pub fn pack(
+    images: &[DecodedImage],
+    num_imgs_col: usize,
+    options: &PackOptions,
+) -> Result<DecodedImage, DecodeError> {
+    match options.tile_resize()? {
+        Some(resize) => {
+            let resized = images
+                .iter()
+                .map(|img| resize::resize(img, &resize))
+                .collect::<Result<Vec<_>, _>>()?;
+            pack_cells(&resized, num_imgs_col, options)
+        }
+        None => pack_cells(images, num_imgs_col, options),
+    }
+}
+
+/// Decoder of a [`PendingImage`]
+enum PendingDecoder<'a> {
+    Jpeg(Box<JpegDecoder<&'a [u8]>>),
+    Generic(&'a [u8]),
+}
+
+/// An image whose headers are read, to be decoded into a buffer the caller owns
+///
+/// The size of the image is known before anything is decoded, so the buffer can be
+/// allocated, or a place picked for the image in a larger surface, first.
+pub struct PendingImage<'a> {
+    decoder: PendingDecoder<'a>,
+    options: DecoderOptions,
+    size: (usize, usize),
+    colorspace: ColorSpace,
+}
+
+impl<'a> PendingImage<'a> {
+    /// Read the headers of `encoded`, using zune-jpeg for jpegs if `use_fast_jpeg` is
+    /// set and falling back to the `image` crate like [`decode`] does
+    pub fn read(
+        encoded: &'a [u8],
+        use_fast_jpeg: bool,
+        options: &DecoderOptions,
+    ) -> Result<PendingImage<'a>, DecodeError> {
+        check_options(options)?;
+        if use_fast_jpeg && is_jpeg(encoded) {
+            let mut decoder = JpegDecoder::new_with_options(encoded, *options);
+            match decoder.decode_headers().map_err(DecodeError::from_jpeg) {
+                Ok(()) => {
+                    let size = decoder
+                        .output_dimensions()
+                        .ok_or_else(missing_jpeg_dimensions)?;
+                    return Ok(PendingImage {
+                        decoder: PendingDecoder::Jpeg(Box::new(decoder)),
+                        options: *options,
+                        size,
+                        colorspace: options.jpeg_get_out_colorspace(),
+                    });
+                }
+                Err(err) if JpegFallback::default().retries(&err) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(PendingImage {
+            decoder: PendingDecoder::Generic(encoded),
+            options: *options,
+            size: generic_output_size(encoded, options)?,
+            colorspace: options.jpeg_get_out_colorspace(),
+        })
+    }
+
+    /// Width and height the image decodes to
+    pub const fn size(&self) -> (usize, usize) {
+        self.size
+    }
+
+    /// Colorspace the image decodes to
+    pub const fn colorspace(&self) -> ColorSpace {
+        self.colorspace
+    }
+
+    /// Number of bytes of a row of the decoded image
+    pub fn row_size(&self) -> usize {
+        self.size.0 * self.colorspace.num_components()
+    }
+
+    /// Number of bytes a buffer needs to hold the image decoded with `layout`, up to
+    /// the end of its last row
+    ///
+    /// # Errors
+    /// The stride of `layout` is smaller than a row, or the size overflows a usize.
+    pub fn buffer_size(&self, layout: OutputLayout) -> Result<usize, DecodeError> {
+        let row_size = self.row_size();
+        if layout.stride < row_size {
+            return Err(DecodeError::invalid_argument(format!(
+                "Stride {} is smaller than a row of {row_size} bytes",
+                layout.stride
+            )));
+        }
+        layout
+            .buffer_size(row_size, self.size.1)
+            .ok_or_else(|| DecodeError::invalid_argument("Output layout overflows".to_string()))
+    }
+
+    /// Decode the image into `out`, writing rows where `layout` says and leaving the
+    /// bytes around them untouched
+    ///
+    /// Jpegs decoded by zune-jpeg, grayscale ones included, are written straight
+    /// into `out`. The `image` crate has no way to decode into rows spread over a
+    /// larger buffer, so other images are decoded into a buffer of their own first
+    /// and converted to the output colorspace while they are copied into `out`.
+    ///
+    /// # Errors
+    /// `out` is smaller than [`buffer_size`](Self::buffer_size), or decoding fails.
+    pub fn decode_into(self, out: &mut [u8], layout: OutputLayout) -> Result<(), DecodeError> {
+        let expected = self.buffer_size(layout)?;
+        if out.len() < expected {
+            return Err(DecodeError::too_small_output(expected, out.len()));
+        }
+        let row_size = self.row_size();
+        let encoded = match self.decoder {
+            PendingDecoder::Jpeg(mut decoder) => {
+                let decoded = decoder
+                    .get_output_colorspace()
+                    .ok_or_else(missing_jpeg_dimensions)?;
+                if decoded != self.colorspace && decoded != ColorSpace::Luma {
+                    return Err(DecodeError::from_jpeg(DecodeErrors::Format(format!(
+                        "Decoded to {decoded:?} instead of the requested {:?}",
+                        self.colorspace
+                    ))));
+                }
+                // grayscale jpegs decode to Luma at the start of each row, spread over
+                // the whole row afterwards
+                match decoder
+                    .decode_into_layout(out, layout)
+                    .map_err(DecodeError::from_jpeg)
+                {
+                    Ok(()) => {
+                        if decoded != self.colorspace {
+                            for row in out[layout.offset..expected].chunks_mut(layout.stride) {
+                                spread_luma(&mut row[..row_size], self.colorspace, u8::MAX);
+                            }
+                        }
+                        return Ok(());
+                    }
+                    Err(err) if JpegFallback::default().retries(&err) => {
+                        // the size read from the headers is the one the `image` crate decodes to
+                        let encoded: &[u8] = decoder.reset(&[]);
+                        encoded
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            PendingDecoder::Generic(encoded) => encoded,
+        };
+        // the `image` crate decodes into a buffer of its own, converted while copied
+        let image = decode_generic_dynamic(encoded, &self.options)?;
+        let decoded_size = (image.width() as usize, image.height() as usize);
+        if decoded_size != self.size {
+            return Err(DecodeError::new(
+                "InvalidGrid",
+                UNKNOWN_FORMAT,
+                format!(
+                    "Image decoded to {decoded_size:?}, its headers say {:?}",
+                    self.size
+                ),
+            ));
+        }
+        write_generic_rows(
+            image,
+            &self.options,
+            &mut out[layout.offset..],
+            layout.stride,
+        );
+        Ok(())
+    }
+}
+
+/// Size an image of any format supported by the `image` crate decodes to, turned
+/// upright and scaled as `options` say, without decoding it
+fn generic_output_size(
+    encoded: &[u8],
+    options: &DecoderOptions,
+) -> Result<(usize, usize), DecodeError> {
+    check_options(options)?;
+    let mut reader = guess_format(encoded)?;
+    reader.limits(image_limits(options));
+    let format = reader.format();
+    let to_decode_error = |err| DecodeError::from_image(err, format);
+    let mut decoder = reader.into_decoder().map_err(to_decode_error)?;
+    let (mut width, mut height) = decoder.dimensions();
+    let orientation = decoder.orientation().map_err(to_decode_error)?;
+    if options.get_apply_orientation()
+        && matches!(
+            orientation,
+            Orientation::Rotate90
+                | Orientation::Rotate270
+                | Orientation::Rotate90FlipH
+                | Orientation::Rotate270FlipH
+        )
+    {
+        (width, height) = (height, width);
+    }
+    let denominator = options.jpeg_get_scale_denominator() as u32;
+    Ok((
+        width.div_ceil(denominator) as usize,
+        height.div_ceil(denominator) as usize,
+    ))
+}
+
+/// Decode images and pack them row by row into a grid with `num_imgs_col` columns,
+/// see [`pack`]
+///
+/// The headers of all images are read and the grid is checked before anything is
+/// decoded, then each image is decoded into its place in the grid, see
+/// [`PendingImage::decode_into`], so at most one image that isn't a jpeg is held
+/// next to the grid at a time. With a tile size, images are
+/// decoded and resized one by one with [`decode_resized`] instead, then placed.
+/// Rows are flipped as a whole if `options` flip them.
+pub fn decode_pack<T: AsRef<[u8]>>(
+    encoded: &[T],
+    num_imgs_col: usize,
+    use_fast_jpeg: bool,
+    options: &DecoderOptions,
+    pack_options: &PackOptions,
+) -> Result<DecodedImage, DecodeError> {
+    check_options(options)?;
+    // cells are placed top to bottom, so the grid is flipped as a whole
+    let cell_options = options.set_flip_vertically(false);
+    if let Some(resize) = pack_options.tile_resize()? {
+        let images = encoded
+            .iter()
+            .map(|encoded| decode_resized(encoded.as_ref(), use_fast_jpeg, &cell_options, &resize))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut image = pack_cells(&images, num_imgs_col, pack_options)?;
+        if options.get_flip_vertically() {
+            flip_vertically(&mut image);
+        }
+        return Ok(image);
+    }
+    let tiles = encoded
+        .iter()
+        .map(|encoded| PendingImage::read(encoded.as_ref(), use_fast_jpeg, &cell_options))
+        .collect::<Result<Vec<_>, _>>()?;
+    let sizes: Vec<_> = tiles.iter().map(PendingImage::size).collect();
+    let grid = grid_layout(&sizes, num_imgs_col, pack_options)?;
+    // tiles decode to the requested colorspace, grayscale jpegs included
+    let colorspace = options.jpeg_get_out_colorspace();
+    check_same_colorspace(
+        core::iter::once(colorspace).chain(tiles.iter().map(PendingImage::colorspace)),
+    )?;
+    let mut image = blank_grid(&grid, colorspace, pack_options);
+    let channels = image.channels();
+    let stride = grid.width * channels;
+    for ((tile, (x, y)), size) in tiles.into_iter().zip(grid.origins).zip(sizes) {
+        let offset = y * stride + x * channels;
+        tile.decode_into(&mut image.data, OutputLayout { offset, stride })?;
+        if pack_options.extrude {
+            extrude_edges(&mut image, (x, y), size, pack_options.gutter as usize);
+        }
+    }
+    if options.get_flip_vertically() {
+        flip_vertically(&mut image);
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_JPEG: &[u8] = include_bytes!("../test_img.jpeg");
+    /// 48x32 progressive jpeg with 4:2:0 chroma
+    const TEST_PROGRESSIVE_JPEG: &[u8] = include_bytes!("../test_img_progressive.jpeg");
+
+    fn solid(width: u32, height: u32, value: u8) -> DecodedImage {
+        DecodedImage {
+            data: vec![value; width as usize * height as usize * 4],
+            width,
+            height,
+            colorspace: ColorSpace::RGBA,
+        }
+    }
+
+    fn encode_png(image: &DecodedImage) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        image::RgbaImage::from_raw(image.width, image.height, image.data.clone())
+            .unwrap()
+            .write_to(&mut Cursor::new(&mut encoded), image::ImageFormat::Png)
+            .unwrap();
+        encoded
+    }
+
+    fn encode_jpeg(width: u32, height: u32) -> Vec<u8> {
+        let pixels = image::RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(x * 8) as u8, (y * 8) as u8, 128])
+        });
+        let mut encoded = Vec::new();
+        pixels
+            .write_to(&mut Cursor::new(&mut encoded), image::ImageFormat::Jpeg)
+            .unwrap();
+        encoded
+    }
+
+    fn encode_dynamic_png(image: DynamicImage) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut encoded), image::ImageFormat::Png)
+            .unwrap();
+        encoded
+    }
+
+    fn encode_gray_jpeg(width: u32, height: u32) -> Vec<u8> {
+        let pixels =
+            image::GrayImage::from_fn(width, height, |x, y| image::Luma([(x * 8 + y) as u8]));
+        let mut encoded = Vec::new();
+        pixels
+            .write_to(&mut Cursor::new(&mut encoded), image::ImageFormat::Jpeg)
+            .unwrap();
+        encoded
+    }
+
+    #[test]
+    fn default_options_match_their_docs() {
+        // the defaults `DecodeOptions` documents in options.rs
+        let options = default_options();
+        assert!(options.get_strict_mode());
+        assert_eq!(options.get_max_width(), 16384);
+        assert_eq!(options.get_max_height(), 16384);
+        assert_eq!(options.get_max_pixels(), 16384 * 16384);
+        assert_eq!(options.get_max_memory(), 2 << 30);
+        assert_eq!(options.jpeg_get_max_scans(), 100);
+        assert!(options.get_use_unsafe());
+        assert_eq!(options.jpeg_get_out_colorspace(), ColorSpace::RGBA);
+        assert_eq!(options.jpeg_get_scale_denominator(), 1);
+        assert!(!options.get_apply_orientation());
+        assert!(!options.get_flip_vertically());
+        assert!(!options.get_premultiply_alpha());
+    }
+
+    #[test]
+    fn decode_jpeg_fast_and_generic() {
+        for use_fast_jpeg in [true, false] {
+            let image = decode(TEST_JPEG, use_fast_jpeg, &default_options()).unwrap();
+            assert_eq!((image.width, image.height), (1536, 1536));
+            assert_eq!(image.data.len(), 1536 * 1536 * 4);
+        }
+        let attempt =
+            decode_with_fallback(TEST_JPEG, true, JpegFallback::Never, &default_options()).unwrap();
+        assert_eq!(attempt.decoder, FAST_JPEG_DECODER);
+        assert!(attempt.fast_path_error.is_none());
+    }
+
+    #[test]
+    fn decode_png_uses_generic_decoder() {
+        let png = encode_png(&solid(3, 2, 7));
+        let attempt =
+            decode_with_fallback(&png, true, JpegFallback::Always, &default_options()).unwrap();
+        assert_eq!(attempt.decoder, GENERIC_DECODER);
+        assert_eq!(attempt.image, solid(3, 2, 7));
+    }
+
+    #[test]
+    fn unsupported_jpeg_reports_fast_path_error() {
+        // SOI followed by a lossless SOF3 frame header for a 1x1 grayscale image
+        let lossless = [
+            0xFF, 0xD8, 0xFF, 0xC3, 0x00, 0x0B, 0x08, 0x00, 0x01, 0x00, 0x01, 0x01, 0x01, 0x11,
+            0x00,
+        ];
+        for fallback in [JpegFallback::Never, JpegFallback::Unsupported] {
+            let err =
+                decode_with_fallback(&lossless, true, fallback, &default_options()).unwrap_err();
+            assert_eq!(err.code, "Unsupported.LosslessHuffman", "{err}");
+            assert_eq!(err.format, "jpeg");
+        }
+    }
+
+    #[test]
+    fn probe_jpeg_header() {
+        let probe = probe(TEST_JPEG).unwrap();
+        assert_eq!((probe.width, probe.height), (1536, 1536));
+        assert_eq!(probe.format, "jpeg");
+        assert_eq!(probe.components, 3);
+        assert_eq!(probe.progressive, Some(false));
+        assert_eq!(
+            probe_jpeg(TEST_PROGRESSIVE_JPEG).unwrap().progressive,
+            Some(true)
+        );
+
+        let png = probe_generic(&encode_png(&solid(3, 2, 0))).unwrap();
+        assert_eq!((png.width, png.height), (3, 2));
+        assert_eq!(png.format, "png");
+        assert_eq!(png.sof, None);
+        assert_eq!(png.progressive, None);
+    }
+
+    #[test]
+    fn decode_many_keeps_order() {
+        let pngs = [encode_png(&solid(1, 1, 1)), encode_png(&solid(2, 1, 2))];
+        let images = decode_many(&pngs, true, &default_options()).unwrap();
+        assert_eq!(images, vec![solid(1, 1, 1), solid(2, 1, 2)]);
+    }
+
+    #[test]
+    fn decode_to_out_colorspaces() {
+        let png = encode_png(&DecodedImage {
+            data: vec![10, 20, 30, 255],
+            width: 1,
+            height: 1,
+            colorspace: ColorSpace::RGBA,
+        });
+        let bgr = default_options().jpeg_set_out_colorspace(ColorSpace::BGR);
+        assert_eq!(decode(&png, true, &bgr).unwrap().data, [30, 20, 10]);
+
+        let jpeg = encode_jpeg(24, 16);
+        for colorspace in OUT_COLORSPACES {
+            let options = default_options().jpeg_set_out_colorspace(colorspace);
+            for use_fast_jpeg in [true, false] {
+                let image = decode(&jpeg, use_fast_jpeg, &options).unwrap();
+                assert_eq!(image.colorspace, colorspace);
+                assert_eq!(image.data.len(), 24 * 16 * colorspace.num_components());
+            }
+        }
+        let ycbcr = default_options().jpeg_set_out_colorspace(ColorSpace::YCbCr);
+        assert_eq!(
+            decode(&jpeg, true, &ycbcr).unwrap_err().code,
+            "InvalidArgument"
+        );
+    }
+
+    #[test]
+    fn decode_at_reduced_scale() {
+        let jpeg = encode_jpeg(100, 60);
+        let full = decode(&jpeg, true, &default_options()).unwrap();
+        for (denominator, size) in [(2, (50, 30)), (4, (25, 15)), (8, (13, 8))] {
+            let options = default_options().jpeg_set_scale_denominator(denominator);
+            for use_fast_jpeg in [true, false] {
+                let image = decode(&jpeg, use_fast_jpeg, &options).unwrap();
+                assert_eq!((image.width, image.height), size);
+                assert_eq!(image.data.len(), (size.0 * size.1 * 4) as usize);
+            }
+            // compare a pixel away from the edges to the average of the pixels it covers
+            let image = decode(&jpeg, true, &options).unwrap();
+            let (x, y) = (40 / denominator, 24 / denominator);
+            for channel in 0..3 {
+                let mut sum = 0;
+                for full_y in y * denominator..(y + 1) * denominator {
+                    for full_x in x * denominator..(x + 1) * denominator {
+                        sum += u32::from(full.data[(full_y * 100 + full_x) * 4 + channel]);
+                    }
+                }
+                let average = sum / (denominator * denominator) as u32;
+                let pixel = u32::from(image.data[(y * size.0 as usize + x) * 4 + channel]);
+                assert!(average.abs_diff(pixel) <= 4, "{average} vs {pixel}");
+            }
+        }
+        let options = default_options().jpeg_set_scale_denominator(3);
+        assert_eq!(
+            decode(&jpeg, true, &options).unwrap_err().code,
+            "InvalidArgument"
+        );
+    }
+
+    #[test]
+    fn grayscale_jpeg_decodes_to_requested_colorspace() {
+        let jpeg = encode_gray_jpeg(24, 16);
+        let luma_options = default_options().jpeg_set_out_colorspace(ColorSpace::Luma);
+        let luma = decode_jpeg(&jpeg, &luma_options).unwrap();
+        assert_eq!(luma.data.len(), 24 * 16);
+        let luma_f32 = decode_jpeg_f32(&jpeg, &luma_options).unwrap();
+        let region = Region {
+            x: 8,
+            y: 8,
+            width: 9,
+            height: 5,
+        };
+
+        for colorspace in OUT_COLORSPACES {
+            let options = default_options().jpeg_set_out_colorspace(colorspace);
+            let channels = colorspace.num_components();
+            let alpha = colorspace.alpha_position();
+            let image = decode_jpeg(&jpeg, &options).unwrap();
+            assert_eq!(image.colorspace, colorspace);
+            for (pixel, &sample) in image.data.chunks_exact(channels).zip(&luma.data) {
+                for (channel, &value) in pixel.iter().enumerate() {
+                    let expected = if Some(channel) == alpha { 255 } else { sample };
+                    assert_eq!(value, expected);
+                }
+            }
+
+            let floats = decode_jpeg_f32(&jpeg, &options).unwrap();
+            assert_eq!(floats.colorspace, colorspace);
+            for (pixel, &sample) in floats.data.chunks_exact(channels).zip(&luma_f32.data) {
+                for (channel, &value) in pixel.iter().enumerate() {
+                    let expected = if Some(channel) == alpha { 1.0 } else { sample };
+                    assert_eq!(value, expected);
+                }
+            }
+
+            let cropped = decode_jpeg_region(&jpeg, region, &options).unwrap();
+            assert_eq!(cropped.colorspace, colorspace);
+            assert_eq!(cropped.data.len(), 9 * 5 * channels);
+
+            let progressive = decode_jpeg_progressive(&jpeg, 1, &options, |pixels, _, _, _| {
+                assert_eq!(pixels.len(), image.data.len());
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(progressive.data, image.data);
+
+            let pending = PendingImage::read(&jpeg, true, &options).unwrap();
+            assert_eq!(pending.colorspace(), colorspace);
+            let layout = OutputLayout::packed(pending.row_size());
+            let mut out = vec![0; pending.buffer_size(layout).unwrap()];
+            pending.decode_into(&mut out, layout).unwrap();
+            assert_eq!(out, image.data);
+        }
+    }
+
+    #[test]
+    fn decode_region_matches_full_decode() {
+        let jpeg = encode_jpeg(100, 60);
+        for denominator in [1, 2] {
+            let options = default_options().jpeg_set_scale_denominator(denominator);
+            let full = decode_jpeg(&jpeg, &options).unwrap();
+            let region = Region {
+                x: 21 / denominator as u32,
+                y: 17 / denominator as u32,
+                width: 30 / denominator as u32,
+                height: 25 / denominator as u32,
+            };
+            let cropped = decode_jpeg_region(&jpeg, region, &options).unwrap();
+            assert_eq!(
+                (cropped.width, cropped.height),
+                (region.width, region.height)
+            );
+
+            let row_len = region.width as usize * 4;
+            for (y, row) in cropped.data.chunks_exact(row_len).enumerate() {
+                let start = ((region.y as usize + y) * full.width as usize + region.x as usize) * 4;
+                assert_eq!(row, &full.data[start..start + row_len], "row {y}");
+            }
+        }
+        let outside = Region {
+            x: 90,
+            y: 0,
+            width: 20,
+            height: 10,
+        };
+        let err = decode_jpeg_region(&jpeg, outside, &default_options()).unwrap_err();
+        assert_eq!(err.code, "InvalidArgument", "{err}");
+    }
+
+    #[test]
+    fn progressive_frames_refine_to_full_decode() {
+        let options = default_options();
+        let full = decode_jpeg(TEST_PROGRESSIVE_JPEG, &options).unwrap();
+        for scan_interval in [1, 2] {
+            let mut frames = Vec::new();
+            let image = decode_jpeg_progressive(
+                TEST_PROGRESSIVE_JPEG,
+                scan_interval,
+                &options,
+                |pixels, width, height, scans| {
+                    assert_eq!((width, height), (48, 32));
+                    assert_eq!(pixels.len(), full.data.len());
+                    frames.push(scans);
+                    Ok(())
+                },
+            )
+            .unwrap();
+            assert_eq!(image, full);
+            assert!(!frames.is_empty());
+            assert!(frames.iter().all(|scans| scans % scan_interval == 0));
+            assert!(frames.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+
+        let stop = DecodeError::invalid_argument("stop".to_string());
+        let err = decode_jpeg_progressive(TEST_PROGRESSIVE_JPEG, 1, &options, |_, _, _, _| {
+            Err(stop.clone())
+        })
+        .unwrap_err();
+        assert_eq!(err, stop);
+
+        // baseline jpegs have no frames to show before the last
+        let baseline = encode_jpeg(16, 8);
+        decode_jpeg_progressive(&baseline, 1, &options, |_, _, _, _| {
+            panic!("baseline jpegs have a single scan")
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn stream_decodes_rows_as_chunks_arrive() {
+        let options = default_options();
+        let jpeg = encode_jpeg(40, 40);
+        let full = decode_jpeg(&jpeg, &options).unwrap();
+        let row_len = 40 * 4;
+
+        let mut stream = JpegStream::new(&options).unwrap();
+        assert!(stream.image().unwrap().is_none());
+        let mut rows = 0;
+        for chunk in jpeg.chunks(50) {
+            let status = stream.push(chunk).unwrap();
+            assert!(stream.rows_decoded() >= rows);
+            rows = stream.rows_decoded();
+            if let Some(partial) = stream.image().unwrap() {
+                let decoded = rows as usize * row_len;
+                assert_eq!(partial.data[..decoded], full.data[..decoded]);
+            }
+            if status == StreamStatus::Complete {
+                break;
+            }
+        }
+        assert!(rows > 0 && rows < 40, "rows appear before the last chunk");
+        assert_eq!(stream.finish().unwrap(), full);
+
+        let upright = options.set_apply_orientation(true);
+        for jpeg in [TEST_PROGRESSIVE_JPEG, &with_exif_orientation(&jpeg, 6)] {
+            for options in [options, upright] {
+                let mut stream = JpegStream::new(&options).unwrap();
+                for chunk in jpeg.chunks(7) {
+                    stream.push(chunk).unwrap();
+                }
+                assert_eq!(
+                    stream.finish().unwrap(),
+                    decode_jpeg(jpeg, &options).unwrap()
+                );
+            }
+        }
+    }
+
+    /// `jpeg` with an EXIF segment holding only an orientation tag
+    fn with_exif_orientation(jpeg: &[u8], orientation: u8) -> Vec<u8> {
+        // big endian TIFF header, IFD0 with one SHORT entry for tag 0x0112
+        let tiff = [
+            b'M',
+            b'M',
+            0,
+            42,
+            0,
+            0,
+            0,
+            8,
+            0,
+            1,
+            0x01,
+            0x12,
+            0,
+            3,
+            0,
+            0,
+            0,
+            1,
+            0,
+            orientation,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ];
+        let length = (2 + 6 + tiff.len()) as u16;
+        let mut out = jpeg[..2].to_vec();
+        out.extend_from_slice(&[0xFF, 0xE1]);
+        out.extend_from_slice(&length.to_be_bytes());
+        out.extend_from_slice(b"Exif\0\0");
+        out.extend_from_slice(&tiff);
+        out.extend_from_slice(&jpeg[2..]);
+        out
+    }
+
+    #[test]
+    fn exif_orientation_is_applied() {
+        let jpeg = encode_jpeg(40, 24);
+        let stored = decode_jpeg(&jpeg, &default_options()).unwrap();
+        // 6 is rotated 90 degrees clockwise
+        let rotated = with_exif_orientation(&jpeg, 6);
+        let options = default_options().set_apply_orientation(true);
+        for use_fast_jpeg in [true, false] {
+            let upright = decode(&rotated, use_fast_jpeg, &options).unwrap();
+            assert_eq!((upright.width, upright.height), (24, 40));
+            for (y, row) in upright.data.chunks_exact(24 * 4).enumerate() {
+                for (x, pixel) in row.chunks_exact(4).enumerate() {
+                    let start = ((23 - x) * 40 + y) * 4;
+                    let expected = &stored.data[start..start + 4];
+                    let close = pixel.iter().zip(expected).all(|(a, b)| a.abs_diff(*b) <= 4);
+                    assert!(
+                        close,
+                        "{use_fast_jpeg} ({x}, {y}) {pixel:?} vs {expected:?}"
+                    );
+                }
+            }
+            // orientation is opt-in
+            let unturned = decode(&rotated, use_fast_jpeg, &default_options()).unwrap();
+            assert_eq!((unturned.width, unturned.height), (40, 24));
+        }
+        // probes report the stored size, which is what the default options decode
+        let probed = probe(&rotated).unwrap();
+        assert_eq!((probed.width, probed.height), (40, 24));
+        let generic = probe_generic(&rotated).unwrap();
+        assert_eq!((generic.width, generic.height), (40, 24));
+
+        // regions are in upright coordinates
+        let upright = decode_jpeg(&rotated, &options).unwrap();
+        let region = Region {
+            x: 5,
+            y: 11,
+            width: 13,
+            height: 20,
+        };
+        let cropped = decode_jpeg_region(&rotated, region, &options).unwrap();
+        let row_len = region.width as usize * 4;
+        for (y, row) in cropped.data.chunks_exact(row_len).enumerate() {
+            let start = ((region.y as usize + y) * 24 + region.x as usize) * 4;
+            assert_eq!(row, &upright.data[start..start + row_len], "row {y}");
+        }
+
+        // pixels that don't fill the image are an error, not a panic
+        let mislabelled = DecodedImage {
+            data: vec![0; 6],
+            ..solid(2, 3, 0)
+        };
+        assert_eq!(
+            orient(mislabelled, Some(6), false).unwrap_err().code,
+            "InvalidArgument"
+        );
+    }
+
+    /// `image` with its rows in reverse order
+    fn flipped(image: &DecodedImage) -> DecodedImage {
+        let row_len = image.width as usize * image.channels();
+        DecodedImage {
+            data: image
+                .data
+                .rchunks_exact(row_len)
+                .flatten()
+                .copied()
+                .collect(),
+            ..image.clone()
+        }
+    }
+
+    #[test]
+    fn flip_and_premultiply_for_webgl() {
+        let oriented = default_options().set_apply_orientation(true);
+        let flip = oriented.set_flip_vertically(true);
+        let rotated = with_exif_orientation(&encode_jpeg(40, 24), 6);
+        for jpeg in [TEST_PROGRESSIVE_JPEG, &encode_jpeg(40, 24), &rotated] {
+            for use_fast_jpeg in [true, false] {
+                let upright = decode(jpeg, use_fast_jpeg, &oriented).unwrap();
+                let image = decode(jpeg, use_fast_jpeg, &flip).unwrap();
+                assert_eq!(image, flipped(&upright), "{use_fast_jpeg}");
+            }
+            let mut stream = JpegStream::new(&flip).unwrap();
+            stream.push(jpeg).unwrap();
+            let upright = decode_jpeg(jpeg, &oriented).unwrap();
+            assert_eq!(stream.finish().unwrap(), flipped(&upright));
+
+            // regions are taken from the upright image before flipping
+            let region = Region {
+                x: 3,
+                y: 5,
+                width: 17,
+                height: 9,
+            };
+            let cropped = decode_jpeg_region(jpeg, region, &oriented).unwrap();
+            let image = decode_jpeg_region(jpeg, region, &flip).unwrap();
+            assert_eq!(image, flipped(&cropped));
+        }
+
+        // jpegs are opaque, so only the generic path has alpha to premultiply
+        let mut translucent = solid(2, 3, 200);
+        translucent
+            .data
+            .chunks_exact_mut(4)
+            .for_each(|pixel| pixel[3] = 255);
+        translucent.data[3] = 128;
+        translucent.data[7] = 0;
+        let png = encode_png(&translucent);
+        let options = default_options()
+            .set_premultiply_alpha(true)
+            .set_flip_vertically(true);
+        let image = decode(&png, true, &options).unwrap();
+        let bottom_row = &image.data[2 * 2 * 4..];
+        assert_eq!(bottom_row, [100, 100, 100, 128, 0, 0, 0, 0]);
+        assert_eq!(image.data[..4], [200, 200, 200, 255]);
+        let jpeg = encode_jpeg(16, 8);
+        let options = default_options().set_premultiply_alpha(true);
+        assert_eq!(
+            decode_jpeg(&jpeg, &options).unwrap(),
+            decode_jpeg(&jpeg, &default_options()).unwrap()
+        );
+    }
+
+    #[test]
+    fn decode_to_floats() {
+        let jpeg = encode_jpeg(40, 24);
+        let png = encode_png(&solid(3, 2, 51));
+        for (encoded, use_fast_jpeg) in [(&jpeg, true), (&jpeg, false), (&png, true)] {
+            for colorspace in OUT_COLORSPACES {
+                let options = default_options().jpeg_set_out_colorspace(colorspace);
+                let bytes = decode(encoded, use_fast_jpeg, &options).unwrap();
+                let floats = decode_f32(encoded, use_fast_jpeg, &options).unwrap();
+                assert_eq!(
+                    (floats.width, floats.height, floats.colorspace),
+                    (bytes.width, bytes.height, bytes.colorspace)
+                );
+                assert_eq!(floats.data.len(), bytes.data.len());
+                // zune-jpeg rounds its fixed point color conversion
+                for (float, byte) in floats.data.iter().zip(&bytes.data) {
+                    assert!((0.0..=1.0).contains(float));
+                    assert!(
+                        (float * 255.0 - f32::from(*byte)).abs() <= 2.0,
+                        "{colorspace:?}"
+                    );
+                }
+            }
+        }
+        let options = default_options().set_flip_vertically(true);
+        let flipped = decode_jpeg_f32(&jpeg, &options).unwrap();
+        let upright = decode_jpeg_f32(&jpeg, &default_options()).unwrap();
+        assert_eq!(flipped.data[..40 * 4], upright.data[23 * 40 * 4..]);
+        assert_eq!(upright.to_f16_bits().len(), upright.data.len());
+    }
+
+    #[test]
+    fn f16_bits_round_to_nearest() {
+        let cases = [
+            (0.0, 0x0000),
+            (-0.0, 0x8000),
+            (1.0, 0x3c00),
+            (0.5, 0x3800),
+            (-2.0, 0xc000),
+            (1.0 / 3.0, 0x3555),
+            (65504.0, 0x7bff),
+            (70000.0, 0x7c00),
+            (f32::INFINITY, 0x7c00),
+            // smallest subnormal, and values rounding to it or to zero
+            (5.960_464_5e-8, 0x0001),
+            (4.0e-8, 0x0001),
+            (2.0e-8, 0x0000),
+            // largest subnormal and smallest normal
+            (6.097_555e-5, 0x03ff),
+            (6.103_515_6e-5, 0x0400),
+            // halfway between 1.0 and the next half float ties to even
+            (1.0 + 1.0 / 2048.0, 0x3c00),
+            (1.0 + 3.0 / 2048.0, 0x3c02),
+        ];
+        for (value, bits) in cases {
+            assert_eq!(f16_bits(value), bits, "{value}");
+        }
+        assert!(f16_bits(f32::NAN) & 0x7fff > 0x7c00);
+    }
+
+    #[test]
+    fn decode_yuv_planes() {
+        let sizes = |image: &YuvImage| -> Vec<_> {
+            image
+                .planes
+                .iter()
+                .map(|plane| (plane.width, plane.height, plane.stride))
+                .collect()
+        };
+        let options = default_options();
+        let i420 = decode_jpeg_yuv(TEST_PROGRESSIVE_JPEG, YuvLayout::Planar, &options).unwrap();
+        assert_eq!(sizes(&i420), [(48, 32, 48), (24, 16, 24), (24, 16, 24)]);
+        assert_eq!(i420.data.len(), 48 * 32 * 3 / 2);
+
+        // luma is the same whichever way chroma is laid out
+        let luma = decode_jpeg(
+            TEST_PROGRESSIVE_JPEG,
+            &options.jpeg_set_out_colorspace(ColorSpace::Luma),
+        )
+        .unwrap();
+        assert_eq!(i420.data[..48 * 32], luma.data);
+
+        let nv12 = decode_jpeg_yuv(TEST_PROGRESSIVE_JPEG, YuvLayout::SemiPlanar, &options).unwrap();
+        assert_eq!(sizes(&nv12), [(48, 32, 48), (24, 16, 48)]);
+        assert_eq!(nv12.data[..48 * 32], luma.data);
+        let (cb, cr) = i420.data[48 * 32..].split_at(24 * 16);
+        let interleaved: Vec<u8> = cb.iter().zip(cr).flat_map(|(cb, cr)| [*cb, *cr]).collect();
+        assert_eq!(nv12.data[48 * 32..], interleaved);
+
+        let yuv444 =
+            decode_jpeg_yuv(TEST_PROGRESSIVE_JPEG, YuvLayout::FullResolution, &options).unwrap();
+        assert_eq!(sizes(&yuv444), [(48, 32, 48); 3]);
+        assert_eq!(yuv444.data[..48 * 32], luma.data);
+
+        // the planes are scaled with the image
+        let options = options.jpeg_set_scale_denominator(4);
+        let yuv444 =
+            decode_jpeg_yuv(TEST_PROGRESSIVE_JPEG, YuvLayout::FullResolution, &options).unwrap();
+        assert_eq!((yuv444.width, yuv444.height), (12, 8));
+
+        let mut gray = Vec::new();
+        image::GrayImage::new(8, 8)
+            .write_to(&mut Cursor::new(&mut gray), image::ImageFormat::Jpeg)
+            .unwrap();
+        assert!(decode_jpeg_yuv(&gray, YuvLayout::Planar, &options).is_err());
+    }
+
+    #[test]
+    fn read_jpeg_and_png_metadata() {
+        let jpeg = with_exif_orientation(&encode_jpeg(16, 8), 3);
+        let metadata = read_metadata(&jpeg).unwrap();
+        assert_eq!(metadata.format, "jpeg");
+        assert_eq!(metadata.exif.unwrap()[..4], *b"MM\0*");
+        assert_eq!(metadata.icc_profile, None);
+        assert_eq!(
+            (
+                metadata.density_units,
+                metadata.x_density,
+                metadata.y_density
+            ),
+            (Some(0), Some(1), Some(1))
+        );
+        assert!(!metadata.is_mjpeg);
+        assert_eq!(metadata.adobe_transform, None);
+
+        let png = encode_png(&solid(3, 2, 7));
+        let metadata = read_metadata(&png).unwrap();
+        assert_eq!(
+            metadata,
+            ImageMetadata {
+                format: "png".to_string(),
+                ..ImageMetadata::default()
+            }
+        );
+    }
+
+    #[test]
+    fn limits_apply_to_both_decoders() {
+        for options in [
+            default_options().set_max_width(1000),
+            default_options().set_max_height(1000),
+            default_options().set_max_pixels(1000 * 1000),
+        ] {
+            for use_fast_jpeg in [true, false] {
+                let err = decode(TEST_JPEG, use_fast_jpeg, &options).unwrap_err();
+                assert_eq!(err.code, "LargeDimensions", "{use_fast_jpeg} {err}");
+            }
+        }
+    }
+
+    #[test]
+    fn memory_limit_applies_before_decoding() {
+        for encoded in [TEST_JPEG, TEST_PROGRESSIVE_JPEG] {
+            let mut decoder = JpegDecoder::new_with_options(encoded, default_options());
+            decoder.decode_headers().unwrap();
+            let peak = decoder.peak_memory().unwrap();
+            let output_size = decoder.output_buffer_size().unwrap();
+            assert!(peak > output_size, "{peak}");
+
+            let options = default_options().set_max_memory(peak - 1);
+            let err = decode(encoded, true, &options).unwrap_err();
+            assert_eq!(err.code, "MemoryLimit", "{err}");
+            let options = default_options().set_max_memory(output_size - 1);
+            let err = decode(encoded, false, &options).unwrap_err();
+            assert_eq!(err.code, "MemoryLimit", "{err}");
+            let options = default_options().set_max_memory(peak);
+            assert!(decode(encoded, true, &options).is_ok());
+        }
+    }
+
+    #[test]
+    fn pack_grid_with_smaller_last_row_and_column() {
+        let images = [
+            solid(2, 2, 1),
+            solid(1, 2, 2),
+            solid(2, 1, 3),
+            solid(1, 1, 4),
+        ];
+        let packed = pack(&images, 2, &PackOptions::default()).unwrap();
+        assert_eq!((packed.width, packed.height), (3, 3));
+        let pixel = |x: usize, y: usize| packed.data[(y * 3 + x) * 4];
+        let rows: Vec<[u8; 3]> = (0..3)
+            .map(|y| [pixel(0, y), pixel(1, y), pixel(2, y)])
+            .collect();
+        assert_eq!(rows, [[1, 1, 2], [1, 1, 2], [3, 3, 4]]);
+    }
+
+    #[test]
+    fn pack_with_gutters_alignment_and_background() {
+        let images = [
+            solid(2, 2, 1),
+            solid(1, 2, 2),
+            solid(2, 1, 3),
+            solid(1, 1, 4),
+        ];
+        let mut options = PackOptions {
+            gutter: 1,
+            extrude: false,
+            background: [10, 20, 30, 40],
+            align: 4,
+            ..PackOptions::default()
+        };
+        // images start on multiples of 4 with at least a pixel around them
+        let packed = pack(&images, 2, &options).unwrap();
+        assert_eq!((packed.width, packed.height), (10, 10));
+        let pixel = |packed: &DecodedImage, x: usize, y: usize| {
+            let start = (y * packed.width as usize + x) * 4;
+            packed.data[start..start + 4].to_vec()
+        };
+        assert_eq!(pixel(&packed, 4, 4), [1; 4]);
+        assert_eq!(pixel(&packed, 8, 8), [4; 4]);
+        assert_eq!(pixel(&packed, 3, 4), [10, 20, 30, 40]);
+
+        options.extrude = true;
+        let packed = pack(&images, 2, &options).unwrap();
+        let samples = |y: usize| -> Vec<u8> { (0..10).map(|x| pixel(&packed, x, y)[0]).collect() };
+        assert_eq!(samples(3), [10, 10, 10, 1, 1, 1, 1, 2, 2, 2]);
+        assert_eq!(samples(4), [10, 10, 10, 1, 1, 1, 1, 2, 2, 2]);
+        assert_eq!(samples(6), [10, 10, 10, 1, 1, 1, 1, 2, 2, 2]);
+        assert_eq!(samples(7), [10, 10, 10, 3, 3, 3, 3, 4, 4, 4]);
+        assert_eq!(samples(9), [10, 10, 10, 3, 3, 3, 3, 4, 4, 4]);
+        assert_eq!(samples(2), [10; 10]);
+
+        // the background is converted to the colorspace of the grid
+        let gray = DecodedImage {
+            data: vec![7; 4],
+            width: 2,
+            height: 2,
+            colorspace: ColorSpace::Luma,
+        };
+        let options = PackOptions {
+            gutter: 1,
+            background: [255, 255, 255, 0],
+            ..PackOptions::default()
+        };
+        let packed = pack(&[gray.clone(), gray], 2, &options).unwrap();
+        assert_eq!((packed.width, packed.height), (8, 4));
+        assert_eq!(
+            packed.data[..9],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255]
+        );
+        assert_eq!(packed.data[9..11], [7, 7]);
+        assert_eq!(
+            pack(
+                &[solid(1, 1, 0)],
+                1,
+                &PackOptions {
+                    align: 0,
+                    ..options
+                }
+            )
+            .unwrap_err()
+            .code,
+            "InvalidArgument"
+        );
+
+        // decoding straight into the grid lays it out the same
+        let encoded = [encode_jpeg(16, 16), encode_jpeg(8, 16)];
+        let options = PackOptions {
+            gutter: 2,
+            extrude: true,
+            background: [0, 0, 0, 255],
+            align: 8,
+            ..PackOptions::default()
+        };
+        for decode_options in [
+            default_options(),
+            default_options().jpeg_set_out_colorspace(ColorSpace::Luma),
+        ] {
+            let decoded = decode_many(&encoded, true, &decode_options).unwrap();
+            let expected = pack(&decoded, 2, &options).unwrap();
+            let packed = decode_pack(&encoded, 2, true, &decode_options, &options).unwrap();
+            assert_eq!(packed, expected);
+        }
+    }
+
+    #[test]
+    fn pack_uniform_tiles_with_fit_and_cover() {
+        let images = [solid(16, 8, 1), solid(4, 8, 2)];
+        let mut options = PackOptions {
+            background: [9, 9, 9, 9],
+            tile_size: Some((8, 8)),
+            ..PackOptions::default()
+        };
+        // letterboxed and centered in their tiles
+        let packed = pack(&images, 2, &options).unwrap();
+        assert_eq!((packed.width, packed.height), (16, 8));
+        let column = |packed: &DecodedImage, x: usize| -> Vec<u8> {
+            (0..8).map(|y| packed.data[(y * 16 + x) * 4]).collect()
+        };
+        assert_eq!(column(&packed, 0), [9, 9, 1, 1, 1, 1, 9, 9]);
+        let row: Vec<u8> = (8..16).map(|x| packed.data[x * 4]).collect();
+        assert_eq!(row, [9, 9, 2, 2, 2, 2, 9, 9]);
+
+        options.tile_fit = ResizeFit::Cover;
+        let packed = pack(&images, 2, &options).unwrap();
+        assert_eq!(column(&packed, 0), [1; 8]);
+        assert_eq!(column(&packed, 10), [2; 8]);
+        assert!(pack(
+            &images,
+            2,
+            &PackOptions {
+                tile_size: Some((0, 8)),
+                ..options
+            }
+        )
+        .is_err());
+
+        // images of different sizes and formats decode into the same tiles
+        let encoded = [
+            encode_jpeg(16, 16),
+            encode_jpeg(8, 4),
+            encode_png(&solid(5, 3, 9)),
+        ];
+        let packed = decode_pack(&encoded, 3, true, &default_options(), &options).unwrap();
+        assert_eq!((packed.width, packed.height), (24, 8));
+    }
+
+    #[test]
+    fn resize_decodes_jpegs_at_reduced_scale() {
+        let resize = ResizeOptions {
+            width: 64,
+            height: 48,
+            fit: ResizeFit::Cover,
+            filter: ResizeFilter::default(),
+            linear_light: false,
+        };
+        // 1536 pixels wide, still larger than the thumbnail at an eighth of its size
+        let options = default_options();
+        assert_eq!(prescale_denominator(TEST_JPEG, true, &options, &resize), 8);
+        assert_eq!(prescale_denominator(TEST_JPEG, false, &options, &resize), 1);
+        let scaled = options.jpeg_set_scale_denominator(2);
+        assert_eq!(prescale_denominator(TEST_JPEG, true, &scaled, &resize), 8);
+        let resized = decode_resized(TEST_JPEG, true, &options, &resize).unwrap();
+        let expected = resize::resize(
+            &decode(TEST_JPEG, true, &options.jpeg_set_scale_denominator(8)).unwrap(),
+            &resize,
+        )
+        .unwrap();
+        assert_eq!(resized, expected);
+
+        // grayscale jpegs come out of zune-jpeg as Luma and are expanded before resizing
+        let gray_jpeg = encode_gray_jpeg(128, 96);
+        assert_eq!(prescale_denominator(&gray_jpeg, true, &options, &resize), 2);
+        let gray = decode_resized(&gray_jpeg, true, &options, &resize).unwrap();
+        assert_eq!(
+            (gray.width, gray.height, gray.colorspace),
+            (64, 48, ColorSpace::RGBA)
+        );
+        assert_eq!(gray.data.len(), 64 * 48 * 4);
+        assert!(gray
+            .data
+            .chunks_exact(4)
+            .all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2] && pixel[3] == 255));
+
+        // 48x32, so only half its size is large enough
+        let resize = ResizeOptions {
+            width: 20,
+            height: 16,
+            ..resize
+        };
+        assert_eq!(
+            prescale_denominator(TEST_PROGRESSIVE_JPEG, true, &options, &resize),
+            2
+        );
+    }
+
+    #[test]
+    fn decode_pack_writes_tiles_in_place() {
+        let rotated = with_exif_orientation(&encode_jpeg(16, 8), 6);
+        let encoded = [
+            encode_jpeg(16, 16),
+            rotated,
+            encode_png(&solid(16, 4, 9)),
+            encode_jpeg(8, 4),
+        ];
+        // the rotated jpeg only fits the grid upright
+        let oriented = default_options().set_apply_orientation(true);
+        for options in [
+            oriented,
+            oriented.set_flip_vertically(true),
+            oriented.jpeg_set_scale_denominator(2),
+        ] {
+            for use_fast_jpeg in [true, false] {
+                let cell_options = options.set_flip_vertically(false);
+                let decoded = decode_many(&encoded, use_fast_jpeg, &cell_options).unwrap();
+                let mut expected = pack(&decoded, 2, &PackOptions::default()).unwrap();
+                if options.get_flip_vertically() {
+                    flip_vertically(&mut expected);
+                }
+                let packed = decode_pack(
+                    &encoded,
+                    2,
+                    use_fast_jpeg,
+                    &options,
+                    &PackOptions::default(),
+                )
+                .unwrap();
+                assert_eq!(packed, expected);
+            }
+        }
+        let progressive = decode_pack(
+            &[TEST_PROGRESSIVE_JPEG],
+            1,
+            true,
+            &default_options(),
+            &PackOptions::default(),
+        );
+        assert_eq!(
+            progressive.unwrap(),
+            decode(TEST_PROGRESSIVE_JPEG, true, &default_options()).unwrap()
+        );
+
+        // sizes are checked from the headers
+        let encoded = [encode_jpeg(16, 16), encode_jpeg(8, 8)];
+        let err = decode_pack(
+            &encoded,
+            1,
+            true,
+            &default_options(),
+            &PackOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.code, "InvalidGrid");
+    }
+
+    #[test]
+    fn decode_into_padded_layout() {
+        let encoded = [
+            encode_jpeg(16, 8),
+            with_exif_orientation(&encode_jpeg(16, 8), 6),
+            encode_gray_jpeg(16, 8),
+            TEST_PROGRESSIVE_JPEG.to_vec(),
+            encode_png(&solid(5, 3, 9)),
+            encode_dynamic_png(DynamicImage::ImageRgb8(image::RgbImage::from_fn(
+                5,
+                3,
+                |x, y| image::Rgb([x as u8 * 40, y as u8 * 60, 7]),
+            ))),
+            encode_dynamic_png(DynamicImage::ImageLumaA8(image::GrayAlphaImage::from_fn(
+                5,
+                3,
+                |x, y| image::LumaA([x as u8 * 40, y as u8 * 90]),
+            ))),
+            encode_dynamic_png(DynamicImage::ImageRgba16(image::ImageBuffer::from_fn(
+                5,
+                3,
+                |x, y| image::Rgba([x as u16 * 9000, y as u16 * 20000, 3, 40000]),
+            ))),
+        ];
+        let options = [
+            default_options(),
+            default_options().set_apply_orientation(true),
+            default_options().set_flip_vertically(true),
+            default_options()
+                .jpeg_set_out_colorspace(ColorSpace::BGRA)
+                .set_premultiply_alpha(true),
+            default_options().jpeg_set_out_colorspace(ColorSpace::BGR),
+            default_options().jpeg_set_out_colorspace(ColorSpace::Luma),
+        ];
+        for (encoded, options) in encoded
+            .iter()
+            .flat_map(|encoded| options.iter().map(move |options| (encoded, options)))
+        {
+            for use_fast_jpeg in [true, false] {
+                let expected = decode(encoded, use_fast_jpeg, options).unwrap();
+                let image = PendingImage::read(encoded, use_fast_jpeg, options).unwrap();
+                let row_size = image.row_size();
+                let layout = OutputLayout {
+                    offset: 7,
+                    stride: row_size + 5,
+                };
+                let size = image.buffer_size(layout).unwrap();
+                assert!(image
+                    .buffer_size(OutputLayout::packed(row_size - 1))
+                    .is_err());
+
+                let mut out = vec![0xAA; size + 3];
+                image.decode_into(&mut out, layout).unwrap();
+                assert_eq!(
+                    out[7..].chunks(layout.stride).count(),
+                    expected.height as usize
+                );
+                assert!(out[..7].iter().all(|&byte| byte == 0xAA));
+                assert!(out[size..].iter().all(|&byte| byte == 0xAA));
+                let rows = out[7..].chunks(layout.stride);
+                for (row, expected) in rows.zip(expected.data.chunks_exact(row_size)) {
+                    assert_eq!(&row[..row_size], expected);
+                    assert!(row[row_size..].iter().all(|&byte| byte == 0xAA));
+                }
+            }
+        }
+        let image = PendingImage::read(&encoded[0], true, &default_options()).unwrap();
+        let err = image
+            .decode_into(&mut [0; 64], OutputLayout::packed(16 * 4))
+            .unwrap_err();
+        assert_eq!(err.code, "TooSmallOutput");
+    }
+
+    #[test]
+    fn decode_into_rejects_invalid_layouts() {
+        let encoded = [encode_jpeg(16, 8), encode_png(&solid(16, 8, 9))];
+        for encoded in &encoded {
+            for use_fast_jpeg in [true, false] {
+                let read =
+                    || PendingImage::read(encoded, use_fast_jpeg, &default_options()).unwrap();
+                let row_size = read().row_size();
+                let mut out = vec![0xAA; 1024];
+                let layouts = [
+                    // rows overlapping each other
+                    (
+                        OutputLayout {
+                            offset: 0,
+                            stride: row_size - 1,
+                        },
+                        "InvalidArgument",
+                    ),
+                    (
+                        OutputLayout {
+                            offset: 0,
+                            stride: 0,
+                        },
+                        "InvalidArgument",
+                    ),
+                    // last row past the end of the buffer
+                    (
+                        OutputLayout {
+                            offset: 1024 - 7 * row_size,
+                            stride: row_size,
+                        },
+                        "TooSmallOutput",
+                    ),
+                    (
+                        OutputLayout {
+                            offset: 2048,
+                            stride: row_size,
+                        },
+                        "TooSmallOutput",
+                    ),
+                    (
+                        OutputLayout {
+                            offset: 0,
+                            stride: 200,
+                        },
+                        "TooSmallOutput",
+                    ),
+                    // sizes overflowing a usize
+                    (
+                        OutputLayout {
+                            offset: usize::MAX,
+                            stride: row_size,
+                        },
+                        "InvalidArgument",
+                    ),
+                    (
+                        OutputLayout {
+                            offset: 0,
+                            stride: usize::MAX,
+                        },
+                        "InvalidArgument",
+                    ),
+                ];
+                for (layout, code) in layouts {
+                    let err = read().decode_into(&mut out, layout).unwrap_err();
+                    assert_eq!(err.code, code, "{layout:?}");
+                }
+                assert!(out.iter().all(|&byte| byte == 0xAA));
+            }
+        }
+    }
+
+    #[test]
+    fn pack_rejects_invalid_grids() {
+        let images = [solid(2, 2, 1), solid(1, 2, 2), solid(2, 1, 3)];
+        assert_eq!(
+            pack(&images, 2, &PackOptions::default()).unwrap_err().code,
+            "InvalidArgument"
+        );
+        assert_eq!(
+            pack(&images, 0, &PackOptions::default()).unwrap_err().code,
+            "InvalidArgument"
+        );
+        assert_eq!(
+            pack(&[], 1, &PackOptions::default()).unwrap_err().code,
+            "InvalidArgument"
+        );
+
+        let images = [
+            solid(2, 2, 1),
+            solid(1, 2, 2),
+            solid(1, 1, 3),
+            solid(1, 1, 4),
+        ];
+        assert_eq!(
+            pack(&images, 2, &PackOptions::default()).unwrap_err().code,
+            "InvalidGrid"
+        );
+
+        // grayscale pixels labelled RGBA, with or without resizing
+        let mislabelled = DecodedImage {
+            data: vec![7; 4],
+            ..solid(2, 2, 0)
+        };
+        let images = [solid(2, 2, 1), mislabelled];
+        let tiles = PackOptions {
+            tile_size: Some((4, 4)),
+            ..PackOptions::default()
+        };
+        for options in [PackOptions::default(), tiles] {
+            assert_eq!(
+                pack(&images, 2, &options).unwrap_err().code,
+                "InvalidArgument"
+            );
+        }
+    }
+
+    #[test]
+    fn decode_pack_expands_grayscale_jpegs() {
+        let encoded = [
+            encode_gray_jpeg(8, 8),
+            encode_jpeg(8, 8),
+            encode_gray_jpeg(8, 8),
+        ];
+        let gray = decode_jpeg(&encoded[0], &default_options()).unwrap();
+        let tiles = PackOptions {
+            tile_size: Some((8, 8)),
+            ..PackOptions::default()
+        };
+        for pack_options in [PackOptions::default(), tiles] {
+            let grid = decode_pack(&encoded, 3, true, &default_options(), &pack_options).unwrap();
+            assert_eq!((grid.width, grid.height), (24, 8));
+            assert_eq!(grid.colorspace, ColorSpace::RGBA);
+            for (row, expected) in grid
+                .data
+                .chunks_exact(24 * 4)
+                .zip(gray.data.chunks_exact(8 * 4))
+            {
+                assert_eq!(&row[..8 * 4], expected);
+                assert_eq!(&row[16 * 4..], expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,516 @@
+//! Decoder options passed from JS
+//!
+//! Options are a plain object such as `{ maxWidth: 4096, outColorspace: "RGB" }`,
+//! mapped onto zune-core's `DecoderOptions`. Missing keys keep the defaults of
+//! [`native::default_options`], unknown keys and invalid values are rejected.
+use wasm_bindgen::prelude::*;
+use zune_core::colorspace::ColorSpace;
+use zune_core::options::DecoderOptions;
+
+use crate::atlas::AtlasOptions;
+use crate::errors::DecodeError;
+use crate::native::{self, PackOptions, OUT_COLORSPACES, SCALE_DENOMINATORS};
+use crate::resize::{ResizeFilter, ResizeFit, ResizeOptions};
+use crate::tensor::{ChannelOrder, TensorLayout, TensorOptions};
+
+#[wasm_bindgen(typescript_custom_section)]
+const DECODE_OPTIONS_TS: &str = r#"
+/** Options accepted by the decode functions and decoder classes */
+export interface DecodeOptions {
+    /** Error on jpegs that don't follow the spec instead of decoding what is possible, default true */
+    strictMode?: boolean;
+    /** Largest accepted image width, applies to all formats, default 16384 */
+    maxWidth?: number;
+    /** Largest accepted image height, applies to all formats, default 16384 */
+    maxHeight?: number;
+    /** Largest accepted number of pixels, width times height, applies to all
+     * formats, default 268435456 (16384 x 16384) */
+    maxPixels?: number;
+    /** Most bytes decoding an image may allocate, jpegs that need more are rejected
+     * from their headers before anything is allocated, other formats once they hit
+     * it, default 2 GiB */
+    maxMemory?: number;
+    /** Most scans accepted in a progressive jpeg, default 100 */
+    maxScans?: number;
+    /** Allow zune-jpeg to use unsafe platform specific routines, default true */
+    useUnsafe?: boolean;
+    /** Layout of the output pixels, `RGBA` (default), `RGB`, `BGRA`, `BGR` or `Luma` */
+    outColorspace?: string;
+    /** Decode at `1/scaleDenominator` of the image size, `1` (default), `2`, `4` or `8`.
+     * Sizes are rounded up, jpegs are scaled while decoding, which is much faster
+     * than decoding them at full size */
+    scaleDenominator?: number;
+    /** Turn images upright using their EXIF orientation, swapping width and height
+     * of rotated images, default false */
+    applyOrientation?: boolean;
+    /** Write rows bottom to top, as WebGL expects texture uploads without
+     * `UNPACK_FLIP_Y_WEBGL`, default false */
+    flipY?: boolean;
+    /** Multiply color channels by alpha, as WebGL expects texture uploads without
+     * `UNPACK_PREMULTIPLY_ALPHA_WEBGL`, default false */
+    premultiplyAlpha?: boolean;
+}
+"#;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TENSOR_OPTIONS_TS: &str = r#"
+/** How the tensor exports lay out images, see `decode_tensor` */
+export interface TensorOptions {
+    /** Order of the dimensions, `NHWC` (default) or `NCHW` */
+    layout?: string;
+    /** Order of the color channels, `RGB` (default) or `BGR` */
+    channelOrder?: string;
+    /** Subtracted from the samples scaled to 0..1, one value per channel in
+     * `channelOrder`, default [0, 0, 0] */
+    mean?: number[];
+    /** Divides the samples once `mean` is subtracted, one value per channel in
+     * `channelOrder`, default [1, 1, 1] */
+    std?: number[];
+    /** Width images are resized to with bilinear filtering, set along with `height`.
+     * Without them all images of a batch must have the same size */
+    width?: number;
+    /** Height images are resized to, set along with `width` */
+    height?: number;
+}
+"#;
+
+#[wasm_bindgen(typescript_custom_section)]
+const RESIZE_OPTIONS_TS: &str = r#"
+/** How `decode_img` and `decode_img_raw` resize images */
+export interface ResizeOptions {
+    /** Width images are resized to, or fitted within */
+    width: number;
+    /** Height images are resized to, or fitted within */
+    height: number;
+    /** `fit` (default) scales images to fit within width x height keeping their
+     * aspect ratio, `cover` scales them to cover it and crops what sticks out,
+     * `fill` stretches them to exactly width x height */
+    fit?: string;
+    /** `lanczos3` (default), `mitchell`, `bilinear` or `box` */
+    filter?: string;
+    /** Filter in linear light instead of on sRGB values, slower but keeps fine
+     * bright details from darkening, default false */
+    linearLight?: boolean;
+}
+"#;
+
+#[wasm_bindgen(typescript_custom_section)]
+const PACK_OPTIONS_TS: &str = r#"
+/** How `decode_pack_imgs` and `decode_pack_imgs_raw` lay out the grid. Pixels
+ * are in the format of `DecodeOptions.outColorspace`, e.g. `Luma` for grayscale sets */
+export interface PackOptions {
+    /** Pixels left around each image so filtering doesn't bleed neighbours in, default 0 */
+    gutter?: number;
+    /** Fill gutters with the edge pixels of their image instead of `background`,
+     * default false */
+    extrude?: boolean;
+    /** RGBA color of pixels no image covers, 0 to 255 per channel, converted to
+     * `outColorspace`, default [0, 0, 0, 0] */
+    background?: number[];
+    /** Multiple of pixels the left and top edges of images are placed on, default 1 */
+    align?: number;
+    /** Width of the cells of the grid, set along with `tileHeight`. Images of any size
+     * are resized into the cells, jpegs are decoded at a reduced scale when they are
+     * large enough. Without it all images must have the same size */
+    tileWidth?: number;
+    /** Height of the cells of the grid, set along with `tileWidth` */
+    tileHeight?: number;
+    /** `fit` (default) scales images to fit within their cell and letterboxes them,
+     * `cover` scales them to cover it and crops what sticks out, `fill` stretches them */
+    tileFit?: string;
+}
+"#;
+
+#[wasm_bindgen(typescript_custom_section)]
+const ATLAS_OPTIONS_TS: &str = r#"
+/** How `decode_atlas` places images */
+export interface AtlasOptions {
+    /** Widest a page may be, without it pages are about as wide as they are tall */
+    maxWidth?: number;
+    /** Tallest a page may be, images that don't fit start a new page. Without it
+     * all images go on a single page */
+    maxHeight?: number;
+    /** Round the width and height of pages up to powers of two, default false */
+    powerOfTwo?: boolean;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    /// JS object holding `DecodeOptions`
+    #[wasm_bindgen(typescript_type = "DecodeOptions")]
+    pub type JsDecodeOptions;
+
+    /// JS object holding `TensorOptions`
+    #[wasm_bindgen(typescript_type = "TensorOptions")]
+    pub type JsTensorOptions;
+
+    /// JS object holding `ResizeOptions`
+    #[wasm_bindgen(typescript_type = "ResizeOptions")]
+    pub type JsResizeOptions;
+
+    /// JS object holding `PackOptions`
+    #[wasm_bindgen(typescript_type = "PackOptions")]
+    pub type JsPackOptions;
+
+    /// JS object holding `AtlasOptions`
+    #[wasm_bindgen(typescript_type = "AtlasOptions")]
+    pub type JsAtlasOptions;
+}
+
+const OPTION_KEYS: [&str; 12] = [
+    "strictMode",
+    "maxWidth",
+    "maxHeight",
+    "maxPixels",
+    "maxMemory",
+    "maxScans",
+    "useUnsafe",
+    "outColorspace",
+    "scaleDenominator",
+    "applyOrientation",
+    "flipY",
+    "premultiplyAlpha",
+];
+
+/// Map a JS `DecodeOptions` object onto `DecoderOptions`
+pub(crate) fn parse_options(
+    js_options: Option<JsDecodeOptions>,
+) -> Result<DecoderOptions, DecodeError> {
+    let mut options = native::default_options();
+    let Some(js_options) = js_options else {
+        return Ok(options);
+    };
+    for entry in option_entries("Decoder", js_options.unchecked_ref()) {
+        options = match entry.key.as_str() {
+            "strictMode" => options.set_strict_mode(entry.bool()?),
+            "maxWidth" => options.set_max_width(entry.size()?),
+            "maxHeight" => options.set_max_height(entry.size()?),
+            "maxPixels" => options.set_max_pixels(entry.size()?),
+            "maxMemory" => options.set_max_memory(entry.size()?),
+            "maxScans" => options.jpeg_set_max_scans(entry.size()?),
+            "useUnsafe" => options.set_use_unsafe(entry.bool()?),
+            "outColorspace" => options.jpeg_set_out_colorspace(entry.colorspace()?),
+            "scaleDenominator" => options.jpeg_set_scale_denominator(entry.scale()?),
+            "applyOrientation" => options.set_apply_orientation(entry.bool()?),
+            "flipY" => options.set_flip_vertically(entry.bool()?),
+            "premultiplyAlpha" => options.set_premultiply_alpha(entry.bool()?),
+            _ => return Err(entry.unknown(&OPTION_KEYS)),
+        };
+    }
+    Ok(options)
+}
+
+/// [`parse_options`] for exports returning `ImageData`, which is always RGBA
+pub(crate) fn parse_image_data_options(
+    js_options: Option<JsDecodeOptions>,
+) -> Result<DecoderOptions, DecodeError> {
+    let options = parse_options(js_options)?;
+    let colorspace = options.jpeg_get_out_colorspace();
+    if colorspace != ColorSpace::RGBA {
+        return Err(DecodeError::invalid_argument(format!(
+            "ImageData output is always RGBA, got outColorspace {colorspace:?}"
+        )));
+    }
+    Ok(options)
+}
+
+const TENSOR_OPTION_KEYS: [&str; 6] = ["layout", "channelOrder", "mean", "std", "width", "height"];
+
+/// Map a JS `TensorOptions` object onto `TensorOptions`
+pub(crate) fn parse_tensor_options(
+    js_options: Option<JsTensorOptions>,
+) -> Result<TensorOptions, DecodeError> {
+    let mut options = TensorOptions::default();
+    let Some(js_options) = js_options else {
+        return Ok(options);
+    };
+    let (mut width, mut height) = (None, None);
+    for entry in option_entries("Tensor", js_options.unchecked_ref()) {
+        match entry.key.as_str() {
+            "layout" => {
+                options.layout =
+                    entry.named(&[("NHWC", TensorLayout::Nhwc), ("NCHW", TensorLayout::Nchw)])?
+            }
+            "channelOrder" => {
+                options.channel_order =
+                    entry.named(&[("RGB", ChannelOrder::Rgb), ("BGR", ChannelOrder::Bgr)])?
+            }
+            "mean" => options.mean = entry.channels()?,
+            "std" => {
+                options.std = entry.channels()?;
+                if options.std.contains(&0.0) {
+                    return Err(entry.invalid("can't hold zeros"));
+                }
+            }
+            "width" => width = Some(entry.size()? as u32),
+            "height" => height = Some(entry.size()? as u32),
+            _ => return Err(entry.unknown(&TENSOR_OPTION_KEYS)),
+        }
+    }
+    options.size = match (width, height) {
+        (Some(width), Some(height)) => Some((width, height)),
+        (None, None) => None,
+        _ => {
+            return Err(DecodeError::invalid_argument(
+                "Tensor options `width` and `height` must be set together".to_string(),
+            ))
+        }
+    };
+    Ok(options)
+}
+
+const RESIZE_OPTION_KEYS: [&str; 5] = ["width", "height", "fit", "filter", "linearLight"];
+
+/// Names of the ways images are fitted into a size, for `fit` and `tileFit`
+const FIT_NAMES: [(&str, ResizeFit); 3] = [
+    ("fit", ResizeFit::Fit),
+    ("cover", ResizeFit::Cover),
+    ("fill", ResizeFit::Fill),
+];
+
+/// Map a JS `ResizeOptions` object onto `ResizeOptions`
+pub(crate) fn parse_resize_options(
+    js_options: JsResizeOptions,
+) -> Result<ResizeOptions, DecodeError> {
+    let (mut width, mut height) = (None, None);
+    let mut fit = ResizeFit::default();
+    let mut filter = ResizeFilter::default();
+    let mut linear_light = false;
+    for entry in option_entries("Resize", js_options.unchecked_ref()) {
+        match entry.key.as_str() {
+            "width" => width = Some(entry.size()? as u32),
+            "height" => height = Some(entry.size()? as u32),
+            "fit" => fit = entry.named(&FIT_NAMES)?,
+            "filter" => {
+                filter = entry.named(&[
+                    ("lanczos3", ResizeFilter::Lanczos3),
+                    ("mitchell", ResizeFilter::Mitchell),
+                    ("bilinear", ResizeFilter::Bilinear),
+                    ("box", ResizeFilter::Box),
+                ])?
+            }
+            "linearLight" => linear_light = entry.bool()?,
+            _ => return Err(entry.unknown(&RESIZE_OPTION_KEYS)),
+        }
+    }
+    let (Some(width), Some(height)) = (width, height) else {
+        return Err(DecodeError::invalid_argument(
+            "Resize options `width` and `height` are required".to_string(),
+        ));
+    };
+    Ok(ResizeOptions {
+        width,
+        height,
+        fit,
+        filter,
+        linear_light,
+    })
+}
+
+const PACK_OPTION_KEYS: [&str; 7] = [
+    "gutter",
+    "extrude",
+    "background",
+    "align",
+    "tileWidth",
+    "tileHeight",
+    "tileFit",
+];
+
+/// Map a JS `PackOptions` object onto `PackOptions`
+pub(crate) fn parse_pack_options(
+    js_options: Option<JsPackOptions>,
+) -> Result<PackOptions, DecodeError> {
+    let mut options = PackOptions::default();
+    let Some(js_options) = js_options else {
+        return Ok(options);
+    };
+    let (mut width, mut height) = (None, None);
+    for entry in option_entries("Pack", js_options.unchecked_ref()) {
+        match entry.key.as_str() {
+            "gutter" => options.gutter = entry.count()? as u32,
+            "extrude" => options.extrude = entry.bool()?,
+            "background" => options.background = entry.color()?,
+            "align" => options.align = entry.size()? as u32,
+            "tileWidth" => width = Some(entry.size()? as u32),
+            "tileHeight" => height = Some(entry.size()? as u32),
+            "tileFit" => options.tile_fit = entry.named(&FIT_NAMES)?,
+            _ => return Err(entry.unknown(&PACK_OPTION_KEYS)),
+        }
+    }
+    options.tile_size = match (width, height) {
+        (Some(width), Some(height)) => Some((width, height)),
+        (None, None) => None,
+        _ => {
+            return Err(DecodeError::invalid_argument(
+                "Pack options `tileWidth` and `tileHeight` must be set together".to_string(),
+            ))
+        }
+    };
+    Ok(options)
+}
+
+const ATLAS_OPTION_KEYS: [&str; 3] = ["maxWidth", "maxHeight", "powerOfTwo"];
+
+/// Map a JS `AtlasOptions` object onto `AtlasOptions`
+pub(crate) fn parse_atlas_options(
+    js_options: Option<JsAtlasOptions>,
+) -> Result<AtlasOptions, DecodeError> {
+    let mut options = AtlasOptions::default();
+    let Some(js_options) = js_options else {
+        return Ok(options);
+    };
+    for entry in option_entries("Atlas", js_options.unchecked_ref()) {
+        match entry.key.as_str() {
+            "maxWidth" => options.max_width = Some(entry.size()? as u32),
+            "maxHeight" => options.max_height = Some(entry.size()? as u32),
+            "powerOfTwo" => options.power_of_two = entry.bool()?,
+            _ => return Err(entry.unknown(&ATLAS_OPTION_KEYS)),
+        }
+    }
+    Ok(options)
+}
+
+/// Entries of a JS options object whose value isn't `undefined`, `kind` names the
+/// options in errors, e.g. "Decoder" or "Pack"
+fn option_entries(
+    kind: &'static str,
+    js_options: &js_sys::Object,
+) -> impl Iterator<Item = OptionEntry> {
+    js_sys::Object::entries(js_options)
+        .into_iter()
+        .filter_map(move |entry| {
+            let entry: js_sys::Array = entry.unchecked_into();
+            let value = entry.get(1);
+            if value.is_undefined() {
+                return None;
+            }
+            Some(OptionEntry {
+                kind,
+                key: entry.get(0).as_string().unwrap_or_default(),
+                value,
+            })
+        })
+}
+
+/// A key of a JS options object and its value, read as the type the key expects
+struct OptionEntry {
+    kind: &'static str,
+    key: String,
+    value: JsValue,
+}
+
+impl OptionEntry {
+    /// Error on a value that isn't what the key expects, `expected` completes
+    /// "Pack option `gutter` ..."
+    fn invalid(&self, expected: &str) -> DecodeError {
+        DecodeError::invalid_argument(format!(
+            "{} option `{}` {expected}, got {:?}",
+            self.kind, self.key, self.value
+        ))
+    }
+
+    /// Error on a key that isn't one of `keys`
+    fn unknown(&self, keys: &[&str]) -> DecodeError {
+        DecodeError::invalid_argument(format!(
+            "Unknown {} option `{}`, expected one of {keys:?}",
+            self.kind.to_lowercase(),
+            self.key
+        ))
+    }
+
+    fn bool(&self) -> Result<bool, DecodeError> {
+        self.value
+            .as_bool()
+            .ok_or_else(|| self.invalid("must be a boolean"))
+    }
+
+    /// An integer from 1 to `u32::MAX`
+    fn size(&self) -> Result<usize, DecodeError> {
+        match self.count()? {
+            0 => Err(self.invalid("must be a positive integer")),
+            size => Ok(size),
+        }
+    }
+
+    /// An integer from 0 to `u32::MAX`
+    fn count(&self) -> Result<usize, DecodeError> {
+        match self.value.as_f64() {
+            Some(count) if count >= 0.0 && count.fract() == 0.0 && count <= f64::from(u32::MAX) => {
+                Ok(count as usize)
+            }
+            _ => Err(self.invalid("must be a non-negative integer")),
+        }
+    }
+
+    /// One of the strings in `names`
+    fn named<T: Copy>(&self, names: &[(&str, T)]) -> Result<T, DecodeError> {
+        let name = self.value.as_string();
+        names
+            .iter()
+            .find(|(option_name, _)| name.as_deref() == Some(*option_name))
+            .map(|(_, option)| *option)
+            .ok_or_else(|| {
+                let names: Vec<_> = names.iter().map(|(name, _)| name).collect();
+                self.invalid(&format!("must be one of {names:?}"))
+            })
+    }
+
+    /// An array of a finite number per color channel
+    fn channels(&self) -> Result<[f32; 3], DecodeError> {
+        let invalid = || self.invalid("must be an array of 3 numbers");
+        if !js_sys::Array::is_array(&self.value) {
+            return Err(invalid());
+        }
+        let array: &js_sys::Array = self.value.unchecked_ref();
+        if array.length() != 3 {
+            return Err(invalid());
+        }
+        let mut channels = [0.0; 3];
+        for (index, channel) in channels.iter_mut().enumerate() {
+            match array.get(index as u32).as_f64() {
+                Some(number) if number.is_finite() => *channel = number as f32,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(channels)
+    }
+
+    /// An array of 4 integers from 0 to 255, red, green, blue and alpha
+    fn color(&self) -> Result<[u8; 4], DecodeError> {
+        let invalid = || self.invalid("must be an array of 4 integers from 0 to 255");
+        if !js_sys::Array::is_array(&self.value) {
+            return Err(invalid());
+        }
+        let array: &js_sys::Array = self.value.unchecked_ref();
+        if array.length() != 4 {
+            return Err(invalid());
+        }
+        let mut color = [0; 4];
+        for (index, channel) in color.iter_mut().enumerate() {
+            match array.get(index as u32).as_f64() {
+                Some(number) if (0.0..=255.0).contains(&number) && number.fract() == 0.0 => {
+                    *channel = number as u8
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(color)
+    }
+
+    fn colorspace(&self) -> Result<ColorSpace, DecodeError> {
+        let name = self.value.as_string();
+        OUT_COLORSPACES
+            .into_iter()
+            .find(|colorspace| Some(format!("{colorspace:?}")) == name)
+            .ok_or_else(|| self.invalid(&format!("must be one of {OUT_COLORSPACES:?}")))
+    }
+
+    fn scale(&self) -> Result<usize, DecodeError> {
+        SCALE_DENOMINATORS
+            .into_iter()
+            .find(|denominator| self.value.as_f64() == Some(*denominator as f64))
+            .ok_or_else(|| self.invalid(&format!("must be one of {SCALE_DENOMINATORS:?}")))
+    }
+}
@@ -0,0 +1,491 @@
+//! Resizing of decoded images
+//!
+//! Images are resampled with separable filters in plain Rust, so thumbnails, atlases
+//! and model inputs can be made inside wasm without a round trip through a canvas.
+//! Colors are weighted by alpha while they are filtered, so transparent pixels don't
+//! bleed into their neighbours, and can be filtered in linear light instead of as the
+//! sRGB encoded values, which keeps fine bright details from darkening.
+//!
+//! Like [`native`](crate::native), this module has no `js_sys` types.
+use crate::errors::DecodeError;
+use crate::native::DecodedImage;
+
+/// Filter images are resampled with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResizeFilter {
+    /// Average of the pixels each output pixel covers, blocky when enlarging
+    Box,
+    /// Linear interpolation between the nearest pixels
+    Bilinear,
+    /// Windowed sinc over three pixels on each side, the sharpest of the filters
+    #[default]
+    Lanczos3,
+    /// Mitchell-Netravali cubic, less sharp than Lanczos3 with less ringing
+    Mitchell,
+}
+
+impl ResizeFilter {
+    /// Distance from the center, in source pixels, past which the filter is zero
+    fn support(self) -> f32 {
+        match self {
+            ResizeFilter::Box => 0.5,
+            ResizeFilter::Bilinear => 1.0,
+            ResizeFilter::Lanczos3 => 3.0,
+            ResizeFilter::Mitchell => 2.0,
+        }
+    }
+
+    fn weight(self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            ResizeFilter::Box => f32::from(u8::from(x < 0.5)),
+            ResizeFilter::Bilinear => (1.0 - x).max(0.0),
+            ResizeFilter::Lanczos3 => {
+                if x < 3.0 {
+                    sinc(x) * sinc(x / 3.0)
+                } else {
+                    0.0
+                }
+            }
+            ResizeFilter::Mitchell => {
+                // B = C = 1/3
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                if x < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                } else if x < 2.0 {
+                    ((-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    let x = x * core::f32::consts::PI;
+    x.sin() / x
+}
+
+/// How an image is fitted into the requested width and height
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResizeFit {
+    /// Stretch the image to exactly the requested size
+    Fill,
+    /// Scale the image to the largest size that fits within the requested one,
+    /// keeping its aspect ratio
+    #[default]
+    Fit,
+    /// Scale the image to the smallest size that covers the requested one, keeping
+    /// its aspect ratio, and crop what sticks out on both sides
+    Cover,
+}
+
+/// How an image is resized
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResizeOptions {
+    pub width: u32,
+    pub height: u32,
+    pub fit: ResizeFit,
+    pub filter: ResizeFilter,
+    /// Filter in linear light, converting color samples from and back to sRGB
+    pub linear_light: bool,
+}
+
+impl ResizeOptions {
+    /// Size of a `width`x`height` image once resized
+    pub fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        if self.fit != ResizeFit::Fit {
+            return (self.width, self.height);
+        }
+        let scale = f64::min(
+            f64::from(self.width) / f64::from(width),
+            f64::from(self.height) / f64::from(height),
+        );
+        // the side that limits the scale is exactly the requested size
+        let fitted =
+            |size: u32, limit: u32| ((f64::from(size) * scale).round() as u32).clamp(1, limit);
+        (fitted(width, self.width), fitted(height, self.height))
+    }
+}
+
+/// Resize `image`, whose alpha, if it has any, is not premultiplied
+///
+/// # Errors
+/// The requested size or the image is empty, or the pixels of the image don't fill
+/// its dimensions.
+pub fn resize(image: &DecodedImage, options: &ResizeOptions) -> Result<DecodedImage, DecodeError> {
+    if options.width == 0 || options.height == 0 || image.width == 0 || image.height == 0 {
+        return Err(DecodeError::invalid_argument(format!(
+            "Can't resize a {}x{} image to {}x{}",
+            image.width, image.height, options.width, options.height
+        )));
+    }
+    image.check_len()?;
+    let (width, height) = (image.width as usize, image.height as usize);
+    let (out_width, out_height) = options.output_size(image.width, image.height);
+    let (out_width, out_height) = (out_width as usize, out_height as usize);
+
+    // the part of the image that ends up in the output
+    let (mut span_x, mut span_y) = (width as f32, height as f32);
+    if options.fit == ResizeFit::Cover {
+        let scale = f32::max(
+            out_width as f32 / width as f32,
+            out_height as f32 / height as f32,
+        );
+        span_x = (out_width as f32 / scale).min(span_x);
+        span_y = (out_height as f32 / scale).min(span_y);
+    }
+    let columns = taps(
+        options.filter,
+        width,
+        out_width,
+        (width as f32 - span_x) / 2.0,
+        span_x,
+    );
+    let rows = taps(
+        options.filter,
+        height,
+        out_height,
+        (height as f32 - span_y) / 2.0,
+        span_y,
+    );
+    let samples = Samples::new(image, options.linear_light);
+    let channels = image.channels();
+
+    // filter the rows the output needs horizontally, then the columns vertically
+    let first_row = rows.first().map_or(0, |tap| tap.start);
+    let last_row = rows.last().map_or(0, |tap| tap.start + tap.weights.len());
+    let mut source_row = vec![0.0; width * channels];
+    let mut horizontal = vec![0.0; (last_row - first_row) * out_width * channels];
+
+    for (y, out_row) in (first_row..last_row).zip(horizontal.chunks_exact_mut(out_width * channels))
+    {
+        let row = &image.data[y * width * channels..(y + 1) * width * channels];
+        for (pixel, working) in row
+            .chunks_exact(channels)
+            .zip(source_row.chunks_exact_mut(channels))
+        {
+            samples.load(pixel, working);
+        }
+        for (tap, out_pixel) in columns.iter().zip(out_row.chunks_exact_mut(channels)) {
+            for (k, weight) in tap.weights.iter().enumerate() {
+                let pixel = &source_row[(tap.start + k) * channels..][..channels];
+                for (out, value) in out_pixel.iter_mut().zip(pixel) {
+                    *out += value * weight;
+                }
+            }
+        }
+    }
+    let mut data = vec![0; out_width * out_height * channels];
+    let mut working = vec![0.0; channels];
+
+    for (tap, out_row) in rows.iter().zip(data.chunks_exact_mut(out_width * channels)) {
+        for (x, out_pixel) in out_row.chunks_exact_mut(channels).enumerate() {
+            working.fill(0.0);
+            for (k, weight) in tap.weights.iter().enumerate() {
+                let row = (tap.start + k - first_row) * out_width * channels;
+                let pixel = &horizontal[row + x * channels..][..channels];
+                for (out, value) in working.iter_mut().zip(pixel) {
+                    *out += value * weight;
+                }
+            }
+            samples.store(&working, out_pixel);
+        }
+    }
+    Ok(DecodedImage {
+        data,
+        width: out_width as u32,
+        height: out_height as u32,
+        colorspace: image.colorspace,
+    })
+}
+
+/// Source pixels an output pixel is computed from, and their weights
+struct Tap {
+    start: usize,
+    weights: Vec<f32>,
+}
+
+/// Return the taps of each of the `dst_len` pixels a row or column of `src_len` pixels is
+/// resized to, the output covering `span` pixels of the source from `start` on
+fn taps(filter: ResizeFilter, src_len: usize, dst_len: usize, start: f32, span: f32) -> Vec<Tap> {
+    let scale = span / dst_len as f32;
+    // when shrinking, the filter is stretched over every source pixel an output pixel covers
+    let filter_scale = scale.max(1.0);
+    let support = filter.support() * filter_scale;
+
+    (0..dst_len)
+        .map(|i| {
+            let center = start + (i as f32 + 0.5) * scale;
+            let first = ((center - support).floor().max(0.0) as usize).min(src_len - 1);
+            let last = ((center + support).ceil() as usize).clamp(first + 1, src_len);
+            let mut weights: Vec<f32> = (first..last)
+                .map(|j| filter.weight((j as f32 + 0.5 - center) / filter_scale))
+                .collect();
+            let sum: f32 = weights.iter().sum();
+            if sum == 0.0 {
+                // a box filter can fall between pixels, take the nearest one
+                let nearest = (center.floor() as usize).clamp(first, last - 1);
+                weights.fill(0.0);
+                weights[nearest - first] = 1.0;
+            } else {
+                weights.iter_mut().for_each(|weight| *weight /= sum);
+            }
+            Tap {
+                start: first,
+                weights,
+            }
+        })
+        .collect()
+}
+
+/// Conversion of pixels to the values they are filtered as, and back
+struct Samples {
+    alpha_position: Option<usize>,
+    /// Color sample to filtered value
+    decode: [f32; 256],
+    /// sRGB encoded colors of filtered linear values from 0.0 to 1.0 in
+    /// `LINEAR_STEPS` steps, empty unless filtering in linear light
+    encode: Vec<u8>,
+}
+
+const LINEAR_STEPS: usize = 4096;
+
+impl Samples {
+    fn new(image: &DecodedImage, linear_light: bool) -> Samples {
+        let srgb_to_linear = |value: f32| {
+            if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        let linear_to_srgb = |value: f32| {
+            if value <= 0.003_130_8 {
+                value * 12.92
+            } else {
+                1.055 * value.powf(1.0 / 2.4) - 0.055
+            }
+        };
+        let decode = core::array::from_fn(|value| {
+            let value = value as f32 / 255.0;
+            if linear_light {
+                srgb_to_linear(value)
+            } else {
+                value
+            }
+        });
+        let encode = if linear_light {
+            (0..LINEAR_STEPS)
+                .map(|step| {
+                    let value = linear_to_srgb(step as f32 / (LINEAR_STEPS - 1) as f32);
+                    (value * 255.0).round() as u8
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Samples {
+            alpha_position: image.colorspace.alpha_position(),
+            decode,
+            encode,
+        }
+    }
+
+    /// Convert `pixel` to the values it is filtered as, with colors weighted by alpha
+    fn load(&self, pixel: &[u8], working: &mut [f32]) {
+        let alpha = self
+            .alpha_position
+            .map_or(1.0, |position| f32::from(pixel[position]) / 255.0);
+        for (position, (value, out)) in pixel.iter().zip(working.iter_mut()).enumerate() {
+            *out = if Some(position) == self.alpha_position {
+                alpha
+            } else {
+                self.decode[usize::from(*value)] * alpha
+            };
+        }
+    }
+
+    /// Convert filtered values back to a pixel
+    fn store(&self, working: &[f32], pixel: &mut [u8]) {
+        let alpha = self
+            .alpha_position
+            .map_or(1.0, |position| working[position].clamp(0.0, 1.0));
+        for (position, (value, out)) in working.iter().zip(pixel.iter_mut()).enumerate() {
+            if Some(position) == self.alpha_position {
+                *out = (alpha * 255.0).round() as u8;
+                continue;
+            }
+            let value = if alpha > 0.0 { value / alpha } else { 0.0 }.clamp(0.0, 1.0);
+            *out = if self.encode.is_empty() {
+                (value * 255.0).round() as u8
+            } else {
+                self.encode[(value * (LINEAR_STEPS - 1) as f32).round() as usize]
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zune_core::colorspace::ColorSpace;
+
+    use super::*;
+
+    const FILTERS: [ResizeFilter; 4] = [
+        ResizeFilter::Box,
+        ResizeFilter::Bilinear,
+        ResizeFilter::Lanczos3,
+        ResizeFilter::Mitchell,
+    ];
+
+    fn options(width: u32, height: u32, fit: ResizeFit, filter: ResizeFilter) -> ResizeOptions {
+        ResizeOptions {
+            width,
+            height,
+            fit,
+            filter,
+            linear_light: false,
+        }
+    }
+
+    /// `width`x`height` RGBA image, red on the left half and blue on the right
+    fn halves(width: u32, height: u32) -> DecodedImage {
+        let data = (0..width * height)
+            .flat_map(|i| {
+                if i % width < width / 2 {
+                    [255, 0, 0, 255]
+                } else {
+                    [0, 0, 255, 255]
+                }
+            })
+            .collect();
+        DecodedImage {
+            data,
+            width,
+            height,
+            colorspace: ColorSpace::RGBA,
+        }
+    }
+
+    #[test]
+    fn fit_and_cover_sizes() {
+        let fit = options(100, 100, ResizeFit::Fit, ResizeFilter::default());
+        assert_eq!(fit.output_size(400, 200), (100, 50));
+        assert_eq!(fit.output_size(30, 60), (50, 100));
+        assert_eq!(fit.output_size(1000, 1), (100, 1));
+        let cover = ResizeOptions {
+            fit: ResizeFit::Cover,
+            ..fit
+        };
+        assert_eq!(cover.output_size(400, 200), (100, 100));
+
+        // covering a 4:1 image with a square keeps its middle, half red and half blue
+        let image = resize(&halves(64, 16), &cover).unwrap();
+        assert_eq!((image.width, image.height), (100, 100));
+        assert_eq!(image.data[..4], [255, 0, 0, 255]);
+        assert_eq!(image.data[99 * 4..100 * 4], [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn flat_images_stay_flat() {
+        for filter in FILTERS {
+            for linear_light in [false, true] {
+                for (width, height) in [(7, 5), (64, 48), (1, 1), (200, 3)] {
+                    let image = DecodedImage {
+                        data: [100, 150, 200, 255].repeat(37 * 23),
+                        width: 37,
+                        height: 23,
+                        colorspace: ColorSpace::RGBA,
+                    };
+                    let options = ResizeOptions {
+                        linear_light,
+                        ..options(width, height, ResizeFit::Fill, filter)
+                    };
+                    let resized = resize(&image, &options).unwrap();
+                    assert_eq!((resized.width, resized.height), (width, height));
+                    for pixel in resized.data.chunks_exact(4) {
+                        assert_eq!(pixel, [100, 150, 200, 255], "{filter:?} {linear_light}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_pixels_dont_bleed() {
+        // an opaque white pixel next to a transparent black one
+        let image = DecodedImage {
+            data: vec![255, 255, 255, 255, 0, 0, 0, 0],
+            width: 2,
+            height: 1,
+            colorspace: ColorSpace::RGBA,
+        };
+        for filter in FILTERS {
+            let resized = resize(&image, &options(1, 1, ResizeFit::Fill, filter)).unwrap();
+            assert_eq!(resized.data[..3], [255, 255, 255], "{filter:?}");
+        }
+    }
+
+    #[test]
+    fn linear_light_averages() {
+        // black and white stripes average to mid gray in linear light, 188 in sRGB
+        let image = DecodedImage {
+            data: vec![0, 255, 0, 255],
+            width: 4,
+            height: 1,
+            colorspace: ColorSpace::Luma,
+        };
+        let mut options = options(1, 1, ResizeFit::Fill, ResizeFilter::Box);
+        assert_eq!(resize(&image, &options).unwrap().data, [128]);
+        options.linear_light = true;
+        assert_eq!(resize(&image, &options).unwrap().data, [188]);
+    }
+
+    #[test]
+    fn grayscale_images_resize() {
+        let gray = DecodedImage {
+            data: (0..32).map(|i| if i % 8 < 4 { 40 } else { 200 }).collect(),
+            width: 8,
+            height: 4,
+            colorspace: ColorSpace::Luma,
+        };
+        for filter in FILTERS {
+            let resized = resize(&gray, &options(4, 2, ResizeFit::Fill, filter)).unwrap();
+            assert_eq!(resized.colorspace, ColorSpace::Luma);
+            assert_eq!(resized.data.len(), 4 * 2);
+            assert!(resized
+                .data
+                .chunks_exact(4)
+                .all(|row| row[0] < 100 && row[3] > 140));
+        }
+
+        // grayscale pixels labelled RGBA don't fill the image
+        let mislabelled = DecodedImage {
+            colorspace: ColorSpace::RGBA,
+            ..gray.clone()
+        };
+        let fit = options(4, 2, ResizeFit::Fit, ResizeFilter::default());
+        assert_eq!(
+            resize(&mislabelled, &fit).unwrap_err().code,
+            "InvalidArgument"
+        );
+        assert_eq!(
+            resize(&gray, &options(0, 2, ResizeFit::Fill, ResizeFilter::Box))
+                .unwrap_err()
+                .code,
+            "InvalidArgument"
+        );
+    }
+}
@@ -0,0 +1,305 @@
+//! Tensor output for ML models
+//!
+//! Decoded images are written as the `f32` tensors image models take as input, laid
+//! out as NHWC or NCHW, normalised per channel and optionally resized to the input
+//! size of the model, so callers don't have to re-layout pixels in JS. Like
+//! [`native`](crate::native), this module has no `js_sys` types.
+use zune_core::colorspace::ColorSpace;
+use zune_core::options::DecoderOptions;
+
+use crate::errors::DecodeError;
+use crate::native::{self, DecodedImage};
+
+/// Order of the dimensions of a tensor
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TensorLayout {
+    /// Batch, height, width, channels, the layout of TensorFlow models
+    #[default]
+    Nhwc,
+    /// Batch, channels, height, width, the layout of PyTorch and most ONNX models
+    Nchw,
+}
+
+/// Order of the color channels of a tensor
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChannelOrder {
+    #[default]
+    Rgb,
+    Bgr,
+}
+
+/// How images are written into a tensor
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TensorOptions {
+    pub layout: TensorLayout,
+    pub channel_order: ChannelOrder,
+    /// Subtracted from the samples scaled to 0.0..=1.0, per channel in `channel_order`
+    pub mean: [f32; 3],
+    /// Divides the samples once `mean` is subtracted, per channel in `channel_order`
+    pub std: [f32; 3],
+    /// Width and height images are resized to with bilinear filtering, without it all
+    /// images of a batch must have the same size
+    pub size: Option<(u32, u32)>,
+}
+
+impl Default for TensorOptions {
+    /// Samples from 0.0 to 1.0 in RGB order, laid out as NHWC without resizing
+    fn default() -> Self {
+        TensorOptions {
+            layout: TensorLayout::default(),
+            channel_order: ChannelOrder::default(),
+            mean: [0.0; 3],
+            std: [1.0; 3],
+            size: None,
+        }
+    }
+}
+
+/// Number of channels of a tensor, images are always written as three color channels
+pub const TENSOR_CHANNELS: usize = 3;
+
+/// Size of each dimension of a batch tensor
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TensorShape {
+    pub layout: TensorLayout,
+    pub batch: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl TensorShape {
+    /// The dimensions in the order of `layout`
+    pub const fn dims(&self) -> [usize; 4] {
+        match self.layout {
+            TensorLayout::Nhwc => [self.batch, self.height, self.width, TENSOR_CHANNELS],
+            TensorLayout::Nchw => [self.batch, TENSOR_CHANNELS, self.height, self.width],
+        }
+    }
+
+    /// Number of samples of an image of the batch
+    pub const fn image_len(&self) -> usize {
+        self.height * self.width * TENSOR_CHANNELS
+    }
+}
+
+/// Writes images one after the other into a batch tensor
+///
+/// The tensor is written to a caller owned vector, so decoding batch after batch
+/// into the same vector only allocates when a batch is larger than the ones before.
+pub struct TensorBatch<'a> {
+    options: TensorOptions,
+    data: &'a mut Vec<f32>,
+    shape: TensorShape,
+}
+
+impl<'a> TensorBatch<'a> {
+    /// Start an empty batch, clearing `data`
+    pub fn new(options: TensorOptions, data: &'a mut Vec<f32>) -> TensorBatch<'a> {
+        data.clear();
+        let (width, height) = options.size.unwrap_or_default();
+        TensorBatch {
+            options,
+            data,
+            shape: TensorShape {
+                layout: options.layout,
+                batch: 0,
+                height: height as usize,
+                width: width as usize,
+            },
+        }
+    }
+
+    /// Append `image` to the batch, resizing it if the options set a size
+    ///
+    /// # Errors
+    /// Without a size, `image` isn't the size of the images before it.
+    pub fn push(&mut self, image: &DecodedImage) -> Result<(), DecodeError> {
+        if self.options.size.is_none() {
+            let size = (image.width as usize, image.height as usize);
+            if self.shape.batch == 0 {
+                (self.shape.width, self.shape.height) = size;
+            } else if size != (self.shape.width, self.shape.height) {
+                return Err(DecodeError::invalid_argument(format!(
+                    "Image {} of the batch is {}x{}, the images before it are {}x{}, \
+                     set a tensor size to resize them",
+                    self.shape.batch, size.0, size.1, self.shape.width, self.shape.height
+                )));
+            }
+        }
+        let start = self.data.len();
+        self.data.resize(start + self.shape.image_len(), 0.0);
+        write_tensor(
+            image,
+            &self.options,
+            self.shape.width,
+            self.shape.height,
+            &mut self.data[start..],
+        );
+        self.shape.batch += 1;
+        Ok(())
+    }
+
+    pub const fn shape(&self) -> TensorShape {
+        self.shape
+    }
+}
+
+/// Decode `encoded` images like [`native::decode`] does and write them in order
+/// into `data` as a batch tensor
+pub fn decode_tensor<T: AsRef<[u8]>>(
+    encoded: &[T],
+    use_fast_jpeg: bool,
+    decode_options: &DecoderOptions,
+    options: TensorOptions,
+    data: &mut Vec<f32>,
+) -> Result<TensorShape, DecodeError> {
+    let mut batch = TensorBatch::new(options, data);
+    for encoded in encoded {
+        batch.push(&native::decode(
+            encoded.as_ref(),
+            use_fast_jpeg,
+            decode_options,
+        )?)?;
+    }
+    Ok(batch.shape())
+}
+
+/// Write `image` resized to `width`x`height` into `out`, which holds a single image
+/// of the tensor
+fn write_tensor(
+    image: &DecodedImage,
+    options: &TensorOptions,
+    width: usize,
+    height: usize,
+    out: &mut [f32],
+) {
+    // positions of red, green and blue in a pixel of the image
+    let rgb = match image.colorspace {
+        ColorSpace::BGR | ColorSpace::BGRA => [2, 1, 0],
+        ColorSpace::Luma => [0, 0, 0],
+        _ => [0, 1, 2],
+    };
+    let channels = match options.channel_order {
+        ChannelOrder::Rgb => rgb,
+        ChannelOrder::Bgr => [rgb[2], rgb[1], rgb[0]],
+    };
+    let image_width = image.width as usize;
+    let pixel_size = image.channels();
+    let sample = |x: usize, y: usize, channel: usize| {
+        f32::from(image.data[(y * image_width + x) * pixel_size + channel])
+    };
+    let lerp = |a: f32, b: f32, weight: f32| a + (b - a) * weight;
+    let columns = bilinear_taps(image_width, width);
+    let plane = width * height;
+
+    for (y, &(top, bottom, weight_y)) in bilinear_taps(image.height as usize, height)
+        .iter()
+        .enumerate()
+    {
+        for (x, &(left, right, weight_x)) in columns.iter().enumerate() {
+            for (c, &channel) in channels.iter().enumerate() {
+                let upper = lerp(
+                    sample(left, top, channel),
+                    sample(right, top, channel),
+                    weight_x,
+                );
+                let lower = lerp(
+                    sample(left, bottom, channel),
+                    sample(right, bottom, channel),
+                    weight_x,
+                );
+                let index = match options.layout {
+                    TensorLayout::Nhwc => (y * width + x) * TENSOR_CHANNELS + c,
+                    TensorLayout::Nchw => c * plane + y * width + x,
+                };
+                let value = lerp(upper, lower, weight_y) / 255.0;
+                out[index] = (value - options.mean[c]) / options.std[c];
+            }
+        }
+    }
+}
+
+/// For each of the `dst` pixels a row or column of `src` pixels is resized to,
+/// the two source pixels it lies between and the weight of the second one
+///
+/// Pixel centers are lined up, so resizing to the same size gives every pixel
+/// its own source pixel.
+fn bilinear_taps(src: usize, dst: usize) -> Vec<(usize, usize, f32)> {
+    let scale = src as f32 / dst as f32;
+    (0..dst)
+        .map(|i| {
+            let position = ((i as f32 + 0.5) * scale - 0.5).clamp(0.0, (src - 1) as f32);
+            let first = position as usize;
+            (first, (first + 1).min(src - 1), position - first as f32)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2x2 RGBA image with a different color in each pixel
+    fn quad() -> DecodedImage {
+        DecodedImage {
+            data: vec![
+                255, 0, 0, 255, 0, 255, 0, 255, //
+                0, 0, 255, 255, 255, 255, 255, 255,
+            ],
+            width: 2,
+            height: 2,
+            colorspace: ColorSpace::RGBA,
+        }
+    }
+
+    #[test]
+    fn layouts_and_channel_orders() {
+        let mut data = Vec::new();
+        let mut batch = TensorBatch::new(TensorOptions::default(), &mut data);
+        batch.push(&quad()).unwrap();
+        assert_eq!(batch.shape().dims(), [1, 2, 2, 3]);
+        assert_eq!(data, [1., 0., 0., 0., 1., 0., 0., 0., 1., 1., 1., 1.]);
+
+        let options = TensorOptions {
+            layout: TensorLayout::Nchw,
+            channel_order: ChannelOrder::Bgr,
+            mean: [0.5, 0.0, 0.0],
+            std: [0.5, 1.0, 2.0],
+            ..TensorOptions::default()
+        };
+        let mut batch = TensorBatch::new(options, &mut data);
+        batch.push(&quad()).unwrap();
+        assert_eq!(batch.shape().dims(), [1, 3, 2, 2]);
+        // blue planes, then green, then red, normalised per channel
+        assert_eq!(data, [-1., -1., 1., 1., 0., 1., 0., 1., 0.5, 0., 0., 0.5]);
+    }
+
+    #[test]
+    fn resize_and_batch_sizes() {
+        let mut data = Vec::new();
+        let mut batch = TensorBatch::new(TensorOptions::default(), &mut data);
+        batch.push(&quad()).unwrap();
+        let mut gray = DecodedImage {
+            data: vec![51; 3 * 2],
+            width: 3,
+            height: 2,
+            colorspace: ColorSpace::Luma,
+        };
+        assert!(batch.push(&gray).is_err());
+
+        let options = TensorOptions {
+            size: Some((4, 1)),
+            ..TensorOptions::default()
+        };
+        let mut batch = TensorBatch::new(options, &mut data);
+        batch.push(&quad()).unwrap();
+        gray.data[0] = 255;
+        batch.push(&gray).unwrap();
+        assert_eq!(batch.shape().dims(), [2, 1, 4, 3]);
+        // the single row lies halfway between the two rows of the image
+        assert_eq!(data[..3], [0.5, 0.0, 0.5]);
+        assert_eq!(data[9..12], [0.5, 1.0, 0.5]);
+        // gray is copied to every channel
+        assert!(data[12..].chunks(3).all(|pixel| pixel[0] == pixel[1]));
+    }
+}
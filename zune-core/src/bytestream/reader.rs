@@ -68,6 +68,13 @@ impl<T: ZReaderTrait> ZByteReader<T> {
     pub fn consume(self) -> T {
         self.stream
     }
+    /// Get a mutable reference to the underlying source of the bytes
+    ///
+    /// This allows a growable source, e.g. a `Vec<u8>`, to be appended to
+    /// between reads
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.stream
+    }
     /// Skip `num` bytes ahead of the stream.
     ///
     /// This bumps up the internal cursor wit a wrapping addition
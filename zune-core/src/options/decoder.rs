@@ -9,7 +9,7 @@
 //! Global Decoder options
 #![allow(clippy::zero_prefixed_literal)]
 
-use crate::bit_depth::ByteEndian;
+use crate::bit_depth::{BitDepth, ByteEndian};
 use crate::colorspace::ColorSpace;
 
 fn decoder_strict_mode() -> DecoderFlags {
@@ -28,7 +28,10 @@ fn decoder_strict_mode() -> DecoderFlags {
         png_add_alpha_channel:     false,
         png_strip_16_bit_to_8_bit: false,
         png_decode_animated:       true,
-        jxl_decode_animated:       true
+        jxl_decode_animated:       true,
+        apply_orientation:         false,
+        flip_vertically:           false,
+        premultiply_alpha:         false
     }
 }
 
@@ -54,7 +57,10 @@ fn fast_options() -> DecoderFlags {
         png_add_alpha_channel:     false,
         png_strip_16_bit_to_8_bit: false,
         png_decode_animated:       true,
-        jxl_decode_animated:       true
+        jxl_decode_animated:       true,
+        apply_orientation:         false,
+        flip_vertically:           false,
+        premultiply_alpha:         false
     }
 }
 
@@ -82,7 +88,10 @@ fn cmd_options() -> DecoderFlags {
         png_strip_16_bit_to_8_bit: false,
 
         png_decode_animated: true,
-        jxl_decode_animated: true
+        jxl_decode_animated: true,
+        apply_orientation:   false,
+        flip_vertically:     false,
+        premultiply_alpha:   false
     }
 }
 
@@ -123,7 +132,13 @@ pub struct DecoderFlags {
     png_strip_16_bit_to_8_bit:    bool,
     /// Decode all frames for an animated images
     png_decode_animated:          bool,
-    jxl_decode_animated:          bool
+    jxl_decode_animated:          bool,
+    /// Whether decoders should turn images upright using their EXIF orientation
+    apply_orientation:            bool,
+    /// Whether decoders should write rows bottom to top
+    flip_vertically:              bool,
+    /// Whether decoders should multiply color channels by alpha
+    premultiply_alpha:            bool
 }
 
 /// Decoder options
@@ -145,6 +160,20 @@ pub struct DecoderOptions {
     /// - Default value: 16384
     /// - Respected by: `all decoders`
     max_height:     usize,
+    /// Maximum number of pixels, width times height, for which
+    /// decoders will not try to decode images with more pixels
+    ///
+    /// - Default value: 268435456 (16384 x 16384)
+    /// - Respected by: `all decoders`
+    max_pixels:     usize,
+    /// Maximum number of bytes decoders may allocate to decode an image
+    ///
+    /// Decoders that can tell how much they need from the headers fail before
+    /// allocating anything large.
+    ///
+    /// - Default value: 2 GiB
+    /// - Respected by: `jpeg`
+    max_memory:     usize,
     /// Output colorspace
     ///
     /// The jpeg decoder allows conversion to a separate colorspace
@@ -164,15 +193,28 @@ pub struct DecoderOptions {
     ///
     /// - Default value:100
     /// - Respected by: `jpeg`
-    max_scans:     usize,
+    max_scans:         usize,
+    /// Denominator of the scale images are decoded at
+    ///
+    /// An image is decoded at `1/scale_denominator` of its size
+    /// in each dimension.
+    ///
+    /// - Default value: 1
+    /// - Respected by: `jpeg`, which supports 1, 2, 4 and 8
+    scale_denominator: usize,
+    /// Depth of the output samples
+    ///
+    /// - Default value: `BitDepth::Eight`
+    /// - Respected by: `jpeg`, which supports `Eight` and `Float32`
+    out_depth:         BitDepth,
     /// Maximum size for deflate.
     /// Respected by all decoders that use inflate/deflate
-    deflate_limit: usize,
+    deflate_limit:     usize,
     /// Boolean flags that influence decoding
-    flags:         DecoderFlags,
+    flags:             DecoderFlags,
     /// The byte endian of the returned bytes will be stored in
     /// in case a single pixel spans more than a byte
-    endianness:    ByteEndian
+    endianness:        ByteEndian
 }
 
 /// Initializers
@@ -223,6 +265,17 @@ impl DecoderOptions {
         self.max_height
     }
 
+    /// Get the maximum number of pixels configured for which the decoder
+    /// should not try to decode images with more pixels
+    pub const fn get_max_pixels(&self) -> usize {
+        self.max_pixels
+    }
+
+    /// Get the maximum number of bytes the decoder may allocate to decode an image
+    pub const fn get_max_memory(&self) -> usize {
+        self.max_memory
+    }
+
     /// Return true whether the decoder should be in strict mode
     /// And reject most errors
     pub fn get_strict_mode(&self) -> bool {
@@ -262,6 +315,41 @@ impl DecoderOptions {
         self
     }
 
+    /// Set the maximum number of pixels, width times height, for which the
+    /// decoder should not try decoding images with more pixels
+    ///
+    /// This catches images that are within the width and height limits but
+    /// still too large, e.g. 16384x16384 ones with the default limits.
+    ///
+    /// # Arguments
+    ///
+    /// * `pixels`: The maximum number of pixels allowed
+    ///
+    /// returns: DecoderOptions
+    #[must_use]
+    pub const fn set_max_pixels(mut self, pixels: usize) -> Self {
+        self.max_pixels = pixels;
+        self
+    }
+
+    /// Set the maximum number of bytes the decoder may allocate to decode an image
+    ///
+    /// This counts the output buffer along with the decoder's own buffers, e.g.
+    /// the coefficients a progressive jpeg keeps for all its scans. Decoders that
+    /// respect it compute what they need from the headers and error out before
+    /// allocating, so images that would exhaust memory are rejected cheaply.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes`: The maximum number of bytes allowed
+    ///
+    /// returns: DecoderOptions
+    #[must_use]
+    pub const fn set_max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = bytes;
+        self
+    }
+
     /// Whether the routines can use unsafe platform specific
     /// intrinsics when necessary
     ///
@@ -327,6 +415,61 @@ impl DecoderOptions {
     pub const fn get_byte_endian(&self) -> ByteEndian {
         self.endianness
     }
+
+    /// Set whether the decoder should write images upright, rotating or
+    /// flipping them as their EXIF orientation tag says
+    ///
+    /// The dimensions reported by the decoder are those of the upright image,
+    /// so width and height are swapped for images that are rotated by 90 degrees.
+    ///
+    /// - Default value: false
+    /// - Respected by: `jpeg`
+    #[must_use]
+    pub const fn set_apply_orientation(mut self, yes: bool) -> Self {
+        self.flags.apply_orientation = yes;
+        self
+    }
+
+    /// Return true if the decoder should write images upright using their
+    /// EXIF orientation
+    pub const fn get_apply_orientation(&self) -> bool {
+        self.flags.apply_orientation
+    }
+
+    /// Set whether the decoder should write the rows of the image bottom to top
+    ///
+    /// This is the layout OpenGL and WebGL expect texture uploads in. Flipping is
+    /// done after the image is turned upright, see
+    /// [`set_apply_orientation`](Self::set_apply_orientation).
+    ///
+    /// - Default value: false
+    /// - Respected by: `jpeg`
+    #[must_use]
+    pub const fn set_flip_vertically(mut self, yes: bool) -> Self {
+        self.flags.flip_vertically = yes;
+        self
+    }
+
+    /// Return true if the decoder should write rows bottom to top
+    pub const fn get_flip_vertically(&self) -> bool {
+        self.flags.flip_vertically
+    }
+
+    /// Set whether the decoder should multiply the color channels of each pixel by
+    /// its alpha, for output colorspaces with an alpha channel
+    ///
+    /// - Default value: false
+    /// - Respected by: `jpeg`
+    #[must_use]
+    pub const fn set_premultiply_alpha(mut self, yes: bool) -> Self {
+        self.flags.premultiply_alpha = yes;
+        self
+    }
+
+    /// Return true if the decoder should premultiply color channels by alpha
+    pub const fn get_premultiply_alpha(&self) -> bool {
+        self.flags.premultiply_alpha
+    }
 }
 
 /// PNG specific options
@@ -440,6 +583,37 @@ impl DecoderOptions {
         self.out_colorspace = colorspace;
         self
     }
+    /// Get the denominator of the scale the jpeg is decoded at
+    pub const fn jpeg_get_scale_denominator(&self) -> usize {
+        self.scale_denominator
+    }
+    /// Decode the jpeg at `1/denominator` of its width and height
+    ///
+    /// This is done in the IDCT, so it is a lot faster than decoding the full
+    /// image and resizing it. The decoder supports denominators of 1, 2, 4 and 8
+    /// and errors out on others.
+    #[must_use]
+    pub fn jpeg_set_scale_denominator(mut self, denominator: usize) -> Self {
+        self.scale_denominator = denominator;
+        self
+    }
+    /// Get the depth of the samples the jpeg is decoded to
+    pub const fn jpeg_get_out_depth(&self) -> BitDepth {
+        self.out_depth
+    }
+    /// Set the depth of the samples the jpeg is decoded to
+    ///
+    /// With `BitDepth::Float32` samples are `f32`s from 0.0 to 1.0, written to output
+    /// buffers as bytes in the order set by [`set_byte_endian`](Self::set_byte_endian).
+    /// The IDCT still outputs 8 bit integers, only the color conversion is done in
+    /// floating point, so converted samples skip the rounding after it while Luma
+    /// samples are the 8 bit ones divided by 255.
+    /// The decoder supports `Eight` and `Float32` and errors out on others.
+    #[must_use]
+    pub fn jpeg_set_out_depth(mut self, depth: BitDepth) -> Self {
+        self.out_depth = depth;
+        self
+    }
 }
 
 /// Intrinsics support
@@ -645,13 +819,17 @@ impl DecoderOptions {
 impl Default for DecoderOptions {
     fn default() -> Self {
         Self {
-            out_colorspace: ColorSpace::RGB,
-            max_width:      1 << 14,
-            max_height:     1 << 14,
-            max_scans:      100,
-            deflate_limit:  1 << 30,
-            flags:          decoder_strict_mode(),
-            endianness:     ByteEndian::BE
+            out_colorspace:    ColorSpace::RGB,
+            max_width:         1 << 14,
+            max_height:        1 << 14,
+            max_pixels:        1 << 28,
+            max_memory:        1 << 31,
+            max_scans:         100,
+            scale_denominator: 1,
+            out_depth:         BitDepth::Eight,
+            deflate_limit:     1 << 30,
+            flags:             decoder_strict_mode(),
+            endianness:        ByteEndian::BE
         }
     }
 }
@@ -55,6 +55,14 @@ pub(crate) struct Components {
     pub up_sampler: UpSampler,
     /// How pixels do we need to go to get to the next line?
     pub width_stride: usize,
+    /// Width in pixels of a decoded 8x8 block, 8 unless
+    /// decoding at a reduced scale
+    pub block_width: usize,
+    /// Height in pixels of a decoded 8x8 block
+    pub block_height: usize,
+    /// Number of 8x8 blocks in a row of the whole image, `width_stride`
+    /// may only cover some of them when decoding a region
+    pub blocks_per_row: usize,
     /// Component ID for progressive
     pub id: u8,
     /// Whether we need to decode this image component.
@@ -145,6 +153,9 @@ impl Components {
             up_sampler: upsample_no_op,
             // set later
             width_stride: horizontal_sample,
+            block_width: 8,
+            block_height: 8,
+            blocks_per_row: 0,
             id: a[0],
             needed: true,
             raw_coeff: vec![],
@@ -170,15 +181,41 @@ impl Components {
     /// # Requirements
     ///  - width stride of this element is set for the component.
     pub fn setup_upsample_scanline(&mut self) {
-        self.row = vec![0; self.width_stride * self.vertical_sample];
-        self.row_up = vec![0; self.width_stride * self.vertical_sample];
-        self.first_row_upsample_dest =
-            vec![128; self.vertical_sample * self.width_stride * self.sample_ratio.sample()];
-        self.upsample_dest =
-            vec![0; self.width_stride * self.sample_ratio.sample() * self.fix_an_annoying_bug * 8];
+        let row_len = self.width_stride * self.vertical_sample;
+        reset_buffer(&mut self.row, row_len, 0);
+        reset_buffer(&mut self.row_up, row_len, 0);
+        reset_buffer(
+            &mut self.first_row_upsample_dest,
+            self.vertical_sample * self.width_stride * self.sample_ratio.sample(),
+            128
+        );
+        reset_buffer(
+            &mut self.upsample_dest,
+            self.width_stride
+                * self.sample_ratio.sample()
+                * self.fix_an_annoying_bug
+                * self.block_height,
+            0
+        );
+    }
+    /// Move the scratch buffers of a component from a previous image
+    /// into this one, so that their allocations can be reused.
+    pub fn reuse_buffers(&mut self, previous: &mut Components) {
+        self.raw_coeff = core::mem::take(&mut previous.raw_coeff);
+        self.upsample_dest = core::mem::take(&mut previous.upsample_dest);
+        self.row_up = core::mem::take(&mut previous.row_up);
+        self.row = core::mem::take(&mut previous.row);
+        self.first_row_upsample_dest = core::mem::take(&mut previous.first_row_upsample_dest);
     }
 }
 
+/// Resize `buffer` to `len` elements all set to `value`,
+/// keeping its allocation if it is already big enough.
+pub(crate) fn reset_buffer(buffer: &mut Vec<i16>, len: usize, value: i16) {
+    buffer.clear();
+    buffer.resize(len, value);
+}
+
 /// Component ID's
 #[derive(Copy, Debug, Clone, PartialEq, Eq)]
 pub enum ComponentID {
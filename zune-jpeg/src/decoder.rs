@@ -12,7 +12,10 @@
 use alloc::string::ToString;
 use alloc::vec::Vec;
 use alloc::{format, vec};
+use core::cmp::min;
+use core::ops::Range;
 
+use zune_core::bit_depth::{BitDepth, ByteEndian};
 use zune_core::bytestream::{ZByteReader, ZReaderTrait};
 use zune_core::colorspace::ColorSpace;
 use zune_core::log::{error, trace, warn};
@@ -22,12 +25,18 @@ use crate::color_convert::choose_ycbcr_to_rgb_convert_func;
 use crate::components::{Components, SampleRatios};
 use crate::errors::{DecodeErrors, UnsupportedSchemes};
 use crate::headers::{
-    parse_app1, parse_app14, parse_app2, parse_dqt, parse_huffman, parse_sos, parse_start_of_frame
+    parse_app0, parse_app1, parse_app14, parse_app2, parse_dqt, parse_huffman, parse_sos,
+    parse_start_of_frame
 };
 use crate::huffman::HuffmanTable;
 use crate::idct::choose_idct_func;
 use crate::marker::Marker;
 use crate::misc::SOFMarkers;
+use crate::orientation::{
+    apply_orientation, flipped_vertically, parse_exif_orientation, stored_region,
+    swaps_dimensions
+};
+use crate::planar::{plane_layout, PlanarImage, Plane, PlaneLayout};
 use crate::upsampler::{
     choose_horizontal_samp_function, choose_hv_samp_function, choose_v_samp_function,
     upsample_no_op
@@ -138,6 +147,10 @@ pub struct JpegDecoder<T: ZReaderTrait> {
     pub(crate) todo:             usize,
     // decoder options
     pub(crate) options:          DecoderOptions,
+    // options as set by the user, the decoder may override some of them
+    // in `options` for a specific image, e.g. the output colorspace of a
+    // grayscale image, so keep these around to restore on `reset`
+    pub(crate) user_options:     DecoderOptions,
     // byte-stream
     pub(crate) stream:           ZByteReader<T>,
     // Indicate whether headers have been decoded
@@ -146,9 +159,17 @@ pub struct JpegDecoder<T: ZReaderTrait> {
     // exif data, lifted from app2
     pub(crate) exif_data:        Option<Vec<u8>>,
 
-    pub(crate) icc_data: Vec<ICCChunk>,
-    pub(crate) is_mjpeg: bool,
-    pub(crate) coeff:    usize // Solves some weird bug :)
+    pub(crate) icc_data:        Vec<ICCChunk>,
+    pub(crate) is_mjpeg:        bool,
+    // color transform of the Adobe APP14 segment
+    pub(crate) adobe_transform: Option<u8>,
+    pub(crate) coeff:           usize, // Solves some weird bug :)
+    // part of the image being decoded, see decode_region_into
+    pub(crate) region:          Option<Region>,
+    // layout and planes being written, see decode_planar_into
+    pub(crate) planar:          Option<(PlaneLayout, Vec<Plane>)>,
+    // bytes from the start of an output row to the next, see decode_into_layout
+    pub(crate) out_stride:      Option<usize>
 }
 
 impl<T> JpegDecoder<T>
@@ -186,13 +207,18 @@ where
             restart_interval:  0,
             todo:              0x7fff_ffff,
             options:           options,
+            user_options:      options,
             stream:            ZByteReader::new(buffer),
             headers_decoded:   false,
             seen_sof:          false,
             exif_data:         None,
             icc_data:          vec![],
             is_mjpeg:          false,
-            coeff:             1
+            adobe_transform:   None,
+            coeff:             1,
+            region:            None,
+            planar:            None,
+            out_stride:        None
         }
     }
     /// Decode a buffer already in memory
@@ -210,6 +236,32 @@ where
         Ok(out)
     }
 
+    /// Decode a buffer already in memory into `f32` samples from 0.0 to 1.0
+    ///
+    /// This is [`decode`](Self::decode) with the output depth set to `BitDepth::Float32`,
+    /// see [`DecoderOptions::jpeg_set_out_depth`] for the precision of the samples.
+    /// The samples are decoded as bytes and then copied into the returned vector, set
+    /// the depth and use [`decode_into`](Self::decode_into) to avoid the copy.
+    ///
+    /// # Errors
+    /// See DecodeErrors for an explanation
+    pub fn decode_f32(&mut self) -> Result<Vec<f32>, DecodeErrors> {
+        let depth = self.options.jpeg_get_out_depth();
+        let endian = self.options.get_byte_endian();
+
+        self.options = self
+            .options
+            .jpeg_set_out_depth(BitDepth::Float32)
+            .set_byte_endian(ByteEndian::LE);
+        let decoded = self.decode();
+        self.options = self.options.jpeg_set_out_depth(depth).set_byte_endian(endian);
+
+        Ok(decoded?
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect())
+    }
+
     /// Create a new Decoder instance
     ///
     /// # Arguments
@@ -254,16 +306,65 @@ where
     #[must_use]
     pub fn output_buffer_size(&self) -> Option<usize> {
         return if self.headers_decoded {
-            Some(
-                usize::from(self.width())
-                    .checked_mul(usize::from(self.height()))?
-                    .checked_mul(self.options.jpeg_get_out_colorspace().num_components())?
-            )
+            let (width, height) = self.output_dimensions()?;
+
+            Some(width.checked_mul(height)?.checked_mul(self.bytes_per_pixel())?)
         } else {
             None
         };
     }
 
+    /// Return the number of bytes decoding the image needs at its peak, with the
+    /// current options
+    ///
+    /// This is computed from the headers, counting the output buffer
+    /// [`decode`](Self::decode) allocates, the copy images turned upright are decoded
+    /// into, the coefficients a progressive image keeps for all its scans and the
+    /// buffers of an MCU row. The decoder errors out before allocating any of it if it
+    /// is more than [`DecoderOptions::set_max_memory`] allows.
+    ///
+    /// # Returns
+    ///  - `Some(usize)`: Number of bytes needed to decode the image
+    ///  - `None`: Indicates the image headers weren't decoded, or the size would overflow a usize
+    #[must_use]
+    pub fn peak_memory(&self) -> Option<usize> {
+        let (width, height) = self.dimensions()?;
+        let h_max = self.components.iter().map(|c| c.horizontal_sample).max()?;
+        let v_max = self.components.iter().map(|c| c.vertical_sample).max()?;
+        // u64 so this doesn't overflow on 32 bit targets before the final check
+        let mcu_x = ((width + h_max * 8 - 1) / (h_max * 8)) as u64;
+        let mcu_y = ((height + v_max * 8 - 1) / (v_max * 8)) as u64;
+        let output = self.output_buffer_size()? as u64;
+        let mut total = output;
+
+        if self.applied_orientation().is_some() {
+            total += output;
+        }
+        for component in &self.components {
+            let blocks_per_row = mcu_x * component.horizontal_sample as u64;
+            // coefficients, up-sampled and scratch rows of an MCU row, all i16
+            total += blocks_per_row * (h_max * v_max * 64 * 4 * 2) as u64;
+
+            if self.is_progressive {
+                let blocks = blocks_per_row * mcu_y * component.vertical_sample as u64;
+                total += blocks * 64 * 2;
+            }
+        }
+        usize::try_from(total).ok()
+    }
+
+    /// Error out if decoding the image needs more memory than the options allow,
+    /// see [`peak_memory`](Self::peak_memory)
+    fn check_memory_limit(&self) -> Result<(), DecodeErrors> {
+        let limit = self.options.get_max_memory();
+        let needed = self.peak_memory().unwrap_or(usize::MAX);
+
+        if needed > limit {
+            return Err(DecodeErrors::MemoryLimit(needed, limit));
+        }
+        Ok(())
+    }
+
     /// Get a mutable reference to the decoder options
     /// for the decoder instance
     ///
@@ -325,6 +426,36 @@ where
     /// ```
     pub fn set_options(&mut self, options: DecoderOptions) {
         self.options = options;
+        self.user_options = options;
+    }
+    /// Reset the decoder to decode a new image from `stream`
+    ///
+    /// This clears all state from the previous image, (headers, tables,
+    /// metadata) and restores the options set by the user, but keeps
+    /// the per-component scratch buffers around so that decoding a
+    /// stream of similarly sized images doesn't re-allocate them
+    /// for every image.
+    ///
+    /// # Returns
+    /// The previous input stream, so that its allocation can be reused
+    ///
+    /// # Example
+    /// ```no_run
+    /// use zune_jpeg::JpegDecoder;
+    ///
+    /// let mut decoder = JpegDecoder::new(std::fs::read("first.jpg").unwrap());
+    /// let first = decoder.decode().unwrap();
+    ///
+    /// // the buffer holding `first.jpg` is handed back
+    /// let first_input = decoder.reset(std::fs::read("second.jpg").unwrap());
+    /// let second = decoder.decode().unwrap();
+    /// ```
+    pub fn reset(&mut self, stream: T) -> T {
+        let components = core::mem::take(&mut self.components);
+        let fresh = JpegDecoder::default(self.user_options, stream);
+        let previous = core::mem::replace(self, fresh);
+        self.components = components;
+        previous.stream.consume()
     }
     /// Decode Decoder headers
     ///
@@ -344,7 +475,8 @@ where
     fn decode_headers_internal(&mut self) -> Result<(), DecodeErrors> {
         if self.headers_decoded {
             trace!("Headers decoded!");
-            return Ok(());
+            // options may have changed since, e.g. the output colorspace
+            return self.check_memory_limit();
         }
         // match output colorspace here
         // we know this will only be called once per image
@@ -354,6 +486,21 @@ where
         // May god help you
         let out_colorspace = self.options.jpeg_get_out_colorspace();
 
+        let scale_denominator = self.options.jpeg_get_scale_denominator();
+
+        if !matches!(scale_denominator, 1 | 2 | 4 | 8) {
+            return Err(DecodeErrors::Format(format!(
+                "Unsupported scale 1/{scale_denominator}, the scale denominator should be 1, 2, 4 or 8"
+            )));
+        }
+        let out_depth = self.options.jpeg_get_out_depth();
+
+        if !matches!(out_depth, BitDepth::Eight | BitDepth::Float32) {
+            return Err(DecodeErrors::Format(format!(
+                "Unsupported output depth {out_depth:?}, the depth should be Eight or Float32"
+            )));
+        }
+
         if matches!(
             out_colorspace,
             ColorSpace::BGR | ColorSpace::BGRA | ColorSpace::RGB | ColorSpace::RGBA
@@ -426,7 +573,7 @@ where
                     if n == Marker::SOS {
                         self.headers_decoded = true;
                         trace!("Input colorspace {:?}", self.input_colorspace);
-                        return Ok(());
+                        return self.check_memory_limit();
                     }
                 } else {
                     bytes_before_marker = 0;
@@ -469,7 +616,8 @@ where
             }
             // Start of Frame Segments not supported
             Marker::SOF(v) => {
-                let feature = UnsupportedSchemes::from_int(v);
+                // from_int takes the marker byte, not the frame type
+                let feature = UnsupportedSchemes::from_int(0xC0 + v);
 
                 if let Some(feature) = feature {
                     return Err(DecodeErrors::Unsupported(feature));
@@ -479,25 +627,7 @@ where
             }
             //APP(0) segment
             Marker::APP(0) => {
-                let mut length = self.stream.get_u16_be_err()?;
-
-                if length < 2 {
-                    return Err(DecodeErrors::Format(format!(
-                        "Found a marker with invalid length:{length}\n"
-                    )));
-                }
-                // skip for now
-                if length > 5 && self.stream.has(5) {
-                    let mut buffer = [0u8; 5];
-                    self.stream.read_exact(&mut buffer).unwrap();
-                    if &buffer == b"AVI1\0" {
-                        self.is_mjpeg = true;
-                    }
-                    length -= 5;
-                }
-                self.stream.skip(length.saturating_sub(2) as usize);
-
-                //parse_app(buf, m, &mut self.info)?;
+                parse_app0(self)?;
             }
             Marker::APP(1) => {
                 parse_app1(self)?;
@@ -524,7 +654,12 @@ where
             }
             Marker::EOI => return Err(DecodeErrors::FormatStatic("Premature End of image")),
 
-            Marker::DAC | Marker::DNL => {
+            Marker::DNL => {
+                return Err(DecodeErrors::Unsupported(
+                    UnsupportedSchemes::DefineNumberOfLines
+                ));
+            }
+            Marker::DAC => {
                 return Err(DecodeErrors::Format(format!(
                     "Parsing of the following header `{m:?}` is not supported,\
                                 cannot continue"
@@ -635,6 +770,52 @@ where
     pub fn exif(&self) -> Option<&Vec<u8>> {
         return self.exif_data.as_ref();
     }
+
+    /// Return whether the image is a motion jpeg frame, i.e. it has an
+    /// `AVI1` APP0 segment
+    ///
+    /// Such frames usually leave out the huffman tables, the decoder
+    /// fills in the standard ones.
+    #[must_use]
+    pub const fn is_mjpeg(&self) -> bool {
+        self.is_mjpeg
+    }
+
+    /// Return the color transform of the Adobe APP14 segment
+    ///
+    /// # Returns
+    /// - `Some(0)`: Components are not transformed, i.e. RGB or CMYK
+    /// - `Some(1)`: Components are YCbCr
+    /// - `Some(2)`: Components are YCCK
+    /// - None: The image has no Adobe segment or the headers haven't been decoded
+    #[must_use]
+    pub const fn adobe_transform(&self) -> Option<u8> {
+        self.adobe_transform
+    }
+
+    /// Return the EXIF orientation of the image
+    ///
+    /// This is a value between 1 and 8 saying how the decoded pixels have
+    /// to be rotated or flipped for the image to be upright, 1 meaning they are already
+    /// upright. The decoder does that itself if [`DecoderOptions::set_apply_orientation`]
+    /// is set.
+    ///
+    /// # Returns
+    /// -`Some(orientation)`: The orientation tag of the exif data
+    /// - None: The image headers haven't been decoded, or the image has no valid orientation tag
+    #[must_use]
+    pub fn orientation(&self) -> Option<u16> {
+        parse_exif_orientation(self.exif_data.as_ref()?)
+    }
+
+    /// The orientation the decoder has to turn the image upright from,
+    /// `None` if it is written as it is stored
+    fn applied_orientation(&self) -> Option<u16> {
+        if !self.options.get_apply_orientation() {
+            return None;
+        }
+        self.orientation().filter(|orientation| *orientation != 1)
+    }
     /// Get the output colorspace the image pixels will be decoded into
     ///
     ///
@@ -702,8 +883,418 @@ where
         let out_len = core::cmp::min(out.len(), expected_size);
         let out = &mut out[0..out_len];
 
+        let (width, height) = self.decoded_dimensions().unwrap();
+
+        self.decode_upright(out, width, height, None)
+    }
+
+    /// Decode into a pre-allocated buffer whose rows are `stride` bytes apart
+    ///
+    /// This is [`decode_into_layout`](Self::decode_into_layout) with rows starting at
+    /// the start of `out`, e.g. for writing a tile straight into its place in a larger
+    /// image by passing the buffer from the tile's first pixel on.
+    ///
+    /// # Errors
+    /// See [`decode_into_layout`](Self::decode_into_layout)
+    pub fn decode_into_with_stride(
+        &mut self, out: &mut [u8], stride: usize
+    ) -> Result<(), DecodeErrors> {
+        self.decode_into_layout(out, OutputLayout { offset: 0, stride })
+    }
+
+    /// Decode into a pre-allocated buffer, writing rows where `layout` says
+    ///
+    /// This is [`decode_into`](Self::decode_into) for surfaces larger than the image,
+    /// such as atlas tiles, GPU staging buffers whose rows must be aligned or padded
+    /// framebuffers. Bytes before the first row and between the end of a row and the
+    /// start of the next are left untouched.
+    ///
+    /// # Errors
+    ///  - The stride of `layout` is smaller than a row of the image
+    ///  - `out` is smaller than [`layout_buffer_size`](Self::layout_buffer_size)
+    ///  - See [`decode_into`](Self::decode_into)
+    ///
+    /// # Example
+    /// ```no_run
+    /// use zune_jpeg::{JpegDecoder, OutputLayout};
+    /// let mut decoder = JpegDecoder::new(&[]);
+    /// decoder.decode_headers().unwrap();
+    ///
+    /// // start rows on multiples of 256 bytes, as WebGPU wants for texture copies
+    /// let (width, _) = decoder.output_dimensions().unwrap();
+    /// let row_size = width * decoder.get_output_colorspace().unwrap().num_components();
+    /// let layout = OutputLayout { offset: 0, stride: (row_size + 255) / 256 * 256 };
+    /// let mut out = vec![0; decoder.layout_buffer_size(layout).unwrap()];
+    /// decoder.decode_into_layout(&mut out, layout).unwrap();
+    /// ```
+    pub fn decode_into_layout(
+        &mut self, out: &mut [u8], layout: OutputLayout
+    ) -> Result<(), DecodeErrors> {
+        self.decode_headers_internal()?;
+
+        let (width, height) = self.decoded_dimensions().unwrap();
+        let row_size = self.output_row_size().unwrap();
+
+        if layout.stride < row_size {
+            return Err(DecodeErrors::Format(format!(
+                "Stride {} is smaller than a row of {row_size} bytes",
+                layout.stride
+            )));
+        }
+        let expected_size = self.layout_buffer_size(layout).ok_or(
+            DecodeErrors::FormatStatic("Output layout size overflows a usize")
+        )?;
+
+        if out.len() < expected_size {
+            return Err(DecodeErrors::TooSmallOutput(expected_size, out.len()));
+        }
+        self.out_stride = Some(layout.stride);
+        let result = self.decode_upright(
+            &mut out[layout.offset..expected_size],
+            width,
+            height,
+            None
+        );
+        self.out_stride = None;
+
+        result
+    }
+
+    /// Return the number of bytes required to hold the image decoded with
+    /// [`decode_into_layout`](Self::decode_into_layout), up to the end of its last row
+    ///
+    /// # Returns
+    ///  - `Some(usize)`: Minimum size for a buffer needed to decode the image
+    ///  - `None`: Indicates the image headers weren't decoded, the stride is smaller
+    ///    than a row or the size would overflow a usize
+    #[must_use]
+    pub fn layout_buffer_size(&self, layout: OutputLayout) -> Option<usize> {
+        let (_, height) = self.output_dimensions()?;
+
+        layout.buffer_size(self.output_row_size()?, height)
+    }
+
+    /// Number of bytes of a row of the decoded image
+    fn output_row_size(&self) -> Option<usize> {
+        let (width, _) = self.output_dimensions()?;
+
+        width.checked_mul(self.bytes_per_pixel())
+    }
+
+    /// Decode into a pre-allocated buffer, showing a progressive image as it refines
+    ///
+    /// This is [`decode_into`](Self::decode_into) that, for progressive images, also renders
+    /// the scans decoded so far into `out` after every `scan_interval` scans and calls `on_scan`
+    /// with those pixels and the number of scans decoded, the way browsers show progressive
+    /// jpegs while they load. Coefficients of later scans are taken as zero, so the first
+    /// renders are blurry or grayscale versions of the image.
+    ///
+    /// Each render costs as much as the IDCT and color conversion of a full decode, so pick a
+    /// larger interval for large images. The last scan isn't rendered through `on_scan`,
+    /// `out` holds the finished image once this returns. Baseline images are only rendered
+    /// once, so `on_scan` is never called for them.
+    ///
+    /// # Errors
+    ///  - `scan_interval` is zero
+    ///  - `on_scan` returns an error, decoding stops and the error is returned
+    ///  - See [`decode_into`](Self::decode_into)
+    ///
+    /// # Example
+    /// ```no_run
+    /// use zune_jpeg::JpegDecoder;
+    /// let mut decoder = JpegDecoder::new(&[]);
+    /// decoder.decode_headers().unwrap();
+    ///
+    /// let mut out = vec![0; decoder.output_buffer_size().unwrap()];
+    /// decoder
+    ///     .decode_into_with_scans(&mut out, 2, |pixels, scans| {
+    ///         println!("{scans} scans decoded, {} bytes to show", pixels.len());
+    ///         Ok(())
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn decode_into_with_scans<F>(
+        &mut self, out: &mut [u8], scan_interval: usize, mut on_scan: F
+    ) -> Result<(), DecodeErrors>
+    where
+        F: FnMut(&[u8], usize) -> Result<(), DecodeErrors>
+    {
+        if scan_interval == 0 {
+            return Err(DecodeErrors::FormatStatic("Scan interval must be at least 1"));
+        }
+        self.decode_headers_internal()?;
+
+        let expected_size = self.output_buffer_size().unwrap();
+
+        if out.len() < expected_size {
+            return Err(DecodeErrors::TooSmallOutput(expected_size, out.len()));
+        }
+        let (width, height) = self.decoded_dimensions().unwrap();
+        let listener = ScanListener {
+            interval: scan_interval,
+            callback: &mut on_scan
+        };
+
+        self.decode_upright(&mut out[..expected_size], width, height, Some(listener))
+    }
+
+    /// Decode a rectangle of the image
+    ///
+    /// This is [`decode_region_into`](Self::decode_region_into) allocating
+    /// the output buffer.
+    ///
+    /// # Errors
+    /// See [`decode_region_into`](Self::decode_region_into)
+    pub fn decode_region(
+        &mut self, x: usize, y: usize, width: usize, height: usize
+    ) -> Result<Vec<u8>, DecodeErrors> {
+        self.decode_headers()?;
+        let size = self.region_buffer_size(width, height).ok_or(DecodeErrors::FormatStatic(
+            "Region dimensions overflow a usize"
+        ))?;
+        let mut out = vec![0; size];
+        self.decode_region_into(x, y, width, height, &mut out)?;
+        Ok(out)
+    }
+
+    /// Decode a rectangle of the image into a pre-allocated buffer
+    ///
+    /// `x`, `y`, `width` and `height` are in pixels of the decoded image, i.e. of
+    /// [`output_dimensions()`](Self::output_dimensions) when decoding at a reduced scale
+    /// or turning the image upright.
+    /// Rows of the rectangle are written one after the other into `out`, which must
+    /// hold at least `width * height * components` bytes.
+    ///
+    /// MCUs before the rectangle still have to be entropy decoded, but only those covering
+    /// the rectangle (and their neighbours if the image is sub-sampled) go through the IDCT,
+    /// up-sampling and color conversion. Decoding of baseline images stops after the last MCU
+    /// row the rectangle needs.
+    ///
+    /// # Errors
+    ///  - The rectangle is empty or doesn't fit inside the image
+    ///  - `out` is too small
+    ///  - See [`decode_into`](Self::decode_into)
+    ///
+    /// # Example
+    /// ```no_run
+    /// use zune_jpeg::JpegDecoder;
+    /// let mut decoder = JpegDecoder::new(&[]);
+    /// // the 256x256 pixels starting at (1024,512)
+    /// let pixels = decoder.decode_region(1024, 512, 256, 256).unwrap();
+    /// ```
+    pub fn decode_region_into(
+        &mut self, x: usize, y: usize, width: usize, height: usize, out: &mut [u8]
+    ) -> Result<(), DecodeErrors> {
+        self.decode_headers_internal()?;
+
+        let (image_width, image_height) = self.output_dimensions().unwrap();
+
+        if width == 0
+            || height == 0
+            || x.saturating_add(width) > image_width
+            || y.saturating_add(height) > image_height
+        {
+            return Err(DecodeErrors::Format(format!(
+                "Region {width}x{height} at ({x},{y}) doesn't fit in the {image_width}x{image_height} image"
+            )));
+        }
+        let expected_size = self.region_buffer_size(width, height).ok_or(
+            DecodeErrors::FormatStatic("Region dimensions overflow a usize")
+        )?;
+
+        if out.len() < expected_size {
+            return Err(DecodeErrors::TooSmallOutput(expected_size, out.len()));
+        }
+
+        let mut region = Region {
+            x,
+            y,
+            width,
+            height
+        };
+        if let Some(orientation) = self.applied_orientation() {
+            let (stored_width, stored_height) = self.decoded_dimensions().unwrap();
+
+            region = stored_region(region, stored_width, stored_height, orientation);
+        }
+        self.region = Some(region);
+        let result =
+            self.decode_upright(&mut out[..expected_size], region.width, region.height, None);
+        self.region = None;
+
+        result
+    }
+
+    /// Return the number of bytes required to hold a `width`x`height` rectangle
+    /// decoded with [`decode_region_into`](Self::decode_region_into)
+    ///
+    /// # Returns
+    ///  - `Some(usize)`: Minimum size for a buffer needed to decode the rectangle
+    ///  - `None`: Indicates the image headers weren't decoded, or the size would overflow a usize
+    #[must_use]
+    pub fn region_buffer_size(&self, width: usize, height: usize) -> Option<usize> {
+        if !self.headers_decoded {
+            return None;
+        }
+        width.checked_mul(height)?.checked_mul(self.bytes_per_pixel())
+    }
+
+    /// Return the planes [`decode_planar_into`](Self::decode_planar_into) writes
+    /// in `layout`, one after the other
+    ///
+    /// # Returns
+    ///  - `Some(planes)`: The Y, Cb and Cr planes, or the Y and the interleaved CbCr plane for
+    ///    `PlaneLayout::SemiPlanar`, the output buffer must hold up to the end of the last one
+    ///  - `None`: Indicates the image headers weren't decoded, or the image isn't YCbCr
+    #[must_use]
+    pub fn planes(&self, layout: PlaneLayout) -> Option<Vec<Plane>> {
+        if !self.headers_decoded
+            || self.input_colorspace != ColorSpace::YCbCr
+            || self.components.len() != 3
+        {
+            return None;
+        }
+        let (width, height) = self.dimensions()?;
+
+        Some(plane_layout(
+            layout,
+            &self.components,
+            width,
+            height,
+            self.block_size()
+        ))
+    }
+
+    /// Decode the Y, Cb and Cr planes of the image without converting them to RGB
+    ///
+    /// This is [`decode_planar_into`](Self::decode_planar_into) allocating
+    /// the output buffer.
+    ///
+    /// # Errors
+    /// See [`decode_planar_into`](Self::decode_planar_into)
+    ///
+    /// # Example
+    /// ```no_run
+    /// use zune_jpeg::{JpegDecoder, PlaneLayout};
+    /// let mut decoder = JpegDecoder::new(&[]);
+    ///
+    /// let image = decoder.decode_planar(PlaneLayout::Planar).unwrap();
+    /// let y = &image.planes[0];
+    /// println!("{}x{} luma samples, {} bytes per row", y.width, y.height, y.stride);
+    /// ```
+    pub fn decode_planar(&mut self, layout: PlaneLayout) -> Result<PlanarImage, DecodeErrors> {
+        self.decode_headers()?;
+        let planes = self.planes(layout).ok_or_else(|| self.not_ycbcr_error())?;
+        let mut data = vec![0; planes.last().map_or(0, Plane::end)];
+
+        self.decode_planar_into(layout, &mut data)?;
+
+        Ok(PlanarImage { data, planes })
+    }
+
+    /// Decode the Y, Cb and Cr planes of the image into a pre-allocated buffer,
+    /// at the positions returned by [`planes`](Self::planes)
+    ///
+    /// Samples are written the way the image stores them, so the output colorspace, depth,
+    /// orientation, flip and premultiplied alpha options are ignored. The scale denominator
+    /// is not, chroma blocks decoded at a reduced scale may cover as many pixels as luma
+    /// ones, in which case the chroma planes are as large as the luma plane.
+    ///
+    /// # Errors
+    ///  - The image isn't YCbCr, e.g. it is grayscale or CMYK
+    ///  - `out` is too small
+    ///  - See [`decode_into`](Self::decode_into)
+    pub fn decode_planar_into(
+        &mut self, layout: PlaneLayout, out: &mut [u8]
+    ) -> Result<(), DecodeErrors> {
+        self.decode_headers_internal()?;
+
+        let planes = self.planes(layout).ok_or_else(|| self.not_ycbcr_error())?;
+        let expected_size = planes.last().map_or(0, Plane::end);
+
+        if out.len() < expected_size {
+            return Err(DecodeErrors::TooSmallOutput(expected_size, out.len()));
+        }
+        let options = self.options;
+
+        self.options = options
+            .jpeg_set_out_colorspace(ColorSpace::YCbCr)
+            .jpeg_set_out_depth(BitDepth::Eight)
+            .set_apply_orientation(false)
+            .set_flip_vertically(false)
+            .set_premultiply_alpha(false);
+        self.planar = Some((layout, planes));
+        let result = self.decode_mcus(&mut out[..expected_size], None);
+        self.planar = None;
+        self.options = options;
+
+        result
+    }
+
+    fn not_ycbcr_error(&self) -> DecodeErrors {
+        DecodeErrors::Format(format!(
+            "Planar output needs a YCbCr image, the image is {:?}",
+            self.input_colorspace
+        ))
+    }
+
+    /// Number of bytes a pixel takes up in the output
+    pub(crate) fn bytes_per_pixel(&self) -> usize {
+        let depth = self.options.jpeg_get_out_depth();
+
+        self.options.jpeg_get_out_colorspace().num_components() * depth.size_of()
+    }
+
+    /// Decode the `width`x`height` pixels of the image, or of the region being decoded,
+    /// into `out`, turning them upright if the options ask for it
+    ///
+    /// Images that are written as stored are flipped a row at a time by
+    /// [`post_process`](Self::post_process), the others by flipping as they are turned upright.
+    fn decode_upright(
+        &mut self, out: &mut [u8], width: usize, height: usize, listener: Option<ScanListener>
+    ) -> Result<(), DecodeErrors> {
+        let Some(mut orientation) = self.applied_orientation() else {
+            return self.decode_mcus(out, listener);
+        };
+        if self.options.get_flip_vertically() {
+            orientation = flipped_vertically(orientation);
+        }
+        let pixel_size = self.bytes_per_pixel();
+        let mut pixels = vec![0; width * height * pixel_size];
+        // the stored image is decoded with packed rows, the stride applies to `out`
+        let stride = self.out_stride.take();
+        let out_stride = stride.unwrap_or_else(|| {
+            pixel_size * if swaps_dimensions(orientation) { height } else { width }
+        });
+
+        let result = if let Some(ScanListener { interval, callback }) = listener {
+            // listeners see the scans upright too
+            let mut upright_callback = |scan_pixels: &[u8], scans: usize| {
+                apply_orientation(
+                    scan_pixels, out, out_stride, width, height, pixel_size, orientation
+                );
+                callback(out, scans)
+            };
+            let listener = ScanListener {
+                interval,
+                callback: &mut upright_callback
+            };
+            self.decode_mcus(&mut pixels, Some(listener))
+        } else {
+            self.decode_mcus(&mut pixels, None)
+        };
+        self.out_stride = stride;
+        result?;
+        apply_orientation(&pixels, out, out_stride, width, height, pixel_size, orientation);
+
+        Ok(())
+    }
+
+    fn decode_mcus(
+        &mut self, out: &mut [u8], listener: Option<ScanListener>
+    ) -> Result<(), DecodeErrors> {
         if self.is_progressive {
-            self.decode_mcu_ycbcr_progressive(out)
+            self.decode_mcu_ycbcr_progressive(out, listener)
         } else {
             self.decode_mcu_ycbcr_baseline(out)
         }
@@ -769,9 +1360,13 @@ where
             }
         }
 
+        let block_size = self.block_size();
+
         for comp in self.components.iter_mut() {
-            let hs = self.h_max / comp.horizontal_sample;
-            let vs = self.v_max / comp.vertical_sample;
+            // blocks of components decoded at a reduced scale may already
+            // cover their whole output, see `setup_component_params`
+            let hs = self.h_max * block_size / (comp.horizontal_sample * comp.block_width);
+            let vs = self.v_max * block_size / (comp.vertical_sample * comp.block_height);
 
             let samp_factor = match (hs, vs) {
                 (1, 1) => {
@@ -832,11 +1427,179 @@ where
             None
         };
     }
+
+    /// Get the dimensions of the decoded image as a tuple of width and height
+    /// or `None` if the image hasn't been decoded.
+    ///
+    /// This is the same as [`dimensions`] unless a scale is set with
+    /// [`DecoderOptions::jpeg_set_scale_denominator`], in which case each
+    /// dimension is divided by the scale denominator, rounding up, or the image is
+    /// turned upright with [`DecoderOptions::set_apply_orientation`] and its
+    /// orientation swaps width and height.
+    ///
+    /// [`dimensions`]: Self::dimensions
+    #[must_use]
+    pub fn output_dimensions(&self) -> Option<(usize, usize)> {
+        let (width, height) = self.decoded_dimensions()?;
+
+        match self.applied_orientation() {
+            Some(orientation) if swaps_dimensions(orientation) => Some((height, width)),
+            _ => Some((width, height))
+        }
+    }
+
+    /// Dimensions of the image after scaling, before it is turned upright
+    pub(crate) fn decoded_dimensions(&self) -> Option<(usize, usize)> {
+        let (width, height) = self.dimensions()?;
+        let denominator = self.options.jpeg_get_scale_denominator();
+
+        Some((
+            (width + denominator - 1) / denominator,
+            (height + denominator - 1) / denominator
+        ))
+    }
+
+    /// Width and height in pixels of a decoded 8x8 block of a component
+    /// that isn't sub-sampled
+    pub(crate) fn block_size(&self) -> usize {
+        8 / self.options.jpeg_get_scale_denominator()
+    }
+
+    /// Return the MCUs that have to be decoded to produce the region set by
+    /// [`decode_region_into`](Self::decode_region_into), out of an image
+    /// `mcu_width` MCUs wide and `mcu_height` MCUs tall.
+    ///
+    /// Without a region this is the whole image.
+    pub(crate) fn mcu_window(&self, mcu_width: usize, mcu_height: usize) -> McuWindow {
+        let (width, height) = self.decoded_dimensions().unwrap();
+        // images that are turned upright are flipped along with that
+        let flip = self.options.get_flip_vertically() && self.applied_orientation().is_none();
+
+        let Some(region) = self.region else {
+            return McuWindow {
+                cols: 0..mcu_width,
+                rows: 0..mcu_height,
+                width,
+                region: Region {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height
+                },
+                flip
+            };
+        };
+        // size of an MCU in output pixels
+        let mcu_pixels_x = self.h_max * self.block_size();
+        let mcu_pixels_y = self.v_max * self.block_size();
+        // up-sampling the edges of the region needs the MCUs around it
+        let margin_x = usize::from(self.h_max > 1);
+        let margin_y = usize::from(self.v_max > 1);
+
+        let cols = (region.x / mcu_pixels_x).saturating_sub(margin_x)
+            ..min(
+                (region.x + region.width + mcu_pixels_x - 1) / mcu_pixels_x + margin_x,
+                mcu_width
+            );
+        let rows = (region.y / mcu_pixels_y).saturating_sub(margin_y)
+            ..min(
+                (region.y + region.height + mcu_pixels_y - 1) / mcu_pixels_y + margin_y,
+                mcu_height
+            );
+
+        McuWindow {
+            width: min(cols.end * mcu_pixels_x, width) - cols.start * mcu_pixels_x,
+            region: Region {
+                x: region.x - cols.start * mcu_pixels_x,
+                y: region.y - rows.start * mcu_pixels_y,
+                ..region
+            },
+            cols,
+            rows,
+            flip
+        }
+    }
+}
+
+/// A rectangle of the decoded image, in pixels
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Region {
+    pub(crate) x:      usize,
+    pub(crate) y:      usize,
+    pub(crate) width:  usize,
+    pub(crate) height: usize
+}
+
+/// MCUs of an image that go through the IDCT, up-sampling and color
+/// conversion, see [`JpegDecoder::mcu_window`]
+#[derive(Clone, Debug)]
+pub(crate) struct McuWindow {
+    /// MCU columns decoded, component buffers only hold these
+    pub(crate) cols:   Range<usize>,
+    /// MCU rows decoded
+    pub(crate) rows:   Range<usize>,
+    /// Width in pixels of the decoded columns, excluding padding past the image edge
+    pub(crate) width:  usize,
+    /// The rectangle written to the output, relative to the top left of the window
+    pub(crate) region: Region,
+    /// Whether rows of the rectangle are written bottom to top
+    pub(crate) flip:   bool
+}
+
+/// Receives the scans of a progressive image decoded so far,
+/// see [`JpegDecoder::decode_into_with_scans`]
+pub(crate) struct ScanListener<'a> {
+    /// Number of scans between renders
+    pub(crate) interval: usize,
+    /// Called with the rendered pixels and the number of scans decoded
+    pub(crate) callback: &'a mut dyn FnMut(&[u8], usize) -> Result<(), DecodeErrors>
+}
+
+/// Where rows of the image are written in an output buffer, see
+/// [`JpegDecoder::decode_into_layout`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct OutputLayout {
+    /// Bytes before the first pixel of the first row
+    pub offset: usize,
+    /// Bytes from the start of a row to the start of the next, at least a row
+    pub stride: usize
+}
+
+impl OutputLayout {
+    /// Rows of `row_size` bytes one after the other from the start of the buffer,
+    /// the layout [`JpegDecoder::decode_into`] writes
+    #[must_use]
+    pub const fn packed(row_size: usize) -> OutputLayout {
+        OutputLayout { offset: 0, stride: row_size }
+    }
+
+    /// Return the number of bytes up to the end of the last of `height` rows of
+    /// `row_size` bytes
+    ///
+    /// Returns `None` if the stride is smaller than `row_size` or the size would
+    /// overflow a usize.
+    #[must_use]
+    pub fn buffer_size(&self, row_size: usize, height: usize) -> Option<usize> {
+        if self.stride < row_size {
+            return None;
+        }
+        if height == 0 {
+            return Some(self.offset);
+        }
+        self.stride
+            .checked_mul(height - 1)?
+            .checked_add(row_size)?
+            .checked_add(self.offset)
+    }
 }
 
 /// A struct representing Image Information
+///
+/// Fields may be added as more of the headers are exposed, so this can only be
+/// created by the decoder, see [`JpegDecoder::info`].
 #[derive(Default, Clone, Eq, PartialEq)]
 #[allow(clippy::module_name_repetitions)]
+#[non_exhaustive]
 pub struct ImageInfo {
     /// Width of the image
     pub width:         u16,
@@ -846,10 +1609,13 @@ pub struct ImageInfo {
     pub pixel_density: u8,
     /// Start of frame markers
     pub sof:           SOFMarkers,
-    /// Horizontal sample
+    /// Horizontal pixel density of the JFIF segment, 0 if the image has none
     pub x_density:     u16,
-    /// Vertical sample
+    /// Vertical pixel density of the JFIF segment, 0 if the image has none
     pub y_density:     u16,
+    /// Units of `x_density` and `y_density`, 0 if they only give the
+    /// aspect ratio of pixels, 1 for dots per inch, 2 for dots per centimeter
+    pub density_units: u8,
     /// Number of components
     pub components:    u8
 }
@@ -890,7 +1656,6 @@ impl ImageInfo {
     /// Set image x-density(dots per pixel)
     ///
     /// Found in the APP(0) marker
-    #[allow(dead_code)]
     pub(crate) fn set_x(&mut self, sample: u16) {
         self.x_density = sample;
     }
@@ -898,8 +1663,14 @@ impl ImageInfo {
     /// Set image y-density
     ///
     /// Found in the APP(0) marker
-    #[allow(dead_code)]
     pub(crate) fn set_y(&mut self, sample: u16) {
         self.y_density = sample;
     }
+
+    /// Set units of the image densities
+    ///
+    /// Found in the APP(0) marker
+    pub(crate) fn set_density_units(&mut self, units: u8) {
+        self.density_units = units;
+    }
 }
@@ -47,7 +47,13 @@ pub enum DecodeErrors {
     /// Large image dimensions(Corrupted data)?
     LargeDimensions(usize),
     /// Too small output for size
-    TooSmallOutput(usize, usize)
+    TooSmallOutput(usize, usize),
+    /// Decoding needs more memory than the limit set with
+    /// `DecoderOptions::set_max_memory`, the bytes needed and the limit
+    MemoryLimit(usize, usize),
+    /// The image is larger than a limit of `DecoderOptions`, the dimension
+    /// checked (width, height or pixels), its value and the limit
+    DimensionLimit(&'static str, usize, usize)
 }
 
 #[cfg(feature = "std")]
@@ -88,7 +94,9 @@ impl Debug for DecodeErrors {
                 f,
                 "Too large dimensions {dimensions},library supports up to {}", crate::decoder::MAX_DIMENSIONS
             ),
-            Self::TooSmallOutput(expected, found) => write!(f, "Too small output, expected buffer with at least {expected} bytes but got one with {found} bytes")
+            Self::TooSmallOutput(expected, found) => write!(f, "Too small output, expected buffer with at least {expected} bytes but got one with {found} bytes"),
+            Self::MemoryLimit(needed, limit) => write!(f, "Decoding needs {needed} bytes of memory, more than the limit of {limit} bytes. Use `set_max_memory` if you want to decode the image"),
+            Self::DimensionLimit(dimension, value, limit) => write!(f, "Image {dimension} {value} greater than the limit {limit}. Use `set_max_{dimension}` if you want to support huge images")
         }
     }
 }
@@ -111,7 +119,9 @@ pub enum UnsupportedSchemes {
     /// Progressive DCT, arithmetic coding,
     ProgressiveDctArithmetic,
     /// Lossless ( sequential), arithmetic coding
-    LosslessArithmetic
+    LosslessArithmetic,
+    /// DNL marker, image height defined after the first scan
+    DefineNumberOfLines
 }
 
 impl Debug for UnsupportedSchemes {
@@ -132,6 +142,9 @@ impl Debug for UnsupportedSchemes {
             Self::LosslessArithmetic => {
                 write!(f,"The library cannot yet decode images encoded with Lossless Arithmetic encoding scheme")
             }
+            Self::DefineNumberOfLines => {
+                write!(f,"The library cannot yet decode images whose height is set by a DNL marker")
+            }
         }
     }
 }
@@ -217,11 +217,11 @@ pub(crate) fn parse_start_of_frame<T: ZReaderTrait>(
     trace!("Image height :{}", img_height);
 
     if usize::from(img_width) > img.options.get_max_width() {
-        return Err(DecodeErrors::Format(format!("Image width {} greater than width limit {}. If use `set_limits` if you want to support huge images", img_width, img.options.get_max_width())));
+        return Err(DecodeErrors::DimensionLimit("width", usize::from(img_width), img.options.get_max_width()));
     }
 
     if usize::from(img_height) > img.options.get_max_height() {
-        return Err(DecodeErrors::Format(format!("Image height {} greater than height limit {}. If use `set_limits` if you want to support huge images", img_height, img.options.get_max_height())));
+        return Err(DecodeErrors::DimensionLimit("height", usize::from(img_height), img.options.get_max_height()));
     }
 
     // Check image width or height is zero
@@ -229,6 +229,12 @@ pub(crate) fn parse_start_of_frame<T: ZReaderTrait>(
         return Err(DecodeErrors::ZeroError);
     }
 
+    let pixels = usize::from(img_width) * usize::from(img_height);
+
+    if pixels > img.options.get_max_pixels() {
+        return Err(DecodeErrors::DimensionLimit("pixels", pixels, img.options.get_max_pixels()));
+    }
+
     // Number of components for the image.
     let num_components = img.stream.get_u8_err()?;
 
@@ -274,7 +280,12 @@ pub(crate) fn parse_start_of_frame<T: ZReaderTrait>(
             .read_exact(&mut temp)
             .map_err(|x| DecodeErrors::Format(format!("Could not read component data\n{x}")))?;
         // create a component.
-        let component = Components::from(temp, pos)?;
+        let mut component = Components::from(temp, pos)?;
+
+        // keep allocations from an image decoded before a `reset`
+        if let Some(previous) = img.components.get_mut(usize::from(pos)) {
+            component.reuse_buffers(previous);
+        }
 
         components.push(component);
     }
@@ -414,6 +425,43 @@ pub(crate) fn parse_sos<T: ZReaderTrait>(image: &mut JpegDecoder<T>) -> Result<(
     Ok(())
 }
 
+/// Parse the APP0 segment
+///
+/// This holds the JFIF pixel density, or marks the image as a motion jpeg frame
+pub(crate) fn parse_app0<T: ZReaderTrait>(
+    decoder: &mut JpegDecoder<T>
+) -> Result<(), DecodeErrors> {
+    let mut length = usize::from(decoder.stream.get_u16_be_err()?);
+
+    if length < 2 {
+        return Err(DecodeErrors::Format(format!(
+            "Found a marker with invalid length:{length}\n"
+        )));
+    }
+    length -= 2;
+
+    if length >= 5 && decoder.stream.has(5) {
+        let mut buffer = [0u8; 5];
+        decoder.stream.read_exact(&mut buffer).unwrap();
+        length -= 5;
+
+        if &buffer == b"AVI1\0" {
+            decoder.is_mjpeg = true;
+        } else if &buffer == b"JFIF\0" && length >= 7 && decoder.stream.has(7) {
+            // skip version
+            decoder.stream.skip(2);
+            decoder.info.set_density_units(decoder.stream.get_u8());
+            decoder.info.set_x(decoder.stream.get_u16_be());
+            decoder.info.set_y(decoder.stream.get_u16_be());
+            length -= 7;
+        }
+    }
+    // skip the thumbnail and anything else, we do not need them
+    decoder.stream.skip(length);
+
+    Ok(())
+}
+
 /// Parse Adobe App14 segment
 pub(crate) fn parse_app14<T: ZReaderTrait>(
     decoder: &mut JpegDecoder<T>
@@ -437,6 +485,8 @@ pub(crate) fn parse_app14<T: ZReaderTrait>(
         // get color transform
         let transform = decoder.stream.get_u8();
         // https://exiftool.org/TagNames/JPEG.html#Adobe
+        decoder.adobe_transform = Some(transform);
+
         match transform {
             0 => decoder.input_colorspace = ColorSpace::CMYK,
             1 => decoder.input_colorspace = ColorSpace::YCbCr,
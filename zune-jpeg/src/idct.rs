@@ -25,6 +25,9 @@
 //! The AVX code also has some cool transpose_u16 instructions which look so complicated to be cool
 //! (spoiler alert, i barely understand how it works, that's why I credited the owner).
 //!
+//! When decoding at a reduced scale, blocks are transformed by the smaller IDCT's in
+//! [`scaled`], see [`idct_block`].
+//!
 #![allow(
     clippy::excessive_precision,
     clippy::unreadable_literal,
@@ -37,15 +40,19 @@ use zune_core::log::debug;
 use zune_core::options::DecoderOptions;
 
 use crate::decoder::IDCTPtr;
-// use crate::idct::scalar::idct_int;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::idct::scalar::idct_int;
 
 #[cfg(feature = "x86")]
 pub mod avx2;
 #[cfg(feature = "neon")]
 pub mod neon;
+#[cfg(target_arch = "wasm32")]
 pub mod wasm;
 
-// pub mod scalar;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod scalar;
+pub mod scaled;
 
 /// Choose an appropriate IDCT function
 #[allow(unused_variables)]
@@ -67,16 +74,67 @@ pub fn choose_idct_func(options: &DecoderOptions) -> IDCTPtr {
             return crate::idct::neon::idct_neon;
         }
     }
-    // #[cfg(target_arch = "wasm32")]
-    // {
-        // if options.use_neon() {
-            debug!("Using vector integer IDCT");
-            return crate::idct::wasm::idct_wasm;
-        // }
-    // }
-    // debug!("Using scalar integer IDCT");
-    // // use generic one
-    // return idct_int;
+    #[cfg(target_arch = "wasm32")]
+    {
+        debug!("Using vector integer IDCT");
+        return crate::idct::wasm::idct_wasm;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        debug!("Using scalar integer IDCT");
+        // use generic one
+        return idct_int;
+    }
+}
+
+/// Return the IDCT function writing `size`x`size` pixels per block,
+/// `idct_func` is the full 8x8 one
+fn choose_scaled_idct_func(idct_func: IDCTPtr, size: usize) -> IDCTPtr {
+    match size {
+        1 => scaled::idct_int_1x1,
+        2 => scaled::idct_int_2x2,
+        4 => scaled::idct_int_4x4,
+        _ => idct_func
+    }
+}
+
+/// Transform the dequantized `coeff` into a `width`x`height` block of pixels at the start of `out`
+///
+/// Blocks are 8x8 unless decoding at a reduced scale, in which case they are
+/// 4x4, 2x2 or 1x1. Components with lower sampling factors than the image are
+/// decoded at a larger block size so that they need little or no up-sampling,
+/// that block may be twice as wide as it is tall (or the other way round)
+/// in which case each pixel is the rounded average of the `step_x`x`step_y`
+/// pixels of the square IDCT output it covers.
+pub(crate) fn idct_block(
+    idct_func: IDCTPtr, coeff: &mut [i32; 64], out: &mut [i16], stride: usize, width: usize,
+    height: usize
+) {
+    if width == height {
+        return choose_scaled_idct_func(idct_func, width)(coeff, out, stride);
+    }
+    let size = width.max(height);
+    let (step_x, step_y) = (size / width, size / height);
+
+    let mut block = [0_i16; 64];
+    choose_scaled_idct_func(idct_func, size)(coeff, &mut block, size);
+
+    // block sizes are powers of two, so is the area averaged
+    let shift = (step_x * step_y).trailing_zeros();
+
+    for (y, out_row) in out.chunks_mut(stride).take(height).enumerate() {
+        let rows = &block[y * step_y * size..(y + 1) * step_y * size];
+
+        for (x, pixel) in out_row[..width].iter_mut().enumerate() {
+            // samples are 0..=255, 64 of them can't overflow
+            let sum: i16 = rows
+                .chunks_exact(size)
+                .flat_map(|row| &row[x * step_x..(x + 1) * step_x])
+                .sum();
+
+            *pixel = (sum + ((1 << shift) >> 1)) >> shift;
+        }
+    }
 }
 
 #[cfg(test)]
@@ -135,6 +193,56 @@ mod tests {
         assert_eq!(output_scalar, output_vector, "IDCT and scalar do not match");
     }
 
+    #[test]
+    fn scaled_idct_dc_only() {
+        // a block with only a DC coefficient is flat at every size
+        let mut coeff = [0; 64];
+        coeff[0] = 80;
+        let mut full_output = [0; 64];
+        idct_int(&mut coeff.clone(), &mut full_output, 8);
+
+        for (width, height) in [(4, 4), (2, 2), (1, 1), (8, 4), (2, 4)] {
+            let mut output = [0; 64];
+            idct_block(idct_int, &mut coeff.clone(), &mut output, 8, width, height);
+
+            for row in output.chunks_exact(8).take(height) {
+                assert_eq!(&row[..width], &full_output[..width], "{width}x{height}");
+            }
+        }
+    }
+
+    #[test]
+    fn scaled_idct_non_square_averages_area() {
+        // a horizontal and a vertical gradient, so every sample of a block differs
+        let mut coeff = [0; 64];
+        coeff[0] = 80;
+        coeff[1] = -120;
+        coeff[8] = 90;
+        coeff[9] = 40;
+
+        for size in [8, 4, 2] {
+            let mut square = [0; 64];
+            idct_block(idct_int, &mut coeff.clone(), &mut square, size, size, size);
+
+            for (width, height) in [(size, size / 2), (size / 2, size)] {
+                let (step_x, step_y) = (size / width, size / height);
+                let mut output = [0; 64];
+                idct_block(idct_int, &mut coeff.clone(), &mut output, 8, width, height);
+
+                for y in 0..height {
+                    for x in 0..width {
+                        let sum: i16 = (0..step_y)
+                            .flat_map(|dy| (0..step_x).map(move |dx| (dx, dy)))
+                            .map(|(dx, dy)| square[(y * step_y + dy) * size + x * step_x + dx])
+                            .sum();
+
+                        assert_eq!(output[y * 8 + x], (sum + 1) / 2, "{width}x{height} at {x},{y}");
+                    }
+                }
+            }
+        }
+    }
+
     fn idct_fnc() -> IDCTPtr {
         #[cfg(feature = "neon")]
         #[cfg(target_arch = "aarch64")]
@@ -0,0 +1,201 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Reduced size IDCT's used when decoding at a smaller scale
+//!
+//! These produce a 4x4, 2x2 or 1x1 block from the 64 coefficients of an MCU
+//! by only looking at the low frequency coefficients, the results are
+//! the same as libjpeg's `jidctred.c` routines from which they are derived.
+//!
+//! They run on all platforms, the work done is small enough that
+//! a vector version wouldn't buy much.
+#![allow(clippy::cast_possible_truncation)]
+
+const CONST_BITS: i32 = 13;
+const PASS1_BITS: i32 = 2;
+
+const FIX_0_211164243: i32 = 1730;
+const FIX_0_509795579: i32 = 4176;
+const FIX_0_601344887: i32 = 4926;
+const FIX_0_720959822: i32 = 5906;
+const FIX_0_765366865: i32 = 6270;
+const FIX_0_850430095: i32 = 6967;
+const FIX_0_899976223: i32 = 7373;
+const FIX_1_061594337: i32 = 8697;
+const FIX_1_272758580: i32 = 10426;
+const FIX_1_451774981: i32 = 11893;
+const FIX_1_847759065: i32 = 15137;
+const FIX_2_172734803: i32 = 17799;
+const FIX_2_562915447: i32 = 20995;
+const FIX_3_624509785: i32 = 29692;
+
+/// Divide by `2^n`, rounding to the nearest
+///
+/// Like the full size IDCT, arithmetic wraps, so coefficients of corrupt
+/// images give garbage pixels instead of overflowing.
+#[inline]
+fn descale(x: i32, n: i32) -> i32 {
+    x.wrapping_add(1 << (n - 1)) >> n
+}
+
+/// Move a sample from -128..127 to 0..255 and clamp it
+#[inline]
+fn level_shift(a: i32) -> i16 {
+    a.wrapping_add(128).clamp(0, 255) as i16
+}
+
+/// Even and odd parts of a 4 point IDCT of coefficients 0, 2, 6 and 1, 3, 5, 7
+///
+/// Returns `(tmp10, tmp12, tmp0, tmp2)`, the outputs are `tmp10 + tmp2`,
+/// `tmp12 + tmp0`, `tmp12 - tmp0` and `tmp10 - tmp2`.
+#[inline]
+fn idct_4(c: [i32; 8]) -> (i32, i32, i32, i32) {
+    // even part
+    let tmp0 = c[0].wrapping_shl((CONST_BITS + 1) as u32);
+    let tmp2 = c[2]
+        .wrapping_mul(FIX_1_847759065)
+        .wrapping_sub(c[6].wrapping_mul(FIX_0_765366865));
+
+    let tmp10 = tmp0.wrapping_add(tmp2);
+    let tmp12 = tmp0.wrapping_sub(tmp2);
+
+    // odd part
+    let (z1, z2, z3, z4) = (c[7], c[5], c[3], c[1]);
+
+    let tmp0 = z2
+        .wrapping_mul(FIX_1_451774981)
+        .wrapping_sub(z1.wrapping_mul(FIX_0_211164243))
+        .wrapping_sub(z3.wrapping_mul(FIX_2_172734803))
+        .wrapping_add(z4.wrapping_mul(FIX_1_061594337));
+    let tmp2 = z3
+        .wrapping_mul(FIX_0_899976223)
+        .wrapping_sub(z1.wrapping_mul(FIX_0_509795579))
+        .wrapping_sub(z2.wrapping_mul(FIX_0_601344887))
+        .wrapping_add(z4.wrapping_mul(FIX_2_562915447));
+
+    (tmp10, tmp12, tmp0, tmp2)
+}
+
+/// Even and odd parts of a 2 point IDCT of coefficients 0 and 1, 3, 5, 7
+///
+/// Returns `(tmp10, tmp0)`, the outputs are `tmp10 + tmp0` and `tmp10 - tmp0`.
+#[inline]
+fn idct_2(c: [i32; 8]) -> (i32, i32) {
+    let tmp10 = c[0].wrapping_shl((CONST_BITS + 2) as u32);
+    let tmp0 = c[5]
+        .wrapping_mul(FIX_0_850430095)
+        .wrapping_sub(c[7].wrapping_mul(FIX_0_720959822))
+        .wrapping_sub(c[3].wrapping_mul(FIX_1_272758580))
+        .wrapping_add(c[1].wrapping_mul(FIX_3_624509785));
+
+    (tmp10, tmp0)
+}
+
+/// IDCT producing a 4x4 block, used when decoding at 1/2 scale
+///
+/// Row 4 and column 4 of the coefficients are not needed, and the
+/// rest of the upper half of the coefficients are combined as a 4 point IDCT.
+pub fn idct_int_4x4(in_vector: &mut [i32; 64], out_vector: &mut [i16], stride: usize) {
+    let mut workspace = [0_i32; 32];
+
+    // Pass 1: process columns, store into a 8 wide, 4 tall work array
+    for ptr in 0..8 {
+        // column 4 isn't used by the second pass
+        if ptr == 4 {
+            continue;
+        }
+        let col: [i32; 8] = core::array::from_fn(|row| in_vector[row * 8 + ptr]);
+
+        if col[1] == 0 && col[2] == 0 && col[3] == 0 && col[5] == 0 && col[6] == 0 && col[7] == 0 {
+            let dc = col[0].wrapping_shl(PASS1_BITS as u32);
+
+            for row in 0..4 {
+                workspace[row * 8 + ptr] = dc;
+            }
+            continue;
+        }
+        let (tmp10, tmp12, tmp0, tmp2) = idct_4(col);
+
+        let shift = CONST_BITS - PASS1_BITS + 1;
+
+        workspace[ptr] = descale(tmp10.wrapping_add(tmp2), shift);
+        workspace[8 + ptr] = descale(tmp12.wrapping_add(tmp0), shift);
+        workspace[16 + ptr] = descale(tmp12.wrapping_sub(tmp0), shift);
+        workspace[24 + ptr] = descale(tmp10.wrapping_sub(tmp2), shift);
+    }
+
+    // Pass 2: process the 4 rows, write them out
+    for (row, out) in workspace.chunks_exact(8).zip(out_vector.chunks_mut(stride)) {
+        let out = &mut out[..4];
+
+        if row[1] == 0 && row[2] == 0 && row[3] == 0 && row[5] == 0 && row[6] == 0 && row[7] == 0 {
+            out.fill(level_shift(descale(row[0], PASS1_BITS + 3)));
+            continue;
+        }
+        let (tmp10, tmp12, tmp0, tmp2) = idct_4(row.try_into().unwrap());
+
+        let shift = CONST_BITS + PASS1_BITS + 3 + 1;
+
+        out[0] = level_shift(descale(tmp10.wrapping_add(tmp2), shift));
+        out[1] = level_shift(descale(tmp12.wrapping_add(tmp0), shift));
+        out[2] = level_shift(descale(tmp12.wrapping_sub(tmp0), shift));
+        out[3] = level_shift(descale(tmp10.wrapping_sub(tmp2), shift));
+    }
+}
+
+/// IDCT producing a 2x2 block, used when decoding at 1/4 scale
+///
+/// Only the DC and the odd coefficients of the first row and column
+/// are needed.
+pub fn idct_int_2x2(in_vector: &mut [i32; 64], out_vector: &mut [i16], stride: usize) {
+    let mut workspace = [0_i32; 16];
+
+    // Pass 1: process columns, store into a 8 wide, 2 tall work array,
+    // columns 2, 4 and 6 aren't used by the second pass
+    for ptr in [0, 1, 3, 5, 7] {
+        let col: [i32; 8] = core::array::from_fn(|row| in_vector[row * 8 + ptr]);
+
+        if col[1] == 0 && col[3] == 0 && col[5] == 0 && col[7] == 0 {
+            let dc = col[0].wrapping_shl(PASS1_BITS as u32);
+
+            workspace[ptr] = dc;
+            workspace[8 + ptr] = dc;
+            continue;
+        }
+        let (tmp10, tmp0) = idct_2(col);
+
+        let shift = CONST_BITS - PASS1_BITS + 2;
+
+        workspace[ptr] = descale(tmp10.wrapping_add(tmp0), shift);
+        workspace[8 + ptr] = descale(tmp10.wrapping_sub(tmp0), shift);
+    }
+
+    // Pass 2: process the 2 rows, write them out
+    for (row, out) in workspace.chunks_exact(8).zip(out_vector.chunks_mut(stride)) {
+        let out = &mut out[..2];
+
+        if row[1] == 0 && row[3] == 0 && row[5] == 0 && row[7] == 0 {
+            out.fill(level_shift(descale(row[0], PASS1_BITS + 3)));
+            continue;
+        }
+        let (tmp10, tmp0) = idct_2(row.try_into().unwrap());
+
+        let shift = CONST_BITS + PASS1_BITS + 3 + 2;
+
+        out[0] = level_shift(descale(tmp10.wrapping_add(tmp0), shift));
+        out[1] = level_shift(descale(tmp10.wrapping_sub(tmp0), shift));
+    }
+}
+
+/// IDCT producing a single pixel, used when decoding at 1/8 scale
+///
+/// This is the average of the block, which is the DC coefficient scaled down,
+/// so no transform is done at all.
+pub fn idct_int_1x1(in_vector: &mut [i32; 64], out_vector: &mut [i16], _stride: usize) {
+    out_vector[0] = level_shift(descale(in_vector[0], 3));
+}
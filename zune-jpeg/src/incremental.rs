@@ -0,0 +1,271 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Decoding of jpegs whose bytes arrive in chunks
+//!
+//! [`JpegDecoder`] reads from a buffer holding the whole file, so an image coming over the
+//! network can only be decoded once all of it has arrived. [`IncrementalDecoder`] takes the
+//! bytes as they come instead and decodes as far as they allow after every chunk.
+//!
+//! Headers are parsed once every segment up to the start of the first scan is
+//! in, segments that are complete are only walked over once however small the chunks
+//! are. Baseline images are then decoded MCU row by MCU row. A row that reads up to the end
+//! of the input so far is taken back and decoded again when more input arrives, so rows of
+//! pixels are only written once all of their data is known.
+//!
+//! Every scan of a progressive image refines the whole image, so these are decoded
+//! in one go once the input is [finished](IncrementalDecoder::finish).
+use alloc::vec;
+use alloc::vec::Vec;
+
+use zune_core::options::DecoderOptions;
+
+use crate::errors::DecodeErrors;
+use crate::mcu::BaselineState;
+use crate::JpegDecoder;
+
+/// Progress of an [`IncrementalDecoder`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DecodeStatus {
+    /// Everything the input so far allows has been decoded, push more of it
+    NeedMoreData,
+    /// The whole image has been decoded
+    Complete
+}
+
+/// A jpeg decoder fed with chunks of input as they arrive
+///
+/// # Example
+/// ```no_run
+/// use zune_jpeg::{DecodeStatus, IncrementalDecoder};
+///
+/// let chunks: Vec<Vec<u8>> = vec![];
+/// let mut decoder = IncrementalDecoder::new();
+///
+/// for chunk in chunks {
+///     if decoder.push(&chunk).unwrap() == DecodeStatus::Complete {
+///         break;
+///     }
+///     println!("{} rows decoded", decoder.rows_decoded());
+/// }
+/// decoder.finish().unwrap();
+/// let pixels = decoder.into_pixels();
+/// ```
+pub struct IncrementalDecoder {
+    decoder:        JpegDecoder<Vec<u8>>,
+    // set once the headers are decoded for baseline images
+    baseline:       Option<BaselineState>,
+    pixels:         Vec<u8>,
+    rows_decoded:   usize,
+    input_complete: bool,
+    status:         DecodeStatus,
+    // start of the first segment not yet known to be complete
+    segments_end:   usize
+}
+
+impl IncrementalDecoder {
+    /// Create a new decoder with the default options
+    #[must_use]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> IncrementalDecoder {
+        IncrementalDecoder::new_with_options(DecoderOptions::default())
+    }
+
+    /// Create a new decoder with the specified options
+    ///
+    /// Rows are written top to bottom in the order they are stored, so
+    /// [`DecoderOptions::set_apply_orientation`] and [`DecoderOptions::set_flip_vertically`]
+    /// are ignored, use [`JpegDecoder::orientation`] to turn the image upright.
+    #[must_use]
+    pub fn new_with_options(options: DecoderOptions) -> IncrementalDecoder {
+        let options = options
+            .set_apply_orientation(false)
+            .set_flip_vertically(false);
+
+        IncrementalDecoder {
+            decoder:        JpegDecoder::new_with_options(Vec::new(), options),
+            baseline:       None,
+            pixels:         vec![],
+            rows_decoded:   0,
+            input_complete: false,
+            status:         DecodeStatus::NeedMoreData,
+            // skip the start of image marker
+            segments_end:   2
+        }
+    }
+
+    /// Append `data` to the input and decode as much of the image as the input allows
+    ///
+    /// # Returns
+    /// - `DecodeStatus::Complete`: The image is fully decoded, more input is ignored
+    /// - `DecodeStatus::NeedMoreData`: Decoding stopped at the end of the input so far
+    ///
+    /// # Errors
+    /// Errors of the input so far, the decoder can't be used after one.
+    pub fn push(&mut self, data: &[u8]) -> Result<DecodeStatus, DecodeErrors> {
+        if self.status == DecodeStatus::NeedMoreData {
+            self.decoder.stream.get_mut().extend_from_slice(data);
+        }
+        self.advance()
+    }
+
+    /// Mark the input as finished and decode the rest of the image
+    ///
+    /// Missing data is handled like [`JpegDecoder::decode`] handles a
+    /// truncated file, so this returns `DecodeStatus::Complete` unless it fails.
+    ///
+    /// # Errors
+    /// See [`JpegDecoder::decode`]
+    pub fn finish(&mut self) -> Result<DecodeStatus, DecodeErrors> {
+        self.input_complete = true;
+        self.advance()
+    }
+
+    /// Return the decoder reading the input
+    ///
+    /// Once the headers are decoded this gives the image information,
+    /// dimensions, colorspace and metadata.
+    #[must_use]
+    pub const fn decoder(&self) -> &JpegDecoder<Vec<u8>> {
+        &self.decoder
+    }
+
+    /// Return the number of rows of pixels at the start of [`pixels`](Self::pixels)
+    /// that are fully decoded
+    #[must_use]
+    pub const fn rows_decoded(&self) -> usize {
+        self.rows_decoded
+    }
+
+    /// Return the pixels decoded so far
+    ///
+    /// This is empty until the headers are decoded and then holds the whole image,
+    /// rows after [`rows_decoded`](Self::rows_decoded) are zero.
+    #[must_use]
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Return the pixels decoded so far, see [`pixels`](Self::pixels)
+    #[must_use]
+    pub fn into_pixels(self) -> Vec<u8> {
+        self.pixels
+    }
+
+    fn advance(&mut self) -> Result<DecodeStatus, DecodeErrors> {
+        if self.status == DecodeStatus::Complete {
+            return Ok(self.status);
+        }
+        if !self.decoder.headers_decoded {
+            if !self.input_complete
+                && !headers_available(self.decoder.stream.get_mut(), &mut self.segments_end)
+            {
+                return Ok(DecodeStatus::NeedMoreData);
+            }
+            self.decoder.decode_headers()?;
+            self.pixels = vec![0; self.decoder.output_buffer_size().unwrap()];
+
+            if !self.decoder.is_progressive {
+                self.baseline = Some(self.decoder.start_baseline_decoding()?);
+            }
+        }
+        let height = self.decoder.output_dimensions().unwrap().1;
+
+        if let Some(state) = &mut self.baseline {
+            while !state.is_finished() {
+                if !self
+                    .decoder
+                    .decode_baseline_row(state, &mut self.pixels, self.input_complete)?
+                {
+                    return Ok(DecodeStatus::NeedMoreData);
+                }
+                // the last MCU row may hold padding rows below the image
+                self.rows_decoded = state.rows_written.min(height);
+            }
+        } else {
+            // progressive scans each cover the whole image
+            if !self.input_complete {
+                return Ok(DecodeStatus::NeedMoreData);
+            }
+            self.decoder.decode_into(&mut self.pixels)?;
+            self.rows_decoded = height;
+        }
+        self.status = DecodeStatus::Complete;
+
+        Ok(self.status)
+    }
+}
+
+/// Whether `data` holds every segment up to and including the first start of scan
+///
+/// This walks the segments like `decode_headers` does, without parsing them, starting
+/// at `segments_end` which is moved past every segment found complete so the next
+/// call with more data doesn't walk over them again.
+fn headers_available(data: &[u8], segments_end: &mut usize) -> bool {
+    while *segments_end < data.len() {
+        let mut position = *segments_end;
+
+        if data[position] != 0xFF {
+            // extra bytes between segments, the decoder skips them too
+            *segments_end += 1;
+            continue;
+        }
+        // markers may be preceded by any number of fill bytes
+        while data.get(position) == Some(&0xFF) {
+            position += 1;
+        }
+        let Some(&marker) = data.get(position) else {
+            return false;
+        };
+        position += 1;
+
+        // start of image, end of image, restart and TEM markers have no segment
+        if matches!(marker, 0x00 | 0x01 | 0xD0..=0xD9) {
+            *segments_end = position;
+            continue;
+        }
+        let Some(length) = data.get(position..position + 2) else {
+            return false;
+        };
+        position += usize::from(u16::from_be_bytes([length[0], length[1]]));
+
+        if position > data.len() {
+            return false;
+        }
+        if marker == 0xDA {
+            return true;
+        }
+        *segments_end = position;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_up_to_sos() {
+        // SOI, a 4 byte APP0 segment with fill bytes before it, then SOS
+        let data = [
+            0xFF, 0xD8, 0xFF, 0xFF, 0xE0, 0x00, 0x04, 0x01, 0x02, 0xFF, 0xDA, 0x00, 0x03, 0x01
+        ];
+        assert!(headers_available(&data, &mut 2));
+
+        for end in 0..data.len() {
+            assert!(!headers_available(&data[..end], &mut 2), "{end}");
+        }
+        // fed one byte at a time, resuming after the segments already walked
+        let mut segments_end = 2;
+        for end in 0..data.len() {
+            assert!(!headers_available(&data[..end], &mut segments_end), "{end}");
+        }
+        assert_eq!(segments_end, 9);
+        assert!(headers_available(&data, &mut segments_end));
+    }
+}
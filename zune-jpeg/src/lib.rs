@@ -106,7 +106,9 @@ extern crate core;
 
 pub use zune_core;
 
-pub use crate::decoder::{ImageInfo, JpegDecoder};
+pub use crate::decoder::{ImageInfo, JpegDecoder, OutputLayout};
+pub use crate::incremental::{DecodeStatus, IncrementalDecoder};
+pub use crate::planar::{PlanarImage, Plane, PlaneLayout};
 
 mod bitstream;
 mod color_convert;
@@ -115,6 +117,7 @@ mod decoder;
 pub mod errors;
 mod headers;
 mod huffman;
+mod incremental;
 #[cfg(not(fuzzing))]
 mod idct;
 #[cfg(fuzzing)]
@@ -123,6 +126,8 @@ mod marker;
 mod mcu;
 mod mcu_prog;
 mod misc;
+mod orientation;
+mod planar;
 mod unsafe_utils;
 mod unsafe_utils_avx2;
 mod unsafe_utils_neon;
@@ -6,27 +6,55 @@
  * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
  */
 
+use alloc::vec::Vec;
 use alloc::{format, vec};
 use core::cmp::min;
+use core::ops::Range;
 
+use zune_core::bit_depth::BitDepth;
 use zune_core::bytestream::ZReaderTrait;
 use zune_core::colorspace::ColorSpace;
 use zune_core::colorspace::ColorSpace::Luma;
 use zune_core::log::{error, trace, warn};
 
 use crate::bitstream::BitStream;
-use crate::components::SampleRatios;
-use crate::decoder::MAX_COMPONENTS;
+use crate::components::{reset_buffer, SampleRatios};
+use crate::decoder::{McuWindow, MAX_COMPONENTS};
 use crate::errors::DecodeErrors;
+use crate::idct::idct_block;
 use crate::marker::Marker;
 use crate::misc::{calculate_padded_width, setup_component_params};
-use crate::worker::{color_convert, upsample};
+use crate::planar::{write_mcu_row_planes, write_row_planes, PlaneLayout};
+use crate::worker::{color_convert, color_convert_f32, upsample};
 use crate::JpegDecoder;
 
 /// The size of a DC block for a MCU.
 
 pub const DCT_BLOCK: usize = 64;
 
+/// Where decoding of a baseline image stands
+///
+/// Decoding can stop after any MCU row and pick up from there,
+/// see [`JpegDecoder::decode_baseline_row`].
+pub(crate) struct BaselineState {
+    mcu_width:               usize,
+    window:                  McuWindow,
+    padded_width:            usize,
+    stream:                  BitStream,
+    /// Next MCU row to decode
+    row:                     usize,
+    /// Rows of pixels written so far
+    pub(crate) rows_written: usize,
+    upsampler_scratch_space: Vec<i16>
+}
+
+impl BaselineState {
+    /// Whether every MCU row the output needs has been decoded
+    pub(crate) fn is_finished(&self) -> bool {
+        self.row >= self.window.rows.end
+    }
+}
+
 impl<T: ZReaderTrait> JpegDecoder<T> {
     /// Check for existence of DC and AC Huffman Tables
     pub(crate) fn check_tables(&self) -> Result<(), DecodeErrors> {
@@ -77,15 +105,33 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
     ///
     /// Because of this, we pull in some very crazy optimization tricks hence readability is a pinch
     /// here.
+    #[inline(never)]
+    pub(crate) fn decode_mcu_ycbcr_baseline(
+        &mut self, pixels: &mut [u8]
+    ) -> Result<(), DecodeErrors> {
+        let mut state = self.start_baseline_decoding()?;
+
+        // MCU rows after the window aren't needed, so stop there
+        while !state.is_finished() {
+            self.decode_baseline_row(&mut state, pixels, true)?;
+        }
+        // it may happen that some images don't have the whole buffer
+        // so we can't panic in case of that
+        // assert_eq!(pixels_written, pixels.len());
+
+        trace!("Finished decoding image");
+
+        Ok(())
+    }
+
+    /// Set up the components for decoding a baseline image and return
+    /// the state to decode its MCU rows from
     #[allow(
         clippy::similar_names,
         clippy::too_many_lines,
         clippy::cast_possible_truncation
     )]
-    #[inline(never)]
-    pub(crate) fn decode_mcu_ycbcr_baseline(
-        &mut self, pixels: &mut [u8]
-    ) -> Result<(), DecodeErrors> {
+    pub(crate) fn start_baseline_decoding(&mut self) -> Result<BaselineState, DecodeErrors> {
         setup_component_params(self)?;
 
         // check dc and AC tables
@@ -141,12 +187,10 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
             mcu_width = ((self.info.width + 7) / 8) as usize;
             mcu_height = ((self.info.height + 7) / 8) as usize;
         }
-        let width = usize::from(self.info.width);
+        let window = self.mcu_window(mcu_width, mcu_height);
 
-        let padded_width = calculate_padded_width(width, self.sub_sample_ratio);
-
-        let mut stream = BitStream::new();
-        let mut tmp = [0_i32; DCT_BLOCK];
+        let padded_width =
+            calculate_padded_width(window.width, self.sub_sample_ratio, self.block_size());
 
         let comp_len = self.components.len();
 
@@ -164,60 +208,108 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
             {
                 // allocate enough space to hold a whole MCU width
                 // this means we should take into account sampling ratios
-                // `*8` is because each MCU spans 8 widths.
-                let len = comp.width_stride * comp.vertical_sample * 8;
+                // `*block_height` is because each MCU spans that many widths
+                // (8 unless decoding at a reduced scale).
+                let len = comp.width_stride * comp.vertical_sample * comp.block_height;
 
                 comp.needed = true;
-                comp.raw_coeff = vec![0; len];
+                reset_buffer(&mut comp.raw_coeff, len, 0);
             } else {
                 comp.needed = false;
             }
         }
 
-        let mut pixels_written = 0;
-
         let is_hv = usize::from(self.is_interleaved);
         let upsampler_scratch_size = is_hv * self.components[0].width_stride;
-        let mut upsampler_scratch_space = vec![0; upsampler_scratch_size];
-
-        for i in 0..mcu_height {
-            // Report if we have no more bytes
-            // This may generate false negatives since we over-read bytes
-            // hence that why 37 is chosen(we assume if we over-read more than 37 bytes, we have a problem)
-            if stream.overread_by > 37
-            // favourite number :)
-            {
-                if self.options.get_strict_mode() {
-                    return Err(DecodeErrors::FormatStatic("Premature end of buffer"));
-                };
 
-                error!("Premature end of buffer");
-                break;
+        Ok(BaselineState {
+            mcu_width,
+            window,
+            padded_width,
+            stream: BitStream::new(),
+            row: 0,
+            rows_written: 0,
+            upsampler_scratch_space: vec![0; upsampler_scratch_size]
+        })
+    }
+
+    /// Decode the next MCU row of a baseline image and write its pixels
+    ///
+    /// If `input_complete` is false more input may still be appended to the stream, so a
+    /// row that reads up to the end of the input is taken back, leaving `state` and the
+    /// stream where they were, and `false` is returned to wait for more input.
+    pub(crate) fn decode_baseline_row(
+        &mut self, state: &mut BaselineState, pixels: &mut [u8], input_complete: bool
+    ) -> Result<bool, DecodeErrors> {
+        // Report if we have no more bytes
+        // This may generate false negatives since we over-read bytes
+        // hence that why 37 is chosen(we assume if we over-read more than 37 bytes, we have a problem)
+        if state.stream.overread_by > 37
+        // favourite number :)
+        {
+            if self.options.get_strict_mode() {
+                return Err(DecodeErrors::FormatStatic("Premature end of buffer"));
+            };
+
+            error!("Premature end of buffer");
+            state.row = state.window.rows.end;
+            return Ok(true);
+        }
+        let i = state.row;
+        // where the row starts, to go back to if it runs out of input
+        let row_start = (!input_complete).then(|| {
+            let mut dc_preds = [0; MAX_COMPONENTS];
+
+            for (dc_pred, component) in dc_preds.iter_mut().zip(&self.components) {
+                *dc_pred = component.dc_pred;
+            }
+            (self.stream.get_position(), state.stream.clone(), self.todo, dc_preds)
+        });
+        // MCU rows before the window have to be entropy decoded to get to
+        // the ones after them, but nothing else
+        let window = &state.window;
+        let idct_cols = if window.rows.contains(&i) { window.cols.clone() } else { 0..0 };
+        let mut tmp = [0_i32; DCT_BLOCK];
+        // decode a whole MCU width,
+        // this takes into account interleaved components.
+        let decoded = self.decode_mcu_width(state.mcu_width, &mut tmp, &mut state.stream, idct_cols);
+
+        if let Some((position, stream, todo, dc_preds)) = row_start {
+            // bits past the end of the input read as zeros, which may also
+            // be what made the row fail to decode
+            if self.stream.eof() {
+                self.stream.set_position(position);
+                state.stream = stream;
+                self.todo = todo;
+
+                for (component, dc_pred) in self.components.iter_mut().zip(dc_preds) {
+                    component.dc_pred = dc_pred;
+                }
+                return Ok(false);
             }
-            // decode a whole MCU width,
-            // this takes into account interleaved components.
-            self.decode_mcu_width(mcu_width, &mut tmp, &mut stream)?;
+        }
+        decoded?;
+        state.row += 1;
+
+        if i >= state.window.rows.start {
             // process that width up until it's impossible
             self.post_process(
                 pixels,
-                i,
-                mcu_height,
-                width,
-                padded_width,
-                &mut pixels_written,
-                &mut upsampler_scratch_space
+                i - state.window.rows.start,
+                state.window.rows.len(),
+                &state.window,
+                state.padded_width,
+                &mut state.rows_written,
+                &mut state.upsampler_scratch_space
             )?;
         }
-        // it may happen that some images don't have the whole buffer
-        // so we can't panic in case of that
-        // assert_eq!(pixels_written, pixels.len());
-
-        trace!("Finished decoding image");
-
-        Ok(())
+        Ok(true)
     }
+    /// Decode a row of MCUs, only MCU columns in `idct_cols` go through the
+    /// IDCT and end up in the component buffers.
     fn decode_mcu_width(
-        &mut self, mcu_width: usize, tmp: &mut [i32; 64], stream: &mut BitStream
+        &mut self, mcu_width: usize, tmp: &mut [i32; 64], stream: &mut BitStream,
+        idct_cols: Range<usize>
     ) -> Result<(), DecodeErrors> {
         for j in 0..mcu_width {
             // iterate over components
@@ -251,18 +343,27 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                             &mut component.dc_pred
                         )?;
 
-                        if component.needed {
+                        if component.needed && idct_cols.contains(&j) {
                             let idct_position = {
                                 // derived from stb and rewritten for my tastes
-                                let c2 = v_samp * 8;
-                                let c3 = ((j * component.horizontal_sample) + h_samp) * 8;
+                                let c2 = v_samp * component.block_height;
+                                let c3 = (((j - idct_cols.start) * component.horizontal_sample)
+                                    + h_samp)
+                                    * component.block_width;
 
                                 component.width_stride * c2 + c3
                             };
 
                             let idct_pos = channel.get_mut(idct_position..).unwrap();
                             //  call idct.
-                            (self.idct_func)(tmp, idct_pos, component.width_stride);
+                            idct_block(
+                                self.idct_func,
+                                tmp,
+                                idct_pos,
+                                component.width_stride,
+                                component.block_width,
+                                component.block_height
+                            );
                         }
                     }
                 }
@@ -334,14 +435,34 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
         }
         Ok(())
     }
+    /// Up-sample and color convert MCU row `i` of the `mcu_height` rows in `window`,
+    /// writing the rows of `window.region` it holds to `pixels`.
+    ///
+    /// `rows_written` counts rows of the window processed so far.
     #[allow(clippy::too_many_lines, clippy::too_many_arguments)]
     pub(crate) fn post_process(
-        &mut self, pixels: &mut [u8], i: usize, mcu_height: usize, width: usize,
-        padded_width: usize, pixels_written: &mut usize, upsampler_scratch_space: &mut [i16]
+        &mut self, pixels: &mut [u8], i: usize, mcu_height: usize, window: &McuWindow,
+        padded_width: usize, rows_written: &mut usize, upsampler_scratch_space: &mut [i16]
     ) -> Result<(), DecodeErrors> {
         let out_colorspace_components = self.options.jpeg_get_out_colorspace().num_components();
+        // rows of pixels in a block, 8 unless decoding at a reduced scale
+        let block_size = self.block_size();
+        let region = window.region;
+        let out_depth = self.options.jpeg_get_out_depth();
+        let row_size = region.width * out_colorspace_components * out_depth.size_of();
+        let out_stride = self.out_stride.unwrap_or(row_size);
+
+        if let Some((layout, planes)) = &self.planar {
+            if *layout != PlaneLayout::FullResolution {
+                // samples are written as stored, nothing to up-sample or convert
+                write_mcu_row_planes(&self.components, planes, i, pixels);
+                *rows_written += block_size * self.v_max;
+                return Ok(());
+            }
+        }
+        let planes = self.planar.as_ref().map(|(_, planes)| planes);
 
-        let mut px = *pixels_written;
+        let mut row = *rows_written;
         // indicates whether image is vertically up-sampled
         let is_vertically_sampled = self
             .components
@@ -359,17 +480,43 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
         }
         let mut color_conv_function =
             |num_iters: usize, samples: [&[i16]; 4]| -> Result<(), DecodeErrors> {
-                for (pos, output) in pixels[px..]
-                    .chunks_exact_mut(width * out_colorspace_components)
-                    .take(num_iters)
-                    .enumerate()
-                {
+                for pos in 0..num_iters {
+                    // rows above or below the region aren't written
+                    let out_row = row.wrapping_sub(region.y);
+                    row += 1;
+
+                    if out_row >= region.height {
+                        continue;
+                    }
+                    let out_row = if window.flip { region.height - 1 - out_row } else { out_row };
+
                     let mut raw_samples: [&[i16]; 4] = [&[], &[], &[], &[]];
 
                     // iterate over each line, since color-convert needs only
-                    // one line
+                    // one line, and only the columns of the region
                     for (j, samp) in raw_samples.iter_mut().enumerate().take(comp_len) {
-                        *samp = &samples[j][pos * padded_width..(pos + 1) * padded_width]
+                        let start = pos * padded_width + region.x;
+                        *samp = &samples[j][start..start + region.width];
+                    }
+                    if let Some(planes) = planes {
+                        write_row_planes(&raw_samples, planes, out_row, pixels);
+                        continue;
+                    }
+                    // converting a row at a time keeps color conversion unaware of
+                    // the stride, it only sees the row it writes
+                    let start = out_row * out_stride;
+                    let output = &mut pixels[start..start + row_size];
+                    if out_depth == BitDepth::Float32 {
+                        color_convert_f32(
+                            &raw_samples,
+                            self.input_colorspace,
+                            self.options.jpeg_get_out_colorspace(),
+                            output,
+                            region.width,
+                            self.options.get_premultiply_alpha(),
+                            self.options.get_byte_endian()
+                        )?;
+                        continue;
                     }
                     color_convert(
                         &raw_samples,
@@ -377,10 +524,10 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                         self.input_colorspace,
                         self.options.jpeg_get_out_colorspace(),
                         output,
-                        width,
-                        padded_width
+                        region.width,
+                        region.width,
+                        self.options.get_premultiply_alpha()
                     )?;
-                    px += width * out_colorspace_components;
                 }
                 Ok(())
             };
@@ -476,7 +623,8 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
             // should sample full raw coeffs
             let is_last_considered = is_vertically_sampled && (i != mcu_height.saturating_sub(1));
 
-            let num_iters = (8 - usize::from(is_last_considered)) * self.coeff * self.v_max;
+            let num_iters =
+                (block_size - usize::from(is_last_considered)) * self.coeff * self.v_max;
 
             color_conv_function(num_iters, samples)?;
         } else {
@@ -487,10 +635,10 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                 .enumerate()
                 .for_each(|(pos, x)| channels_ref[pos] = &x.raw_coeff);
 
-            color_conv_function(8 * self.coeff, channels_ref)?;
+            color_conv_function(block_size * self.coeff, channels_ref)?;
         }
 
-        *pixels_written = px;
+        *rows_written = row;
         Ok(())
     }
 }
\ No newline at end of file
@@ -29,11 +29,12 @@ use zune_core::colorspace::ColorSpace;
 use zune_core::log::{debug, error, warn};
 
 use crate::bitstream::BitStream;
-use crate::components::{ComponentID, SampleRatios};
-use crate::decoder::{JpegDecoder, MAX_COMPONENTS};
+use crate::components::{reset_buffer, ComponentID, SampleRatios};
+use crate::decoder::{JpegDecoder, ScanListener, MAX_COMPONENTS};
 use crate::errors::DecodeErrors;
 use crate::errors::DecodeErrors::Format;
 use crate::headers::{parse_huffman, parse_sos};
+use crate::idct::idct_block;
 use crate::marker::Marker;
 use crate::mcu::DCT_BLOCK;
 use crate::misc::{calculate_padded_width, setup_component_params};
@@ -42,6 +43,9 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
     /// Decode a progressive image
     ///
     /// This routine decodes a progressive image, stopping if it finds any error.
+    ///
+    /// If there is a `listener`, the scans decoded so far are rendered into `pixels`
+    /// and handed to it every `listener.interval` scans.
     #[allow(
         clippy::needless_range_loop,
         clippy::cast_sign_loss,
@@ -50,7 +54,7 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
     )]
     #[inline(never)]
     pub(crate) fn decode_mcu_ycbcr_progressive(
-        &mut self, pixels: &mut [u8]
+        &mut self, pixels: &mut [u8], mut listener: Option<ScanListener>
     ) -> Result<(), DecodeErrors> {
         setup_component_params(self)?;
 
@@ -130,6 +134,10 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
             .take()
             .ok_or(DecodeErrors::FormatStatic("Marker missing where expected"))?;
 
+        if marker != Marker::EOI {
+            self.render_scans(&block, mcu_width, pixels, seen_scans, &mut listener)?;
+        }
+
         // if marker is EOI, we are done, otherwise continue scanning.
         //
         // In case we have a premature image, we print a warning or return
@@ -165,6 +173,15 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                                 )));
                             }
 
+                            if marker != Marker::EOI {
+                                self.render_scans(
+                                    &block,
+                                    mcu_width,
+                                    pixels,
+                                    seen_scans,
+                                    &mut listener
+                                )?;
+                            }
                             stream.reset();
                             continue 'eoi;
                         }
@@ -198,6 +215,24 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
         self.finish_progressive_decoding(&block, mcu_width, pixels)
     }
 
+    /// Render the `seen_scans` scans decoded so far into `pixels` and hand them to
+    /// `listener`, if it wants a render after that many scans
+    fn render_scans(
+        &mut self, block: &[Vec<i16>; MAX_COMPONENTS], mcu_width: usize, pixels: &mut [u8],
+        seen_scans: usize, listener: &mut Option<ScanListener>
+    ) -> Result<(), DecodeErrors> {
+        let Some(listener) = listener else {
+            return Ok(());
+        };
+        if seen_scans % listener.interval != 0 {
+            return Ok(());
+        }
+        // this doesn't touch the coefficients, later scans keep refining them
+        self.finish_progressive_decoding(block, mcu_width, pixels)?;
+
+        (listener.callback)(pixels, seen_scans)
+    }
+
     #[allow(clippy::too_many_lines, clippy::cast_sign_loss)]
     fn parse_entropy_coded_data(
         &mut self, stream: &mut BitStream, buffer: &mut [Vec<i16>; MAX_COMPONENTS]
@@ -255,7 +290,7 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                         stream.eob_run -= 1;
                         continue;
                     }
-                    let start = 64 * (j + i * (self.components[k].width_stride / 8));
+                    let start = 64 * (j + i * self.components[k].blocks_per_row);
 
                     let data: &mut [i16; 64] = buffer
                         .get_mut(k)
@@ -386,7 +421,7 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                             for h_samp in 0..component.horizontal_sample {
                                 let x2 = j * component.horizontal_sample + h_samp;
                                 let y2 = i * component.vertical_sample + v_samp;
-                                let position = 64 * (x2 + y2 * component.width_stride / 8);
+                                let position = 64 * (x2 + y2 * component.blocks_per_row);
 
                                 let data = &mut buffer[n][position];
 
@@ -440,19 +475,23 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
         //
         //
 
-        let mcu_height = if self.is_interleaved {
-            self.mcu_y
+        let (mcu_width, mcu_height) = if self.is_interleaved {
+            (self.mcu_x, self.mcu_y)
         } else {
             // For non-interleaved images( (1*1) subsampling)
             // number of MCU's are the widths (+7 to account for paddings) divided by 8.
-            ((self.info.height + 7) / 8) as usize
+            (
+                ((self.info.width + 7) / 8) as usize,
+                ((self.info.height + 7) / 8) as usize
+            )
         };
 
         // Size of our output image(width*height)
         let is_hv = usize::from(self.is_interleaved);
         let upsampler_scratch_size = is_hv * self.components[0].width_stride;
-        let width = usize::from(self.info.width);
-        let padded_width = calculate_padded_width(width, self.sub_sample_ratio);
+        let window = self.mcu_window(mcu_width, mcu_height);
+        let padded_width =
+            calculate_padded_width(window.width, self.sub_sample_ratio, self.block_size());
 
         //let mut pixels = vec![0; capacity * out_colorspace_components];
         let mut upsampler_scratch_space = vec![0; upsampler_scratch_size];
@@ -472,20 +511,22 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
             {
                 // allocate enough space to hold a whole MCU width
                 // this means we should take into account sampling ratios
-                // `*8` is because each MCU spans 8 widths.
-                let len = comp.width_stride * comp.vertical_sample * 8;
+                // `*block_height` is because each MCU spans that many widths
+                // (8 unless decoding at a reduced scale).
+                let len = comp.width_stride * comp.vertical_sample * comp.block_height;
 
                 comp.needed = true;
-                comp.raw_coeff = vec![0; len];
+                reset_buffer(&mut comp.raw_coeff, len, 0);
             } else {
                 comp.needed = false;
             }
         }
 
-        let mut pixels_written = 0;
+        let mut rows_written = 0;
 
-        // dequantize, idct and color convert.
-        for i in 0..mcu_height {
+        // dequantize, idct and color convert, all coefficients are already
+        // decoded so only MCUs in the window need to be visited.
+        for i in window.rows.clone() {
             'component: for (position, component) in &mut self.components.iter_mut().enumerate() {
                 if !component.needed {
                     continue 'component;
@@ -511,15 +552,16 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                 // To figure out how many pixels we iterate by we use effective pixels
                 // Given to us by component.x
                 // iterate per effective pixels.
-                let mcu_x = component.width_stride / 8;
+                let mcu_x = component.blocks_per_row;
+                let block_cols = window.cols.start * component.horizontal_sample
+                    ..window.cols.end * component.horizontal_sample;
 
                 // iterate per every vertical sample.
                 for k in 0..component.vertical_sample {
-                    for j in 0..mcu_x {
-                        // after writing a single stride, we need to skip 8 rows.
+                    for j in block_cols.clone() {
+                        // after writing a single stride, we need to skip a row of blocks.
                         // This does the row calculation
-                        let width_stride = k * 8 * component.width_stride;
-                        let start = j * 64 + width_stride;
+                        let start = (k * mcu_x + j) * 64;
 
                         // dequantize
                         for ((x, out), qt_val) in slice[start..start + 64]
@@ -532,17 +574,24 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
                         // determine where to write.
                         let sl = &mut temp_channel[component.idct_pos..];
 
-                        component.idct_pos += 8;
+                        component.idct_pos += component.block_width;
                         // tmp now contains a dequantized block so idct it
-                        (self.idct_func)(&mut tmp, sl, component.width_stride);
+                        idct_block(
+                            self.idct_func,
+                            &mut tmp,
+                            sl,
+                            component.width_stride,
+                            component.block_width,
+                            component.block_height
+                        );
                     }
                     // after every write of 8, skip 7 since idct write stride wise 8 times.
                     //
                     // Remember each MCU is 8x8 block, so each idct will write 8 strides into
-                    // sl
+                    // sl (fewer when decoding at a reduced scale)
                     //
                     // and component.idct_pos is one stride long
-                    component.idct_pos += 7 * component.width_stride;
+                    component.idct_pos += (component.block_height - 1) * component.width_stride;
                 }
                 component.idct_pos = 0;
             }
@@ -550,11 +599,11 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
             // process that width up until it's impossible
             self.post_process(
                 pixels,
-                i,
-                mcu_height,
-                width,
+                i - window.rows.start,
+                window.rows.len(),
+                &window,
                 padded_width,
-                &mut pixels_written,
+                &mut rows_written,
                 &mut upsampler_scratch_space
             )?;
         }
@@ -578,7 +627,14 @@ impl<T: ZReaderTrait> JpegDecoder<T> {
         self.sub_sample_ratio = SampleRatios::None;
         self.is_interleaved = false;
         self.components[0].vertical_sample = 1;
-        self.components[0].width_stride = (((self.info.width as usize) + 7) / 8) * 8;
+        self.components[0].block_width = self.block_size();
+        self.components[0].block_height = self.block_size();
+        let mcu_width = ((self.info.width as usize) + 7) / 8;
+        let mcu_height = ((self.info.height as usize) + 7) / 8;
+        let mcu_cols = self.mcu_window(mcu_width, mcu_height).cols.len();
+
+        self.components[0].blocks_per_row = mcu_width;
+        self.components[0].width_stride = mcu_cols * self.components[0].block_width;
         self.components[0].horizontal_sample = 1;
     }
 }
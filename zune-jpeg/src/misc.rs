@@ -10,7 +10,7 @@
 #![allow(dead_code)]
 
 use alloc::format;
-use core::cmp::max;
+use core::cmp::{max, min};
 use core::fmt;
 
 use zune_core::bytestream::{ZByteReader, ZReaderTrait};
@@ -260,8 +260,22 @@ pub(crate) fn setup_component_params<T: ZReaderTrait>(
         // probably not needed. :)
         component.y = y;
         component.quantization_table = qt_table;
-        // initially stride contains its horizontal sub-sampling
-        component.width_stride *= img.mcu_x * 8;
+    }
+    // When decoding at a reduced scale, blocks of components with a lower sampling
+    // factor than the image cover more of the output, so decode them at a larger size,
+    // up to the full 8x8. This way the IDCT does their up-sampling.
+    let block_size = img.block_size();
+    // When decoding a region, buffers only hold the MCU columns covering it
+    let mcu_cols = img.mcu_window(img.mcu_x, img.mcu_y).cols.len();
+
+    for component in &mut img.components {
+        let h_scale = img.h_max / component.horizontal_sample;
+        let v_scale = img.v_max / component.vertical_sample;
+
+        component.block_width = min(8, block_size * h_scale);
+        component.block_height = min(8, block_size * v_scale);
+        component.blocks_per_row = component.horizontal_sample * img.mcu_x;
+        component.width_stride = component.horizontal_sample * mcu_cols * component.block_width;
     }
     {
         // Sampling factors are one thing that suck
@@ -321,20 +335,24 @@ pub(crate) fn setup_component_params<T: ZReaderTrait>(
 /// and the actual width, this calculates the padded bytes that were added to the image
 ///
 ///  # Params
-/// -actual_width: Actual width of the image
+/// -actual_width: Actual width of the image, scaled down if decoding at a reduced scale
 /// -sub_sample: Sub sampling factor of the image
+/// -block_size: Width of a decoded block, 8 unless decoding at a reduced scale
 ///
 /// # Returns
 /// The padded width, this is how long the width is for a particular image
-pub fn calculate_padded_width(actual_width: usize, sub_sample: SampleRatios) -> usize {
+pub fn calculate_padded_width(
+    actual_width: usize, sub_sample: SampleRatios, block_size: usize
+) -> usize {
     match sub_sample {
         SampleRatios::None | SampleRatios::V => {
             // None+V sends one MCU row, so that's a simple calculation
-            ((actual_width + 7) / 8) * 8
+            ((actual_width + block_size - 1) / block_size) * block_size
         }
         SampleRatios::H | SampleRatios::HV => {
-            // sends two rows, width can be expanded by up to 15 more bytes
-            ((actual_width + 15) / 16) * 16
+            // sends two rows, width can be expanded by up to 2*block_size - 1 more bytes
+            let mcu_size = block_size * 2;
+            ((actual_width + mcu_size - 1) / mcu_size) * mcu_size
         }
     }
 }
@@ -0,0 +1,226 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! EXIF orientation
+//!
+//! Cameras store pixels the way the sensor read them and record how the
+//! image has to be turned to be upright in the Orientation tag of the EXIF data,
+//! values go from 1 (already upright) to 8, see
+//! <https://web.archive.org/web/20200412005226/https://www.impulseadventure.com/photo/exif-orientation.html>
+//! for what each one means.
+
+use crate::decoder::Region;
+
+/// Tag number of the orientation in IFD0
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// Read the orientation tag from EXIF data, as stored by `parse_app1`
+///
+/// Returns `None` if the data is malformed, doesn't have the tag, or
+/// the tag isn't between 1 and 8.
+pub(crate) fn parse_exif_orientation(exif: &[u8]) -> Option<u16> {
+    // EXIF data is a TIFF header followed by image file directories,
+    // the orientation is in the first one
+    let big_endian = match exif.get(0..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None
+    };
+    let read_u16 = |offset: usize| -> Option<u16> {
+        let bytes = exif.get(offset..offset + 2)?.try_into().ok()?;
+
+        Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    };
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let bytes = exif.get(offset..offset + 4)?.try_into().ok()?;
+
+        Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    };
+    if read_u16(2)? != 42 {
+        return None;
+    }
+    let ifd_offset = usize::try_from(read_u32(4)?).ok()?;
+    let num_entries = usize::from(read_u16(ifd_offset)?);
+
+    for entry in 0..num_entries {
+        // entries are 12 bytes, tag, type, count and value
+        let entry_offset = ifd_offset.checked_add(2 + entry * 12)?;
+
+        if read_u16(entry_offset)? == ORIENTATION_TAG {
+            // a SHORT is stored in the first two bytes of the value
+            let orientation = read_u16(entry_offset + 8)?;
+
+            return (1..=8).contains(&orientation).then_some(orientation);
+        }
+    }
+    None
+}
+
+/// Whether an image stored with `orientation` is upright once width and
+/// height are swapped, i.e. it is rotated by 90 or 270 degrees
+pub(crate) const fn swaps_dimensions(orientation: u16) -> bool {
+    matches!(orientation, 5..=8)
+}
+
+/// Orientation that turns an image stored with `orientation` upright
+/// and then flips it upside down
+pub(crate) const fn flipped_vertically(orientation: u16) -> u16 {
+    match orientation {
+        2 => 3,
+        3 => 2,
+        4 => 1,
+        5 => 8,
+        6 => 7,
+        7 => 6,
+        8 => 5,
+        _ => 4
+    }
+}
+
+/// Position in a stored `width`x`height` image of the pixel at `(x, y)`
+/// of the upright image
+const fn stored_position(
+    orientation: u16, x: usize, y: usize, width: usize, height: usize
+) -> (usize, usize) {
+    match orientation {
+        2 => (width - 1 - x, y),
+        3 => (width - 1 - x, height - 1 - y),
+        4 => (x, height - 1 - y),
+        5 => (y, x),
+        6 => (y, height - 1 - x),
+        7 => (width - 1 - y, height - 1 - x),
+        8 => (width - 1 - y, x),
+        _ => (x, y)
+    }
+}
+
+/// Write `pixels`, a stored `width`x`height` image with `components` bytes per pixel,
+/// upright into `out`, whose rows start `out_stride` bytes apart
+pub(crate) fn apply_orientation(
+    pixels: &[u8], out: &mut [u8], out_stride: usize, width: usize, height: usize,
+    components: usize, orientation: u16
+) {
+    let (out_width, out_height) = if swaps_dimensions(orientation) {
+        (height, width)
+    } else {
+        (width, height)
+    };
+
+    for (y, out_row) in out.chunks_mut(out_stride).take(out_height).enumerate() {
+        let out_row = &mut out_row[..out_width * components];
+
+        for (x, out_pixel) in out_row.chunks_exact_mut(components).enumerate() {
+            let (stored_x, stored_y) = stored_position(orientation, x, y, width, height);
+            let start = (stored_y * width + stored_x) * components;
+
+            out_pixel.copy_from_slice(&pixels[start..start + components]);
+        }
+    }
+}
+
+/// Return the rectangle of a stored `width`x`height` image holding `region`
+/// of the upright image
+pub(crate) fn stored_region(
+    region: Region, width: usize, height: usize, orientation: u16
+) -> Region {
+    let (x0, y0) = stored_position(orientation, region.x, region.y, width, height);
+    let (x1, y1) = stored_position(
+        orientation,
+        region.x + region.width - 1,
+        region.y + region.height - 1,
+        width,
+        height
+    );
+
+    Region {
+        x:      x0.min(x1),
+        y:      y0.min(y1),
+        width:  x0.abs_diff(x1) + 1,
+        height: y0.abs_diff(y1) + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// EXIF data holding only an orientation tag
+    fn exif_with_orientation(big_endian: bool, orientation: u16) -> Vec<u8> {
+        let mut exif = Vec::new();
+        let u16_bytes = |v: u16| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let u32_bytes = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+
+        exif.extend_from_slice(if big_endian { b"MM" } else { b"II" });
+        exif.extend_from_slice(&u16_bytes(42));
+        exif.extend_from_slice(&u32_bytes(8));
+        exif.extend_from_slice(&u16_bytes(1));
+        exif.extend_from_slice(&u16_bytes(ORIENTATION_TAG));
+        exif.extend_from_slice(&u16_bytes(3));
+        exif.extend_from_slice(&u32_bytes(1));
+        exif.extend_from_slice(&u16_bytes(orientation));
+        exif.extend_from_slice(&[0, 0]);
+        exif
+    }
+
+    #[test]
+    fn parse_orientation() {
+        for big_endian in [true, false] {
+            for orientation in 1..=8 {
+                let exif = exif_with_orientation(big_endian, orientation);
+                assert_eq!(parse_exif_orientation(&exif), Some(orientation));
+            }
+            let exif = exif_with_orientation(big_endian, 9);
+            assert_eq!(parse_exif_orientation(&exif), None);
+        }
+        assert_eq!(parse_exif_orientation(b"MM\0*\0\0\0\x08\0"), None);
+        assert_eq!(parse_exif_orientation(&[]), None);
+    }
+
+    #[test]
+    fn orient_pixels() {
+        // 3x2 image
+        // 1 2 3
+        // 4 5 6
+        let pixels = [1, 2, 3, 4, 5, 6];
+        let expected: [&[u8]; 8] = [
+            &[1, 2, 3, 4, 5, 6],
+            &[3, 2, 1, 6, 5, 4],
+            &[6, 5, 4, 3, 2, 1],
+            &[4, 5, 6, 1, 2, 3],
+            &[1, 4, 2, 5, 3, 6],
+            &[4, 1, 5, 2, 6, 3],
+            &[6, 3, 5, 2, 4, 1],
+            &[3, 6, 2, 5, 1, 4]
+        ];
+        for (orientation, expected) in (1..=8).zip(expected) {
+            let upright_width = if swaps_dimensions(orientation) { 2 } else { 3 };
+            let mut out = [0; 6];
+            apply_orientation(&pixels, &mut out, upright_width, 3, 2, 1, orientation);
+            assert_eq!(&out, expected, "orientation {orientation}");
+
+            // flipping gives the rows of the upright image in reverse order
+            let mut flipped = [0; 6];
+            let flipped_orientation = flipped_vertically(orientation);
+            apply_orientation(&pixels, &mut flipped, upright_width, 3, 2, 1, flipped_orientation);
+            let reversed: Vec<_> = out.rchunks_exact(upright_width).flatten().collect();
+            assert!(flipped.iter().eq(reversed), "flipped orientation {orientation}");
+
+            // the stored rectangle of the upright top left pixel holds it
+            let upright = Region {
+                x:      0,
+                y:      0,
+                width:  1,
+                height: 1
+            };
+            let stored = stored_region(upright, 3, 2, orientation);
+            assert_eq!(pixels[stored.y * 3 + stored.x], expected[0]);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+/*
+ * Copyright (c) 2023.
+ *
+ * This software is free software;
+ *
+ * You can redistribute it or modify it under terms of the MIT, Apache License or Zlib license
+ */
+
+//! Output of the Y, Cb and Cr planes of an image without color conversion
+//!
+//! Video encoders and other consumers of YUV frames want the samples the way a jpeg
+//! stores them, so [`JpegDecoder::decode_planar`](crate::JpegDecoder::decode_planar)
+//! writes every component into a plane of its own instead of converting pixels to RGB.
+//!
+//! Chroma planes are either kept at the resolution they are stored in, e.g. half the width
+//! and height of the image for 4:2:0 (I420, or NV12 with the Cb and Cr samples interleaved),
+//! or up-sampled to the size of the image (YUV444).
+#![allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+
+use alloc::vec::Vec;
+use core::cmp::min;
+
+use crate::components::Components;
+use crate::decoder::MAX_COMPONENTS;
+
+/// How the planes written by [`JpegDecoder::decode_planar`](crate::JpegDecoder::decode_planar)
+/// are laid out
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PlaneLayout {
+    /// Y, Cb and Cr planes one after the other, each at the resolution it is stored in
+    ///
+    /// This is I420 for 4:2:0 images, I422 for 4:2:2 ones and I444 for
+    /// images that aren't sub-sampled.
+    Planar,
+    /// A Y plane followed by a plane of interleaved Cb and Cr samples, both at the
+    /// resolution they are stored in
+    ///
+    /// This is NV12 for 4:2:0 images.
+    SemiPlanar,
+    /// Y, Cb and Cr planes one after the other, all the size of the image
+    ///
+    /// Sub-sampled chroma is up-sampled the same way it is for RGB output.
+    FullResolution
+}
+
+/// A plane of samples written by [`JpegDecoder::decode_planar`](crate::JpegDecoder::decode_planar)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Plane {
+    /// Position of the first sample of the plane in the output
+    pub offset: usize,
+    /// Width of the plane in samples, in pairs of Cb and Cr samples for the
+    /// second plane of [`PlaneLayout::SemiPlanar`]
+    pub width:  usize,
+    /// Height of the plane in rows
+    pub height: usize,
+    /// Number of bytes from the start of a row of the plane to the start of the next
+    pub stride: usize
+}
+
+impl Plane {
+    /// Position just past the last row of the plane
+    #[must_use]
+    pub const fn end(&self) -> usize {
+        self.offset + self.stride * self.height
+    }
+}
+
+/// Planes of an image decoded by [`JpegDecoder::decode_planar`](crate::JpegDecoder::decode_planar)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlanarImage {
+    /// Samples of every plane
+    pub data:   Vec<u8>,
+    /// The Y, Cb and Cr planes in `data`, or the Y and the interleaved CbCr
+    /// plane for [`PlaneLayout::SemiPlanar`]
+    pub planes: Vec<Plane>
+}
+
+/// Return the planes of an image with `components`, stored `width`x`height` pixels, decoded
+/// with `block_size`x`block_size` pixel blocks, laid out one after the other in `layout`
+///
+/// The planes of [`PlaneLayout::FullResolution`] hold the decoded image, the others
+/// hold as many samples of a component as its blocks are decoded to, which is what
+/// `setup_component_params` sets up once decoding starts.
+pub(crate) fn plane_layout(
+    layout: PlaneLayout, components: &[Components], width: usize, height: usize,
+    block_size: usize
+) -> Vec<Plane> {
+    let h_max = components.iter().map(|c| c.horizontal_sample).max().unwrap_or(1);
+    let v_max = components.iter().map(|c| c.vertical_sample).max().unwrap_or(1);
+    let mut planes = Vec::with_capacity(components.len());
+    let mut offset = 0;
+
+    for (pos, component) in components.iter().enumerate() {
+        // Cr samples are written next to the Cb ones
+        if layout == PlaneLayout::SemiPlanar && pos == 2 {
+            break;
+        }
+        let (plane_width, plane_height) = if layout == PlaneLayout::FullResolution {
+            ((width * block_size + 7) / 8, (height * block_size + 7) / 8)
+        } else {
+            let h = component.horizontal_sample;
+            let v = component.vertical_sample;
+            // pixels of a decoded block, see `setup_component_params`
+            let x_scale = h * min(8, block_size * h_max / h);
+            let y_scale = v * min(8, block_size * v_max / v);
+
+            (
+                (width * x_scale + h_max * 8 - 1) / (h_max * 8),
+                (height * y_scale + v_max * 8 - 1) / (v_max * 8)
+            )
+        };
+        let stride = if layout == PlaneLayout::SemiPlanar && pos == 1 {
+            plane_width * 2
+        } else {
+            plane_width
+        };
+        let plane = Plane {
+            offset,
+            width: plane_width,
+            height: plane_height,
+            stride
+        };
+        offset = plane.end();
+        planes.push(plane);
+    }
+    planes
+}
+
+/// Return the plane component `pos` is written to, along with the distance between its
+/// samples and the position of its first sample in a row
+fn component_plane(planes: &[Plane], pos: usize) -> (&Plane, usize, usize) {
+    if planes.len() == 2 && pos > 0 {
+        // Cb and Cr samples take turns in the second plane of a semi-planar layout
+        (&planes[1], 2, pos - 1)
+    } else {
+        (&planes[pos], 1, 0)
+    }
+}
+
+/// Write the samples of `components` decoded for MCU row `mcu_row` to their `planes`
+/// in `out`, without up-sampling them
+pub(crate) fn write_mcu_row_planes(
+    components: &[Components], planes: &[Plane], mcu_row: usize, out: &mut [u8]
+) {
+    for (pos, component) in components.iter().enumerate() {
+        let (plane, step, first) = component_plane(planes, pos);
+        // rows of the component in an MCU row
+        let mcu_rows = component.vertical_sample * component.block_height;
+
+        for (y, samples) in component
+            .raw_coeff
+            .chunks_exact(component.width_stride)
+            .enumerate()
+        {
+            let row = mcu_row * mcu_rows + y;
+
+            if row >= plane.height {
+                break;
+            }
+            let start = plane.offset + row * plane.stride + first;
+            let out_row = &mut out[start..=start + (plane.width - 1) * step];
+
+            for (pixel, sample) in out_row.iter_mut().step_by(step).zip(samples) {
+                *pixel = *sample as u8;
+            }
+        }
+    }
+}
+
+/// Write row `row` of up-sampled `samples`, one row of each component,
+/// to full resolution `planes` in `out`
+pub(crate) fn write_row_planes(
+    samples: &[&[i16]; MAX_COMPONENTS], planes: &[Plane], row: usize, out: &mut [u8]
+) {
+    for (plane, samples) in planes.iter().zip(samples) {
+        let start = plane.offset + row * plane.stride;
+
+        for (pixel, sample) in out[start..start + plane.width].iter_mut().zip(*samples) {
+            *pixel = *sample as u8;
+        }
+    }
+}
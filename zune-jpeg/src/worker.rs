@@ -9,6 +9,7 @@
 use alloc::format;
 use core::convert::TryInto;
 
+use zune_core::bit_depth::ByteEndian;
 use zune_core::colorspace::ColorSpace;
 
 use crate::color_convert::ycbcr_to_grayscale;
@@ -26,11 +27,19 @@ fn blinn_8x8(in_val: u8, y: u8) -> u8 {
     return ((t + (t >> 8)) >> 8) as u8;
 }
 
-#[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+/// Convert rows of `width` samples to `output_colorspace`, writing them packed to `output`
+///
+/// Rows of `output` are `width` pixels apart, callers writing with a larger stride
+/// convert a row at a time into the slice of that row, see `post_process`.
+#[allow(
+    clippy::cast_sign_loss,
+    clippy::cast_possible_truncation,
+    clippy::too_many_arguments
+)]
 pub(crate) fn color_convert(
     unprocessed: &[&[i16]; MAX_COMPONENTS], color_convert_16: ColorConvert16Ptr,
     input_colorspace: ColorSpace, output_colorspace: ColorSpace, output: &mut [u8], width: usize,
-    padded_width: usize
+    padded_width: usize, premultiply_alpha: bool
 ) -> Result<(), DecodeErrors> // so many parameters..
 {
     // maximum sampling factors are in Y-channel, no need to pass them.
@@ -97,11 +106,136 @@ pub(crate) fn color_convert(
             return Err(DecodeErrors::Format(msg));
         }
     }
+    if premultiply_alpha {
+        premultiply(output_colorspace, &mut output[..width * output_colorspace.num_components()]);
+    }
+    Ok(())
+}
+
+/// Color convert a row of samples to `f32` pixels from 0.0 to 1.0, written to
+/// `output` as bytes in `endian` order
+///
+/// This is the float counterpart of [`color_convert`], samples are converted with
+/// the exact JFIF coefficients instead of the fixed point ones and aren't
+/// rounded to integers.
+pub(crate) fn color_convert_f32(
+    unprocessed: &[&[i16]; MAX_COMPONENTS], input_colorspace: ColorSpace,
+    output_colorspace: ColorSpace, output: &mut [u8], width: usize, premultiply_alpha: bool,
+    endian: ByteEndian
+) -> Result<(), DecodeErrors> {
+    let num_components = output_colorspace.num_components();
+    // only pixels with alpha are premultiplied
+    let premultiply_at = output_colorspace
+        .alpha_position()
+        .filter(|_| premultiply_alpha);
+
+    let is_copy = input_colorspace == output_colorspace
+        && matches!(input_colorspace.num_components(), 3 | 4);
+    let is_supported = is_copy
+        || matches!(
+            (input_colorspace, output_colorspace),
+            (ColorSpace::YCbCr | ColorSpace::Luma, ColorSpace::Luma)
+                | (
+                    ColorSpace::YCbCr,
+                    ColorSpace::RGB | ColorSpace::RGBA | ColorSpace::BGR | ColorSpace::BGRA
+                )
+                | (ColorSpace::YCCK | ColorSpace::CMYK, ColorSpace::RGB | ColorSpace::RGBA)
+        );
+    if !is_supported {
+        let msg = format!(
+            "Unimplemented colorspace mapping from {input_colorspace:?} to {output_colorspace:?}"
+        );
+        return Err(DecodeErrors::Format(msg));
+    }
+
+    for (x, out) in output
+        .chunks_exact_mut(num_components * 4)
+        .take(width)
+        .enumerate()
+    {
+        let sample = |component: usize| f32::from(unprocessed[component][x]) / 255.0;
+        let mut pixel = [1.0; 4];
+
+        if is_copy {
+            for (position, value) in pixel.iter_mut().enumerate().take(num_components) {
+                *value = sample(position);
+            }
+        } else if output_colorspace == ColorSpace::Luma {
+            pixel[0] = sample(0);
+        } else {
+            let [mut r, mut g, mut b] = match input_colorspace {
+                ColorSpace::CMYK => [sample(0), sample(1), sample(2)],
+                _ => ycbcr_to_rgb_f32(sample(0), sample(1), sample(2))
+            };
+            if input_colorspace == ColorSpace::YCCK {
+                // the RGB is inverted CMY, see color_convert_ycck_to_rgb
+                [r, g, b] = [1.0 - r, 1.0 - g, 1.0 - b];
+            }
+            if matches!(input_colorspace, ColorSpace::YCCK | ColorSpace::CMYK) {
+                let k = sample(3);
+                [r, g, b] = [r * k, g * k, b * k];
+            }
+            pixel[..3].copy_from_slice(&match output_colorspace {
+                ColorSpace::BGR | ColorSpace::BGRA => [b, g, r],
+                _ => [r, g, b]
+            });
+        }
+        if let Some(alpha_position) = premultiply_at {
+            let alpha = pixel[alpha_position];
+
+            for (position, value) in pixel.iter_mut().enumerate().take(num_components) {
+                if position != alpha_position {
+                    *value *= alpha;
+                }
+            }
+        }
+        for (value, bytes) in pixel.iter().zip(out.chunks_exact_mut(4)) {
+            bytes.copy_from_slice(&match endian {
+                ByteEndian::LE => value.to_le_bytes(),
+                ByteEndian::BE => value.to_be_bytes()
+            });
+        }
+    }
     Ok(())
 }
 
+/// Convert a YCbCr pixel with samples from 0.0 to 1.0 to RGB
+fn ycbcr_to_rgb_f32(y: f32, cb: f32, cr: f32) -> [f32; 3] {
+    let cb = cb - 128.0 / 255.0;
+    let cr = cr - 128.0 / 255.0;
+
+    let r = y + 1.402 * cr;
+    let g = y - 0.344_136 * cb - 0.714_136 * cr;
+    let b = y + 1.772 * cb;
+
+    [r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0)]
+}
+
+/// Multiply the color channels of every pixel in `output` by its alpha
+///
+/// Alpha of a jpeg is always 255, which leaves the pixel as it is, so only
+/// pixels with other values are touched.
+fn premultiply(colorspace: ColorSpace, output: &mut [u8]) {
+    let Some(alpha_position) = colorspace.alpha_position() else {
+        return;
+    };
+    for pixel in output.chunks_exact_mut(colorspace.num_components()) {
+        let alpha = pixel[alpha_position];
+
+        if alpha != 255 {
+            for (position, value) in pixel.iter_mut().enumerate() {
+                if position != alpha_position {
+                    *value = blinn_8x8(*value, alpha);
+                }
+            }
+        }
+    }
+}
+
 /// Copy a block to output removing padding bytes from input
 /// if necessary
+///
+/// Output rows are packed, like [`color_convert`]
 #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
 fn copy_removing_padding(
     mcu_block: &[&[i16]; MAX_COMPONENTS], width: usize, padded_width: usize, output: &mut [u8]